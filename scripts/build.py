@@ -1,3 +1,4 @@
+import argparse
 import os
 import platform
 import shutil
@@ -51,7 +52,10 @@ def run_in_qemu():
 
 
 def run_hosted():
-    run_and_check(["cargo", "run", "--release"])
+    run_and_check(
+        ["cargo", "run", "--release", "--features", "run_hosted"],
+        cwd=_REPO_ROOT,
+    )
 
 
 def compile_and_run():
@@ -71,7 +75,6 @@ def compile_and_run():
             "build",
             "--release",
             "--features", "run_in_uefi",
-            #"--features", "run_hosted",
             "--target",
             _ARCH_TARGET_NAME,
         ],
@@ -88,8 +91,13 @@ def compile_and_run():
         raise RuntimeError(f'Expected staged app to exist: {staged_uefi_app_path.as_posix()}')
 
     run_in_qemu()
-    # run_hosted()
 
 
 if __name__ == '__main__':
-    compile_and_run()
+    parser = argparse.ArgumentParser()
+    parser.add_argument("--hosted", action="store_true", help="Run in a host window rather than in QEMU")
+    args = parser.parse_args()
+    if args.hosted:
+        run_hosted()
+    else:
+        compile_and_run()
@@ -1,86 +1,1781 @@
+use alloc::collections::VecDeque;
 use alloc::format;
 use alloc::rc::Rc;
-use log::info;
-use uefi::prelude::BootServices;
-use uefi::table::boot::ScopedProtocol;
-use crate::connection::{get_tcp_protocol, TcpConnection};
-use crate::connection::get_tcp_service_binding_protocol;
-use crate::ipv4::IPv4Address;
-use crate::tcpv4::TCPv4ServiceBindingProtocol;
-
-#[derive(Debug)]
-pub struct IrcClient<'a> {
-    boot_services: &'static BootServices,
-    tcp_service_binding_protocol: ScopedProtocol<'a, TCPv4ServiceBindingProtocol>,
-
-    pub active_connection: Option<Rc<TcpConnection<'a>>>,
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::cmp::{max, min};
+use agx_definitions::{Color, Drawable, NestedLayerSlice, Point, Rect, Size, StrokeThickness};
+use libgui::{AwmWindow, KeyCode};
+use libgui::button::Button;
+use libgui::text_view::TextView;
+use libgui::ui_elements::UIElement;
+use ttf_renderer::{Font, rendered_string_size};
+use crate::buffers::{BufferId, BufferKind, BufferList};
+use crate::chat_log::ChatLogger;
+use crate::clock::{Clock, DateTime, Deadline, WallClock};
+use crate::gui::{ContentView, InputBoxView, NetworkStatusView, SearchBoxView, TitleView};
+use crate::irc::{ConnectionEvent, IrcClient, IrcCommand, IrcMessage, JoinTarget, ResponseParser, UserCommand};
+use crate::rendered_line::{LineKind, LineStyle, RenderedLine};
+use crate::resolution::Resolution;
+use crate::scrollback::{SavedBuffer, ScrollbackStore};
+use crate::search::{SearchHits, SearchQuery};
+use crate::storage::FileStorage;
+use crate::theme::{load_theme, Theme, BUILT_IN_THEME_NAMES, THEME_DIRECTORY};
+use crate::transport::ConnectionState;
+
+/// Paths of the fonts used by the UI, relative to the root of the EFI filesystem
+pub const FONT_REGULAR_PATH: &str = "EFI\\Boot\\BigCaslon.ttf";
+pub const FONT_ITALIC_PATH: &str = "EFI\\Boot\\new_york_italic.ttf";
+
+/// Keys without a character are identified by their UEFI scan code, moved past the end of Unicode.
+/// Scan codes overlap with character codes (Page Up's is the same as tab's), so this keeps them apart.
+const SPECIAL_KEY_BASE: u32 = 0x11_0000;
+
+/// The key code for a key from UEFI's scan code table
+pub const fn special_key(scan_code: u16) -> KeyCode {
+    KeyCode((SPECIAL_KEY_BASE + scan_code as u32) as _)
 }
 
-impl<'a> IrcClient<'a> {
-    pub fn new(
-        boot_services: &'static BootServices,
-    ) -> Self {
-        let tcp_service_binding_protocol = get_tcp_service_binding_protocol(boot_services);
+pub fn character_key(ch: char) -> KeyCode {
+    KeyCode(ch as _)
+}
+
+fn is_character_key(key: KeyCode) -> bool {
+    (key.0 as u32) < SPECIAL_KEY_BASE
+}
+
+/// Toggles the network status panel
+const NETWORK_STATUS_HOTKEY: KeyCode = special_key(0x0c);
+/// Switch to the previous and next buffer. These are F5 and F6.
+const PREVIOUS_BUFFER_HOTKEY: KeyCode = special_key(0x0f);
+const NEXT_BUFFER_HOTKEY: KeyCode = special_key(0x10);
+/// Starts searching the scrollback. This is F4.
+const SEARCH_HOTKEY: KeyCode = special_key(0x0e);
+const ESCAPE_KEY: KeyCode = special_key(0x17);
+/// UEFI reports backspace as a character rather than a scan code
+const BACKSPACE_KEY: KeyCode = KeyCode(0x08);
+/// Scrolling keys
+const ARROW_UP_KEY: KeyCode = special_key(0x01);
+const ARROW_DOWN_KEY: KeyCode = special_key(0x02);
+const HOME_KEY: KeyCode = special_key(0x05);
+const END_KEY: KeyCode = special_key(0x06);
+const PAGE_UP_KEY: KeyCode = special_key(0x09);
+const PAGE_DOWN_KEY: KeyCode = special_key(0x0a);
+/// How far the content view moves for each arrow press or notch of the scroll wheel
+const SCROLL_STEP_PX: isize = 100;
+/// Space left below the last line when scrolled to the bottom
+const CONTENT_BOTTOM_MARGIN_PX: isize = 30;
+const SCROLLBAR_WIDTH_PX: isize = 8;
+/// How long each iteration of the event loop can spend parsing and drawing received lines, before
+/// handing back control so that input is handled. Anything left over is picked up on the next iteration.
+/// Two 10 ms ticks of `TimerClock`
+const RENDER_BUDGET_MS: u64 = 20;
+/// Beyond this, the oldest lines of a buffer are forgotten
+const MAX_LINES_PER_BUFFER: usize = 2000;
+/// The content view still holds lines that have been evicted from the active buffer, until it's laid out again.
+/// That's done once this many have built up, rather than for every new line.
+const EVICTED_LINES_BEFORE_RELAYOUT: usize = 100;
+
+#[derive(Debug, Copy, Clone)]
+struct RenderStructuredMessageAttributes<'a> {
+    leading_text: &'a str,
+    leading_text_color: Color,
+    leading_text_background_color: Color,
+    leading_text_background_border_color: Color,
 
+    main_text: &'a str,
+    main_text_color: Color,
+    main_text_background_color: Color,
+    main_text_background_border_color: Color,
+}
+
+impl<'a> RenderStructuredMessageAttributes<'a> {
+    fn new(
+        leading_text: &'a str,
+        leading_text_color: Color,
+        leading_text_background_color: Color,
+        leading_text_background_border_color: Color,
+        main_text: &'a str,
+        main_text_color: Color,
+        main_text_background_color: Color,
+        main_text_background_border_color: Color,
+    ) -> Self {
         Self {
-            boot_services,
-            tcp_service_binding_protocol,
-            active_connection: None,
+            leading_text,
+            leading_text_color,
+            leading_text_background_color,
+            leading_text_background_border_color,
+            main_text,
+            main_text_color,
+            main_text_background_color,
+            main_text_background_border_color,
+        }
+    }
+}
+
+/// Where a line of the active buffer was drawn in the content view, so that it can be drawn again in place
+#[derive(Debug, Copy, Clone)]
+struct DrawnLine {
+    origin: Point,
+    height: isize,
+    is_highlighted: bool,
+}
+
+/// The views in the window, in the order they're drawn, so that later views are on top of earlier ones
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ViewId {
+    Title,
+    Content,
+    InputBox,
+    SendButton,
+    NetworkStatus,
+    SearchBox,
+}
+
+impl ViewId {
+    const ALL: [ViewId; 6] = [
+        ViewId::Title,
+        ViewId::Content,
+        ViewId::InputBox,
+        ViewId::SendButton,
+        ViewId::NetworkStatus,
+        ViewId::SearchBox,
+    ];
+}
+
+fn rects_overlap(a: Rect, b: Rect) -> bool {
+    a.min_x() < b.max_x() && b.min_x() < a.max_x() && a.min_y() < b.max_y() && b.min_y() < a.max_y()
+}
+
+/// The part of the rect that's within the bounds
+fn clamp_rect(rect: Rect, bounds: Size) -> Rect {
+    let min_x = rect.min_x().clamp(0, bounds.width);
+    let min_y = rect.min_y().clamp(0, bounds.height);
+    let max_x = rect.max_x().clamp(0, bounds.width);
+    let max_y = rect.max_y().clamp(0, bounds.height);
+    Rect::from_parts(Point::new(min_x, min_y), Size::new(max_x - min_x, max_y - min_y))
+}
+
+impl<'a> RenderStructuredMessageAttributes<'a> {
+    fn for_line(line: &'a RenderedLine, theme: &Theme) -> Self {
+        let colors = theme.line_colors(line.kind, line.style);
+        Self::new(
+            &line.sender,
+            colors.leading_text,
+            colors.leading_text_background,
+            colors.leading_text_background_border,
+            &line.text,
+            colors.main_text,
+            colors.main_text_background,
+            colors.main_text_background_border,
+        )
+    }
+}
+
+pub struct App<'a> {
+    irc_client: RefCell<IrcClient<'a>>,
+    font_regular: Font,
+    font_italic: Font,
+    pub window: Rc<AwmWindow>,
+    title_view: Rc<TitleView>,
+    /// The connection status most recently shown in the title bar, so we only update it on changes
+    displayed_connection_status: RefCell<String>,
+    /// Whether we've told the user how the network was configured for the current connection
+    has_reported_network_info: RefCell<bool>,
+    content_view: Rc<ContentView>,
+    buffers: RefCell<BufferList<RenderedLine>>,
+    /// The buffer that rendered messages are added to. Anything not routed elsewhere goes to the server buffer.
+    render_target: Cell<BufferId>,
+    /// The buffer list most recently shown in the title bar, so we only update it on changes
+    displayed_buffer_list: RefCell<String>,
+    network_status_view: Rc<NetworkStatusView>,
+    /// The rows most recently shown in the network status panel, so we only redraw it on changes
+    displayed_network_status: RefCell<Vec<(&'static str, String)>>,
+    input_box_view: Rc<InputBoxView>,
+    send_button: Rc<Button>,
+    search_box_view: Rc<SearchBoxView>,
+    /// What's been typed into the search box. Only present while searching.
+    search_query: RefCell<Option<String>>,
+    /// Where each line matching the search was drawn in the content view
+    search_hits: RefCell<SearchHits<isize>>,
+    /// Lines evicted from the active buffer that are still drawn in the content view
+    evicted_lines_in_view: Cell<usize>,
+    /// Where each of the active buffer's lines was drawn, oldest first
+    drawn_lines: RefCell<VecDeque<DrawnLine>>,
+    currently_held_key: RefCell<Option<KeyCode>>,
+    current_pointer_pos: RefCell<Point>,
+    cursor_size: Size,
+    /// Where the cursor was last drawn, along with the window's pixels that it covered up
+    drawn_cursor: RefCell<Option<(Rect, Vec<u8>)>>,
+    /// Views whose contents have changed since they were last drawn
+    dirty_views: RefCell<Vec<ViewId>>,
+    /// Set when something's changed that could affect any part of the window
+    needs_full_redraw: Cell<bool>,
+    is_left_click_down: RefCell<bool>,
+    response_parser: RefCell<ResponseParser>,
+    /// Only present when chat logging is turned on in the config
+    chat_logger: Option<RefCell<ChatLogger<'a>>>,
+    /// Only present when scrollback persistence is turned on in the config
+    scrollback_store: Option<RefCell<ScrollbackStore<'a>>>,
+    clock: Rc<dyn Clock + 'a>,
+    wall_clock: Rc<dyn WallClock + 'a>,
+    /// strftime-style. Empty to hide the timestamp column.
+    timestamp_format: String,
+    /// When the server says the message being rendered was sent, converted to local time
+    message_time: Cell<Option<DateTime>>,
+    /// The screen resolutions that the platform can switch to
+    available_resolutions: RefCell<Vec<Resolution>>,
+    /// A resolution the user asked to switch to, waiting for the platform glue to pick it up
+    requested_resolution: Cell<Option<Resolution>>,
+    theme: RefCell<Theme>,
+    /// Where custom themes are read from. Absent if the filesystem couldn't be opened.
+    theme_storage: Option<Rc<dyn FileStorage + 'a>>,
+}
+
+impl<'a> App<'a> {
+    /// The window is provided by the platform glue, as its construction differs between UEFI and hosted builds.
+    pub fn new(
+        window: Rc<AwmWindow>,
+        font_regular: Font,
+        font_italic: Font,
+        irc_client: IrcClient<'a>,
+        chat_logger: Option<ChatLogger<'a>>,
+        scrollback_store: Option<ScrollbackStore<'a>>,
+        theme_storage: Option<Rc<dyn FileStorage + 'a>>,
+        clock: Rc<dyn Clock + 'a>,
+        wall_clock: Rc<dyn WallClock + 'a>,
+        timestamp_format: &str,
+        theme_name: &str,
+    ) -> Rc<Self> {
+        let resolution = window.frame().size;
+        // Fall back to the default theme, and tell the user once everything's set up
+        let (theme, theme_error) = match load_theme(theme_storage.as_deref(), theme_name) {
+            Ok(theme) => (theme, None),
+            Err(e) => (Theme::light(), Some(e)),
+        };
+        let title_sizer = |superview_size: Size| {
+            Rect::with_size(
+                Size::new(
+                    superview_size.width,
+                    (superview_size.height as f64 * 0.084) as _,
+                )
+            )
+        };
+
+        let title_sizer_clone = title_sizer.clone();
+        let content_sizer = move |superview_size: Size| {
+            let title_frame = title_sizer_clone(superview_size);
+            Rect::from_parts(
+                Point::new(0, title_frame.max_y()),
+                Size::new(
+                    superview_size.width,
+                    (superview_size.height as f64 * 0.82) as _,
+                )
+            )
+        };
+
+        let content_sizer_clone = content_sizer.clone();
+        let input_box_sizer = move |superview_size: Size| {
+            let content_frame = content_sizer_clone(superview_size);
+            Rect::from_parts(
+                Point::new(
+                    0,
+                    content_frame.max_y(),
+                ),
+                Size::new(
+                    (superview_size.width as f64 * 0.9) as _,
+                    (superview_size.height as f64 * 0.1) as _,
+                )
+            )
+        };
+
+        let content_sizer_for_network_status = content_sizer.clone();
+        let network_status_sizer = move |superview_size: Size| {
+            // Float over the right-hand side of the content view
+            let content_frame = content_sizer_for_network_status(superview_size);
+            let width = (content_frame.width() as f64 * 0.45) as isize;
+            Rect::from_parts(
+                Point::new(
+                    content_frame.max_x() - width - 20,
+                    content_frame.min_y() + 20,
+                ),
+                Size::new(
+                    width,
+                    content_frame.height() - 40,
+                )
+            )
+        };
+
+        let content_sizer_for_search_box = content_sizer.clone();
+        let search_box_sizer = move |superview_size: Size| {
+            // Float along the bottom of the content view
+            let content_frame = content_sizer_for_search_box(superview_size);
+            let height = 40;
+            Rect::from_parts(
+                Point::new(
+                    content_frame.min_x() + 20,
+                    content_frame.max_y() - height - 10,
+                ),
+                Size::new(
+                    content_frame.width() - 40,
+                    height,
+                )
+            )
+        };
+
+        let input_box_sizer_clone = input_box_sizer.clone();
+        let send_button_sizer = move |superview_size: Size| {
+            let input_box_frame = input_box_sizer_clone(superview_size);
+            Rect::from_parts(
+                Point::new(
+                    input_box_frame.max_x(),
+                    input_box_frame.min_y(),
+                ),
+                Size::new(
+                    superview_size.width - input_box_frame.width(),
+                    input_box_frame.height(),
+                )
+            )
+        };
+
+        let title = TitleView::new(
+            font_regular.clone(),
+            Size::new(32, 32),
+            &theme,
+            move |v, s| title_sizer(s),
+        );
+
+        let content = ContentView::new(
+            font_regular.clone(),
+            Size::new(20, 20),
+            &theme,
+            move |v, s| content_sizer(s),
+        );
+
+        let input_box = InputBoxView::new(
+            font_regular.clone(),
+            Size::new(24, 24),
+            move |v, s| input_box_sizer(s),
+        );
+
+        let send_button = Button::new(
+            "Send",
+            Some(font_regular.clone()),
+            move |v, s| send_button_sizer(s),
+        );
+
+        let network_status = NetworkStatusView::new(
+            font_regular.clone(),
+            Size::new(20, 20),
+            &theme,
+            move |v, s| network_status_sizer(s),
+        );
+
+        let search_box = SearchBoxView::new(
+            font_regular.clone(),
+            Size::new(20, 20),
+            &theme,
+            move |v, s| search_box_sizer(s),
+        );
+
+        Rc::clone(&window).add_component(Rc::clone(&title) as Rc<dyn UIElement>);
+        Rc::clone(&window).add_component(Rc::clone(&content) as Rc<dyn UIElement>);
+        Rc::clone(&window).add_component(Rc::clone(&input_box) as Rc<dyn UIElement>);
+        Rc::clone(&window).add_component(Rc::clone(&send_button) as Rc<dyn UIElement>);
+        // Added last so that they're drawn on top of the content view
+        Rc::clone(&window).add_component(Rc::clone(&network_status) as Rc<dyn UIElement>);
+        Rc::clone(&window).add_component(Rc::clone(&search_box) as Rc<dyn UIElement>);
+
+        let buffers = BufferList::new("Server", MAX_LINES_PER_BUFFER);
+        let server_buffer_id = buffers.server_id();
+        let _self = Rc::new(
+            Self {
+                irc_client: RefCell::new(irc_client),
+                font_regular,
+                font_italic,
+                window,
+                title_view: Rc::clone(&title),
+                displayed_connection_status: RefCell::new(String::new()),
+                has_reported_network_info: RefCell::new(false),
+                content_view: content,
+                buffers: RefCell::new(buffers),
+                render_target: Cell::new(server_buffer_id),
+                displayed_buffer_list: RefCell::new(String::new()),
+                network_status_view: network_status,
+                displayed_network_status: RefCell::new(vec![]),
+                input_box_view: Rc::clone(&input_box),
+                send_button: Rc::clone(&send_button),
+                search_box_view: search_box,
+                search_query: RefCell::new(None),
+                search_hits: RefCell::new(SearchHits::default()),
+                evicted_lines_in_view: Cell::new(0),
+                drawn_lines: RefCell::new(VecDeque::new()),
+                currently_held_key: RefCell::new(None),
+                // Start off the mouse in the middle of the screen
+                current_pointer_pos: RefCell::new(Point::new(resolution.mid_x(), resolution.mid_y())),
+                cursor_size: Size::new(15, 15),
+                drawn_cursor: RefCell::new(None),
+                dirty_views: RefCell::new(vec![]),
+                // Nothing has been drawn yet
+                needs_full_redraw: Cell::new(true),
+                is_left_click_down: RefCell::new(false),
+                response_parser: RefCell::new(ResponseParser::new()),
+                chat_logger: chat_logger.map(RefCell::new),
+                scrollback_store: scrollback_store.map(RefCell::new),
+                clock,
+                wall_clock,
+                timestamp_format: timestamp_format.to_string(),
+                message_time: Cell::new(None),
+                available_resolutions: RefCell::new(vec![]),
+                requested_resolution: Cell::new(None),
+                theme: RefCell::new(theme),
+                theme_storage,
+            }
+        );
+
+        let self_clone_for_button_cb: Rc<App<'static>> = unsafe { core::mem::transmute(Rc::clone(&_self)) };
+        send_button.on_left_click(move |b|{
+            self_clone_for_button_cb.send_input_and_clear_input_text_box();
+        });
+
+        let self_clone_for_input_box_cb: Rc<App<'static>> = unsafe { core::mem::transmute(Rc::clone(&_self)) };
+        input_box.view.set_on_key_pressed(move |v, key_code|{
+            // PT: UEFI represents the enter key as a carriage return rather than newline
+            if key_code.0 as u8 == '\r' as u8 {
+                Rc::clone(&self_clone_for_input_box_cb).handle_enter_key_pressed();
+            }
+        });
+
+        _self.restore_scrollback();
+        _self.update_buffer_list();
+        if let Some(e) = theme_error {
+            _self.render_error(&format!("Failed to load theme {theme_name}: {e}"));
+        }
+
+        _self
+    }
+
+    /// How far down the content view can scroll, which leaves the last line at the bottom of the view
+    fn max_scroll_offset(&self) -> isize {
+        let cursor_pos = self.content_view.view.cursor_pos.borrow().1;
+        let viewport_height = self.content_view.frame().height();
+        max(0, cursor_pos.y - viewport_height + CONTENT_BOTTOM_MARGIN_PX)
+    }
+
+    fn scroll_to_last_visible_line(&self) {
+        // Auto-scroll to the last visible message
+        self.set_scroll_offset_y(self.max_scroll_offset());
+    }
+
+    fn scroll_by(&self, delta_y: isize) {
+        let scroll_pos = self.content_view.view.view.layer.scroll_offset();
+        self.set_scroll_offset_y(scroll_pos.y + delta_y);
+    }
+
+    fn set_scroll_offset_y(&self, y: isize) {
+        let bound_scroll_offset = self.bind_scroll_offset_to_scrollable_region(Point::new(0, y));
+        if bound_scroll_offset != self.content_view.view.view.layer.scroll_offset() {
+            self.content_view.view.view.layer.set_scroll_offset(bound_scroll_offset);
+            self.set_needs_display(ViewId::Content);
+        }
+    }
+
+    /// Keep the view within the lines that have been drawn, rather than scrolling off into empty space
+    fn bind_scroll_offset_to_scrollable_region(&self, scroll_offset: Point) -> Point {
+        Point::new(
+            0,
+            scroll_offset.y.clamp(0, self.max_scroll_offset()),
+        )
+    }
+
+    /// Scroll by roughly a screenful, keeping a little of the previous screen in view for context
+    fn page_size(&self) -> isize {
+        max(SCROLL_STEP_PX, self.content_view.frame().height() - SCROLL_STEP_PX)
+    }
+
+    /// Scroll the content view in response to the mouse wheel.
+    /// Positive notches scroll down, towards the most recent lines.
+    pub fn handle_scroll_wheel(&self, notches: isize) {
+        self.scroll_by(notches * SCROLL_STEP_PX);
+    }
+
+    fn is_scrolled_to_bottom(&self) -> bool {
+        let scroll_offset = self.content_view.view.view.layer.scroll_offset();
+        scroll_offset.y >= self.max_scroll_offset()
+    }
+
+    fn write_string(&self, s: &str) {
+        self.content_view.view.draw_string(s, self.theme.borrow().content_text);
+        self.set_needs_display(ViewId::Content);
+        self.scroll_to_last_visible_line();
+    }
+
+    pub fn handle_recv_data(&self, recv_data: &[u8]) {
+        let recv_as_str = core::str::from_utf8(recv_data).unwrap();
+        self.write_string(recv_as_str);
+    }
+
+    /// Add a message to the render target's buffer, and draw it if that buffer is being shown
+    fn render_line(&self, kind: LineKind, sender: &str, text: &str) {
+        let target = self.render_target.get();
+        let timestamp = self.message_time.get().unwrap_or_else(|| self.wall_clock.now());
+        let previous_timestamp = {
+            let buffers = self.buffers.borrow();
+            let buffer = buffers.get(target).expect("Render target should be open");
+            if let Some(chat_logger) = &self.chat_logger {
+                chat_logger.borrow_mut().log(buffer.name(), sender, text);
+            }
+            buffer.lines().iter().rev().find_map(|line| line.timestamp)
+        };
+        if previous_timestamp.is_some_and(|previous| !previous.is_same_day(&timestamp)) {
+            let day_changed = format!("Day changed to {}", timestamp.format("%Y-%m-%d"));
+            self.add_line_to_buffer(target, RenderedLine::separator("\u{2014}", &day_changed));
+        }
+        self.add_line_to_buffer(target, RenderedLine::new(kind, sender, text, Some(timestamp)));
+    }
+
+    fn add_line_to_buffer(&self, id: BufferId, line: RenderedLine) {
+        let pushed = self.buffers.borrow_mut().push_line(id, line.clone());
+        // Otherwise, the buffer list shows that there's something unread the next time it's updated
+        if !pushed.is_shown {
+            return;
+        }
+        if pushed.did_evict {
+            self.evicted_lines_in_view.set(self.evicted_lines_in_view.get() + 1);
+            // The evicted line stays drawn until the next layout, but can no longer be searched for
+            let evicted = self.drawn_lines.borrow_mut().pop_front();
+            if evicted.is_some_and(|evicted| evicted.is_highlighted) {
+                self.search_hits.borrow_mut().remove_oldest();
+                self.update_search_box();
+            }
+        }
+        if self.evicted_lines_in_view.get() >= EVICTED_LINES_BEFORE_RELAYOUT {
+            // Laying out the buffer again draws the new line too
+            self.show_active_buffer();
+            return;
+        }
+        let query = self.search_query.borrow().as_deref().and_then(SearchQuery::new);
+        let drawn = self.append_line_to_view(&line, query.as_ref());
+        if drawn.is_highlighted {
+            self.search_hits.borrow_mut().push(drawn.origin.y);
+            self.update_search_box();
+        }
+    }
+
+    /// Draw a line of the active buffer at the end of the content view, highlighted if it matches the search
+    fn append_line_to_view(&self, line: &RenderedLine, query: Option<&SearchQuery>) -> DrawnLine {
+        let origin = self.content_view.view.cursor_pos().1;
+        let is_highlighted = query.is_some_and(|query| query.matches_line(line));
+        match is_highlighted {
+            true => self.draw_line(&line.clone().highlighted()),
+            false => self.draw_line(line),
+        }
+        let drawn = DrawnLine {
+            origin,
+            height: self.content_view.view.cursor_pos().1.y - origin.y,
+            is_highlighted,
+        };
+        self.drawn_lines.borrow_mut().push_back(drawn);
+        drawn
+    }
+
+    /// Draw a line again where it was drawn before, with or without the search highlight.
+    /// Restyling doesn't change the height of a line, so the lines around it stay where they are.
+    fn redraw_line_in_place(&self, line: &RenderedLine, drawn: &DrawnLine, is_highlighted: bool) {
+        let text_view = &self.content_view.view;
+        let row = Rect::from_parts(
+            Point::new(0, drawn.origin.y),
+            Size::new(text_view.frame().size.width, drawn.height),
+        );
+        text_view.view.get_slice().fill_rect(
+            row,
+            self.theme.borrow().content_background,
+            StrokeThickness::Filled,
+        );
+        let end_of_content = text_view.cursor_pos();
+        let mut cursor = end_of_content;
+        cursor.1 = drawn.origin;
+        text_view.set_cursor_pos(cursor);
+        match is_highlighted {
+            true => self.draw_line(&line.clone().highlighted()),
+            false => self.draw_line(line),
+        }
+        text_view.set_cursor_pos(end_of_content);
+    }
+
+    /// Route any messages rendered by the closure to the given buffer
+    fn render_into_buffer<F: FnOnce()>(&self, id: BufferId, f: F) {
+        let previous_target = self.render_target.replace(id);
+        f();
+        self.render_target.set(previous_target);
+    }
+
+    /// Lay out and draw a line at the end of the content view
+    fn draw_line(&self, line: &RenderedLine) {
+        self.set_needs_display(ViewId::Content);
+        let theme = self.theme.borrow();
+        let attributes = RenderStructuredMessageAttributes::for_line(line, &theme);
+        let timestamp = line.timestamp;
+        let text_view = &self.content_view.view;
+        let scroll_view = &self.content_view.view.view;
+
+        // TODO(PT): Share this with the content view?
+        let font_size = Size::new(24, 24);
+
+        // The timestamp column has a fixed width, so that everything after it lines up
+        let timestamp_column_width = match self.timestamp_format.is_empty() {
+            true => 0,
+            false => {
+                let widest_timestamp = DateTime::new(2088, 8, 28, 20, 58, 58).format(&self.timestamp_format);
+                rendered_string_size(&widest_timestamp, &self.font_regular, font_size).width + 8
+            }
+        };
+        if timestamp_column_width > 0 {
+            let initial_x = text_view.cursor_pos().1.x;
+            if let Some(timestamp) = timestamp {
+                text_view.draw_string_with_font(
+                    &timestamp.format(&self.timestamp_format),
+                    &self.font_regular,
+                    font_size,
+                    theme.timestamp,
+                );
+            }
+            let mut cursor = text_view.cursor_pos();
+            cursor.1.x = initial_x + timestamp_column_width;
+            text_view.set_cursor_pos(cursor);
+        }
+
+        // Figure out the layout of the 'leading' / title text
+        let leading_right_side_padding_px = 10;
+        let rendered_leading_text_size = rendered_string_size(
+            attributes.leading_text,
+            &self.font_italic,
+            font_size,
+        );
+
+        // Figure out the layout of the 'content' / main text
+        let start_of_message_content_x = timestamp_column_width + rendered_leading_text_size.width + leading_right_side_padding_px;
+        let message_left_side_padding_x = 6;
+        let message_line_width = text_view.frame().size.width - start_of_message_content_x;
+        let rendered_message_text_size = TextView::rendered_string_size(
+            attributes.main_text,
+            &self.font_regular,
+            font_size,
+            Size::new(
+                message_line_width,
+                // We're not interested in the vertical bound
+                isize::MAX,
+            ),
+            Point::new(
+                start_of_message_content_x,
+                0,
+            )
+        );
+
+        // The background rectangles should take the larger size of the rendered LHS or RHS
+        let background_rect_height = max(rendered_leading_text_size.height, rendered_message_text_size.height);
+
+        let initial_cursor = text_view.cursor_pos();
+        let leading_text_background_frame = Rect::from_parts(
+            initial_cursor.1,
+            Size::new(
+                rendered_leading_text_size.width + leading_right_side_padding_px,
+                rendered_leading_text_size.height,
+            ),
+        );
+
+        // Background of leading text
+        scroll_view.get_slice().fill_rect(
+            leading_text_background_frame,
+            attributes.leading_text_background_color,
+            StrokeThickness::Filled,
+        );
+        // Background border of leading text
+        scroll_view.get_slice().fill_rect(
+            leading_text_background_frame,
+            attributes.leading_text_background_border_color,
+            StrokeThickness::Width(1),
+        );
+
+        text_view.draw_string_with_font(
+            attributes.leading_text,
+            &self.font_italic,
+            font_size,
+            attributes.leading_text_color,
+        );
+
+        let mut cursor = text_view.cursor_pos();
+        cursor.1.x = start_of_message_content_x + message_left_side_padding_x;
+        text_view.set_cursor_pos(cursor);
+
+        // Draw the background for the message itself
+        // TODO(PT): What about when we need to break to a new line..?
+        let message_line_size = Size::new(
+            text_view.frame().size.width - start_of_message_content_x,
+            background_rect_height,
+        );
+        let message_background_frame = Rect::from_parts(
+            Point::new(start_of_message_content_x, cursor.1.y),
+            message_line_size,
+        );
+        // Background of leading text
+        scroll_view.get_slice().fill_rect(
+            message_background_frame,
+            attributes.main_text_background_color,
+            StrokeThickness::Filled,
+        );
+        // Edge case, we'll need to draw the background under the left hand side of the leading text
+        // The layout of the main text broke off into this area as the text view can't know that we
+        // didn't want to do layout underneath the leading text
+        if rendered_message_text_size.height > rendered_leading_text_size.height {
+            let region_beneath_leading_text = Rect::from_parts(
+                Point::new(
+                    initial_cursor.1.x - timestamp_column_width,
+                    initial_cursor.1.y + rendered_leading_text_size.height,
+                ),
+                Size::new(
+                    timestamp_column_width + rendered_leading_text_size.width + leading_right_side_padding_px,
+                    rendered_message_text_size.height - rendered_leading_text_size.height,
+                ),
+            );
+            scroll_view.get_slice().fill_rect(
+                region_beneath_leading_text,
+                attributes.main_text_background_color,
+                StrokeThickness::Filled,
+            );
         }
+
+        // TODO(PT): It looks like outline rects that are spread across multiple scroll view tiles render edges
+        // at tile boundaries, which is incorrect.
+        text_view.draw_string_with_font(
+            attributes.main_text,
+            &self.font_regular,
+            font_size,
+            attributes.main_text_color,
+        );
+        // Advance to the next line
+        let mut updated_cursor = text_view.cursor_pos();
+        updated_cursor.1 = Point::new(
+            initial_cursor.1.x - timestamp_column_width,
+            initial_cursor.1.y + background_rect_height,
+        );
+        text_view.set_cursor_pos(updated_cursor);
+    }
+
+    fn render_structured_server_notice(
+        &self,
+        leading_text: &str,
+        message_text: &str,
+    ) {
+        self.render_line(LineKind::ServerNotice, leading_text, message_text)
+    }
+
+    fn render_unparseable_message(&self, message_text: &str) {
+        self.render_line(LineKind::Unparseable, "Unparseable", message_text)
+    }
+
+    fn render_error(&self, message_text: &str) {
+        self.render_line(LineKind::Error, "Error", message_text)
+    }
+
+    fn render_private_message(&self, leading_str: &str, message: &str) {
+        self.render_line(LineKind::PrivateMessage, leading_str, message)
+    }
+
+    fn render_join_event(&self, message_text: &str) {
+        self.render_line(LineKind::Join, "Join", message_text)
+    }
+
+    fn render_names(&self, channel: &str, names: &[String]) {
+        self.render_line(LineKind::Names, &format!("{channel} members"), &names.join(", "))
+    }
+
+    fn render_message_from_user(&self, message_text: &str) {
+        self.render_line(LineKind::OwnMessage, "You", message_text)
+    }
+
+    fn render_noninteractive_server_prompt(&self, message_text: &str) {
+        self.render_line(LineKind::ServerPrompt, message_text, "")
+    }
+
+    fn render_structured_user_notice(
+        &self,
+        leading_text: &str,
+        message_text: &str,
+    ) {
+        self.render_line(LineKind::UserNotice, leading_text, message_text)
     }
 
-    pub fn connect_to_server_and_register(
-        &mut self,
-        ip_address: IPv4Address,
-        port: u16,
-        nickname: &str,
-        real_name: &str,
+    fn render_structured_user_notice_level2(
+        &self,
+        leading_text: &str,
+        message_text: &str,
     ) {
-        info!("Initializing connection to IRC server...");
-        let tcp_protocol = get_tcp_protocol(
-            self.boot_services,
-            &self.tcp_service_binding_protocol,
+        self.render_line(LineKind::UserNoticeDetail, leading_text, message_text)
+    }
+
+    fn render_message(&self, msg: IrcMessage) {
+        match msg.command {
+            IrcCommand::Notice(p) => {
+                self.render_structured_server_notice("Notice", &p.message);
+            }
+            IrcCommand::ReplyLocalUsers(p) => {
+                self.render_structured_server_notice("User Info", &p.message);
+            }
+            IrcCommand::ReplyMessageOfTheDayStart(p) => {
+                self.render_structured_user_notice("Welcome", &p.message);
+            }
+            IrcCommand::ReplyMessageOfTheDayLine(p) => {
+                self.render_structured_user_notice_level2("Welcome", &p.message);
+            }
+            IrcCommand::ReplyMessageOfTheDayEnd(p) => {
+                self.render_structured_user_notice("Welcome", &p.message);
+            }
+            IrcCommand::Mode(p) => {
+                self.render_structured_server_notice("Mode", &p.mode);
+            }
+            IrcCommand::ReplyListClientUsers(p) => {
+                self.render_structured_server_notice("User Info", &p.message);
+            }
+            IrcCommand::ReplyListOperatorUsers(p) => {
+                self.render_structured_server_notice(
+                    "User Info",
+                    &format!("{} {}", p.operator_count, p.message),
+                );
+            }
+            IrcCommand::ReplyListChannels(p) => {
+                self.render_structured_server_notice(
+                    "Channel Info",
+                    &format!("{} {}", p.channel_count, p.message),
+                );
+            }
+            IrcCommand::ReplyListUnknownUsers(p) => {
+                self.render_structured_server_notice(
+                    "Stats",
+                    &format!("{} {}", p.unknown_user_count, p.message),
+                );
+            }
+            IrcCommand::ReplyListUserMe(p) => {
+                self.render_structured_server_notice(
+                    "User Info",
+                    &p.message,
+                );
+            }
+            IrcCommand::ReplyGlobalUsers(p) => {
+                self.render_structured_server_notice(
+                    "Stats",
+                    &p.message,
+                );
+            }
+            IrcCommand::ReplyConnectionStats(p) => {
+                self.render_structured_server_notice(
+                    "Stats",
+                    &p.message,
+                );
+            }
+            IrcCommand::ReplyWelcome(p) => {
+                self.render_structured_user_notice_level2("Welcome", &p.message);
+            }
+            IrcCommand::ReplyYourHost(p) => {
+                self.render_structured_user_notice_level2("Host", &p.message);
+            }
+            IrcCommand::ReplyCreated(p) => {
+                self.render_structured_server_notice("Created", &p.message);
+            }
+            IrcCommand::ReplyMyInfo(p) => {
+                //self.render_structured_server_notice("Created", &p.message);
+                //self.write_string(&format!("MyInfo {}: {} {} {} {} {:?}", p.nick, p.version, p.server_name, p.available_user_modes, p.available_channel_modes, p.channel_modes_with_params));
+            }
+            IrcCommand::ReplyISupport(p) => {
+                //self.write_string(&format!("ISupport {}: {:?}", p.nick, p.entries));
+            }
+            IrcCommand::Unparseable(msg) => {
+                self.render_unparseable_message(&msg);
+            }
+            IrcCommand::Ping(_) => {
+                // The client replies with the PONG itself
+                self.render_noninteractive_server_prompt("→ Ping");
+                self.render_noninteractive_server_prompt("← Pong");
+            }
+            IrcCommand::ErrorUnknownCommand(p) => {
+                self.render_error(&format!("{}: {}", p.message, p.command));
+            }
+            IrcCommand::ErrorCannotJoin(p) => {
+                self.render_error(&format!("Couldn't join {}: {}", p.channel.0, p.reason));
+            }
+            IrcCommand::PrivateMessage(p) => {
+                let is_to_us = p.recipient.0.eq_ignore_ascii_case(self.irc_client.borrow().nickname());
+                match is_to_us {
+                    true => {
+                        // Direct messages get a query of their own
+                        let query = self.buffers.borrow_mut().open_query(&p.sender.0);
+                        self.update_buffer_list();
+                        self.render_into_buffer(query, || self.render_private_message(&p.sender.0, &p.message));
+                    }
+                    false => {
+                        self.render_private_message(&format!("PM from {}", p.sender.0), &p.message);
+                    }
+                }
+            }
+            IrcCommand::Nick(p) => {
+                let new_nickname = p.nickname.to_string();
+                let description = format!("{} is now known as {new_nickname}", p.user.0);
+                let renamed_query = self.buffers.borrow_mut().rename_query(&p.user.0, &new_nickname);
+                match renamed_query {
+                    None => self.render_structured_server_notice("Nick", &description),
+                    Some(query) => {
+                        self.update_buffer_list();
+                        self.render_into_buffer(query, || self.render_structured_server_notice("Nick", &description));
+                    }
+                }
+            }
+            IrcCommand::Join(p) => {
+                self.render_join_event(&format!("Joined {}", p.channel.0));
+            }
+            IrcCommand::Part(p) => {
+                let description = format!("{} left {}", p.user.0, p.channel.0);
+                match &p.reason {
+                    None => self.render_structured_server_notice("Part", &description),
+                    Some(reason) => self.render_structured_server_notice("Part", &format!("{description} ({reason})")),
+                }
+            }
+            IrcCommand::Kick(p) => {
+                let description = format!("{} was kicked from {} by {}", p.kicked, p.channel.0, p.user.0);
+                match &p.reason {
+                    None => self.render_structured_server_notice("Kick", &description),
+                    Some(reason) => self.render_structured_server_notice("Kick", &format!("{description} ({reason})")),
+                }
+            }
+            IrcCommand::Names(p) => {
+                self.render_names(&p.channel, &p.names);
+            }
+            IrcCommand::EndOfNames(p) => {
+                // Nothing to display
+            }
+            IrcCommand::Topic(p) => {
+                self.render_structured_server_notice(&format!("{} Topic", p.channel), &p.message);
+            }
+            IrcCommand::TopicLastSet(p) => {
+                self.render_structured_server_notice(&format!("{} Topic Update History", p.channel), &format!("Last updated by {}", p.user));
+            }
+            unknown => {
+                self.render_structured_server_notice("Unknown", &format!("{unknown:?}"));
+            }
+        }
+    }
+
+    fn handle_enter_key_pressed(&self) {
+        self.send_input_and_clear_input_text_box();
+    }
+
+    fn send_input_and_clear_input_text_box(&self) {
+        let input_view = &self.input_box_view;
+        let input_str = {
+            let input_drawn_characters = input_view.view.view.text.borrow();
+            input_drawn_characters.iter().map(|c| c.value).collect::<String>()
+        };
+        // Draw the message we just sent, in the buffer it was typed into
+        let input_buffer = self.buffers.borrow().active_id();
+        self.render_into_buffer(input_buffer, || self.render_message_from_user(&input_str));
+        self.input_box_view.view.clear();
+        self.set_needs_display(ViewId::InputBox);
+
+        let (input_buffer_kind, input_buffer_name) = {
+            let buffers = self.buffers.borrow();
+            let active = buffers.active();
+            (active.kind(), active.name().to_string())
+        };
+        match UserCommand::parse(&input_str) {
+            UserCommand::Quit(reason) => {
+                self.irc_client.borrow_mut().quit(reason.as_deref());
+                // We're probably about to be powered off
+                self.prepare_for_exit();
+            }
+            UserCommand::Query(nickname) => {
+                let query = self.buffers.borrow_mut().open_query(&nickname);
+                self.switch_to_buffer(query);
+            }
+            UserCommand::Close => {
+                let did_close = self.buffers.borrow_mut().close(input_buffer);
+                match did_close {
+                    true => self.show_active_buffer(),
+                    false => self.render_error("Only queries can be closed"),
+                }
+            }
+            UserCommand::Join { channels, keys } => self.join_channels(&channels, &keys),
+            UserCommand::Resolution(resolution) => self.handle_resolution_command(resolution.as_deref()),
+            UserCommand::Theme(theme_name) => self.handle_theme_command(theme_name.as_deref()),
+            UserCommand::Raw(command) => self.irc_client.borrow_mut().send_line_command(&command),
+            UserCommand::Text(text) => {
+                let mut irc_client = self.irc_client.borrow_mut();
+                match input_buffer_kind {
+                    BufferKind::Query => irc_client.send_message_to_user(&input_buffer_name, &text),
+                    BufferKind::Server => irc_client.send_line_command(&text),
+                }
+            }
+        }
+    }
+
+    fn join_channels(&self, channels: &[String], keys: &[String]) {
+        let mut irc_client = self.irc_client.borrow_mut();
+        let mut targets = vec![];
+        for (i, name) in channels.iter().enumerate() {
+            match irc_client.parse_channel(name) {
+                Ok(channel) => targets.push(JoinTarget::new(&channel, keys.get(i).map(|key| key.as_str()))),
+                Err(e) => self.render_error(&format!("Can't join {name}: {e}")),
+            }
+        }
+        irc_client.join_channels(&targets);
+    }
+
+    fn current_resolution(&self) -> Resolution {
+        let size = self.window.frame().size;
+        Resolution::new(size.width as _, size.height as _)
+    }
+
+    /// List the resolutions that can be switched to, or ask for a switch to the given one
+    fn handle_resolution_command(&self, arg: Option<&str>) {
+        let current_resolution = self.current_resolution();
+        let available_resolutions = self.available_resolutions.borrow().clone();
+        let arg = match arg {
+            None => {
+                let resolutions = available_resolutions.iter().map(|resolution| {
+                    match *resolution == current_resolution {
+                        true => format!("[{resolution}]"),
+                        false => resolution.to_string(),
+                    }
+                }).collect::<Vec<String>>().join("  ");
+                self.render_structured_server_notice("Resolutions", &resolutions);
+                return;
+            }
+            Some(arg) => arg,
+        };
+        let resolution = match Resolution::parse(arg) {
+            None => {
+                self.render_error(&format!("Can't parse resolution {arg}, expected <width>x<height>"));
+                return;
+            }
+            Some(resolution) => resolution,
+        };
+        if resolution == current_resolution {
+            self.render_structured_server_notice("Resolution", &format!("Already using {resolution}"));
+        }
+        else if !available_resolutions.contains(&resolution) {
+            self.render_error(&format!("{resolution} isn't supported by the display. Use /resolution to list what is."));
+        }
+        else {
+            // Switching the screen mode is up to the platform glue
+            self.requested_resolution.set(Some(resolution));
+        }
+    }
+
+    /// List the themes, or switch to the given one
+    fn handle_theme_command(&self, theme_name: Option<&str>) {
+        let theme_name = match theme_name {
+            None => {
+                let themes = format!(
+                    "Using {}. Built in: {}. Others are read from {THEME_DIRECTORY}\\<name>.txt",
+                    self.theme.borrow().name,
+                    BUILT_IN_THEME_NAMES.join(", "),
+                );
+                self.render_structured_server_notice("Themes", &themes);
+                return;
+            }
+            Some(theme_name) => theme_name,
+        };
+        match load_theme(self.theme_storage.as_deref(), theme_name) {
+            Ok(theme) => self.set_theme(theme),
+            Err(e) => self.render_error(&format!("Failed to load theme {theme_name}: {e}")),
+        }
+    }
+
+    /// Recolour everything, redrawing the retained lines with the new theme
+    fn set_theme(&self, theme: Theme) {
+        self.title_view.set_theme(&theme);
+        self.content_view.set_theme(&theme);
+        self.network_status_view.set_theme(&theme);
+        self.search_box_view.set_theme(&theme);
+        let theme_name = theme.name.clone();
+        *self.theme.borrow_mut() = theme;
+
+        // Fill in the panels again with their new colours
+        self.displayed_network_status.borrow_mut().clear();
+        self.refresh_network_status();
+        self.show_active_buffer();
+        self.needs_full_redraw.set(true);
+        self.render_structured_server_notice("Theme", &format!("Switched to {theme_name}"));
+    }
+
+    /// Provided by the platform glue, so that the user can pick from them
+    pub fn set_available_resolutions(&self, resolutions: Vec<Resolution>) {
+        *self.available_resolutions.borrow_mut() = resolutions;
+    }
+
+    /// The resolution the user has asked to switch to, if any.
+    /// The platform glue should try to switch, then report back with handle_resolution_changed().
+    pub fn take_requested_resolution(&self) -> Option<Resolution> {
+        self.requested_resolution.take()
+    }
+
+    /// Lay everything out again for the new screen mode
+    pub fn handle_resolution_changed(&self, result: Result<Resolution, String>) {
+        let resolution = match result {
+            Err(e) => {
+                self.render_error(&format!("Failed to switch resolution: {e}"));
+                return;
+            }
+            Ok(resolution) => resolution,
+        };
+        // This reallocates the window's framebuffer, and each view is re-laid-out through its sizer
+        self.window.resize(Size::new(resolution.width as _, resolution.height as _));
+        self.needs_full_redraw.set(true);
+        // The cursor's covered pixels are from the old framebuffer
+        *self.drawn_cursor.borrow_mut() = None;
+        // Keep the cursor on screen
+        self.handle_mouse_moved_to(self.pointer_position());
+        // Wrap the retained lines to the new width
+        self.show_active_buffer();
+        self.render_structured_server_notice("Resolution", &format!("Switched to {resolution}"));
+    }
+
+    fn switch_to_buffer(&self, id: BufferId) {
+        if self.buffers.borrow_mut().switch_to(id) {
+            self.show_active_buffer();
+        }
+    }
+
+    fn switch_buffer_by(&self, offset: isize) {
+        if self.buffers.borrow_mut().switch_by(offset) {
+            self.show_active_buffer();
+        }
+    }
+
+    /// Lay out and draw the content view again from the active buffer's retained lines.
+    /// While searching, lines that match are highlighted, and the view is kept on the selected hit.
+    fn show_active_buffer(&self) {
+        self.content_view.view.clear();
+        self.evicted_lines_in_view.set(0);
+        self.drawn_lines.borrow_mut().clear();
+        self.set_needs_display(ViewId::Content);
+        let query = self.search_query.borrow().as_deref().and_then(SearchQuery::new);
+        let buffers = self.buffers.borrow();
+        for line in buffers.active().lines().iter() {
+            self.append_line_to_view(line, query.as_ref());
+        }
+        drop(buffers);
+        self.select_most_recent_search_hit();
+        self.update_buffer_list();
+    }
+
+    /// Highlight the lines that match the search after it's changed.
+    /// Only the lines that start or stop matching are drawn again, rather than laying out the whole buffer.
+    fn restyle_search_hits(&self) {
+        self.set_needs_display(ViewId::Content);
+        let query = self.search_query.borrow().as_deref().and_then(SearchQuery::new);
+        let buffers = self.buffers.borrow();
+        let mut drawn_lines = self.drawn_lines.borrow_mut();
+        for (line, drawn) in buffers.active().lines().iter().zip(drawn_lines.iter_mut()) {
+            let is_highlighted = query.as_ref().is_some_and(|query| query.matches_line(line));
+            if is_highlighted != drawn.is_highlighted {
+                self.redraw_line_in_place(line, drawn, is_highlighted);
+                drawn.is_highlighted = is_highlighted;
+            }
+        }
+        drop(drawn_lines);
+        drop(buffers);
+        self.select_most_recent_search_hit();
+    }
+
+    /// Gather the highlighted lines into the search's hits, and scroll to the most recent
+    fn select_most_recent_search_hit(&self) {
+        let hit_offsets = self.drawn_lines.borrow()
+            .iter()
+            .filter(|drawn| drawn.is_highlighted)
+            .map(|drawn| drawn.origin.y)
+            .collect();
+        *self.search_hits.borrow_mut() = SearchHits::new(hit_offsets);
+        let selected = self.search_hits.borrow().selected().copied();
+        match selected {
+            Some(hit_offset) => self.scroll_to_search_hit(hit_offset),
+            None => self.scroll_to_last_visible_line(),
+        }
+        self.update_search_box();
+    }
+
+    fn is_searching(&self) -> bool {
+        self.search_query.borrow().is_some()
+    }
+
+    fn toggle_search(&self) {
+        let is_searching = !self.is_searching();
+        *self.search_query.borrow_mut() = is_searching.then(String::new);
+        self.search_box_view.view.set_visible(is_searching);
+        self.set_needs_display(ViewId::SearchBox);
+        // Starting a search doesn't need a redraw, as nothing matches an empty query yet
+        match is_searching {
+            true => self.update_search_box(),
+            // Drop the highlights
+            false => self.restyle_search_hits(),
+        }
+    }
+
+    /// Handle a key press while the search box is open. Returns whether the key was used by the search.
+    fn handle_search_key(&self, key: KeyCode) -> bool {
+        if key == SEARCH_HOTKEY || key == ESCAPE_KEY {
+            self.toggle_search();
+        }
+        // Enter and the up arrow step back through the scrollback, as that's the direction people search in
+        else if key == character_key('\r') || key == ARROW_UP_KEY {
+            self.select_search_hit(|hits| hits.select_older());
+        }
+        else if key == ARROW_DOWN_KEY {
+            self.select_search_hit(|hits| hits.select_newer());
+        }
+        else if key == BACKSPACE_KEY {
+            if let Some(query) = self.search_query.borrow_mut().as_mut() {
+                query.pop();
+            }
+            self.restyle_search_hits();
+        }
+        else if let Some(ch) = char::from_u32(key.0 as u32).filter(|ch| !ch.is_control()) {
+            if let Some(query) = self.search_query.borrow_mut().as_mut() {
+                query.push(ch);
+            }
+            self.restyle_search_hits();
+        }
+        else {
+            return false;
+        }
+        true
+    }
+
+    fn select_search_hit<F: FnOnce(&mut SearchHits<isize>)>(&self, f: F) {
+        let selected = {
+            let mut search_hits = self.search_hits.borrow_mut();
+            f(&mut search_hits);
+            search_hits.selected().copied()
+        };
+        if let Some(hit_offset) = selected {
+            self.scroll_to_search_hit(hit_offset);
+        }
+        self.update_search_box();
+    }
+
+    /// Scroll so that the line drawn at the given offset is a third of the way down the content view
+    fn scroll_to_search_hit(&self, hit_offset: isize) {
+        let viewport_height = self.content_view.frame().height();
+        self.set_scroll_offset_y(hit_offset - viewport_height / 3);
+    }
+
+    fn update_search_box(&self) {
+        if let Some(query) = self.search_query.borrow().as_ref() {
+            let status = match query.is_empty() {
+                true => String::new(),
+                false => self.search_hits.borrow().description(),
+            };
+            self.search_box_view.set_query(query, &status);
+            self.set_needs_display(ViewId::SearchBox);
+        }
+    }
+
+    /// Bring back the scrollback saved by the previous session, greyed out and above a separator
+    fn restore_scrollback(&self) {
+        let saved_buffers = match &self.scrollback_store {
+            None => return,
+            Some(store) => store.borrow().load(),
+        };
+        let mut buffers = self.buffers.borrow_mut();
+        for saved_buffer in saved_buffers.iter().filter(|saved_buffer| !saved_buffer.lines.is_empty()) {
+            let id = match saved_buffer.kind {
+                BufferKind::Server => buffers.server_id(),
+                BufferKind::Query => buffers.open_query(&saved_buffer.name),
+            };
+            for line in saved_buffer.lines.iter() {
+                buffers.push_history(id, line.clone().dimmed());
+            }
+            buffers.push_history(id, RenderedLine::separator("\u{2014}", "previous session \u{2014}"));
+        }
+        drop(buffers);
+        self.show_active_buffer();
+    }
+
+    /// Write out anything that's still buffered, rather than waiting for the next periodic write
+    pub fn prepare_for_exit(&self) {
+        if let Some(chat_logger) = &self.chat_logger {
+            chat_logger.borrow_mut().flush();
+        }
+        self.save_scrollback();
+    }
+
+    /// Write out the recent scrollback of every buffer, so that it can be restored on the next boot
+    fn save_scrollback(&self) {
+        let store = match &self.scrollback_store {
+            None => return,
+            Some(store) => store,
+        };
+        let saved_buffers: Vec<SavedBuffer> = self.buffers.borrow().iter().map(|buffer| {
+            SavedBuffer {
+                kind: buffer.kind(),
+                name: buffer.name().to_string(),
+                lines: buffer
+                    .lines()
+                    .iter()
+                    .filter(|line| !line.is_separator())
+                    .cloned()
+                    .collect(),
+            }
+        }).collect();
+        store.borrow_mut().save(&saved_buffers);
+    }
+
+    fn buffer_list_description(&self) -> String {
+        let buffers = self.buffers.borrow();
+        let active_id = buffers.active_id();
+        buffers.iter().map(|buffer| {
+            if buffer.id() == active_id {
+                format!("[{}]", buffer.name())
+            }
+            else if buffer.has_unread() {
+                format!("{}*", buffer.name())
+            }
+            else {
+                buffer.name().to_string()
+            }
+        }).collect::<Vec<String>>().join("  ")
+    }
+
+    fn update_buffer_list(&self) {
+        let buffer_list = self.buffer_list_description();
+        let mut displayed_buffer_list = self.displayed_buffer_list.borrow_mut();
+        if *displayed_buffer_list != buffer_list {
+            self.title_view.set_buffer_list(&buffer_list);
+            self.set_needs_display(ViewId::Title);
+            *displayed_buffer_list = buffer_list;
+        }
+    }
+
+    /// Dispatch a key press that the platform glue has translated with `special_key` or `character_key`
+    pub fn handle_key_pressed(&self, key: KeyCode) {
+        let currently_held_key = *self.currently_held_key.borrow();
+        // Are we switching away from a held key?
+        if let Some(currently_held_key) = currently_held_key {
+            self.window.handle_key_released(currently_held_key);
+        }
+        // While searching, typing goes to the search box rather than the input box
+        if self.is_searching() && self.handle_search_key(key) {
+            return;
+        }
+        // Hack to support scrolling the main content view up and down.
+        // Directly eat scrolling key inputs, instead of forwarding them to libgui.
+        if key == ARROW_UP_KEY {
+            self.scroll_by(-SCROLL_STEP_PX);
+        }
+        else if key == ARROW_DOWN_KEY {
+            self.scroll_by(SCROLL_STEP_PX);
+        }
+        else if key == PAGE_UP_KEY {
+            self.scroll_by(-self.page_size());
+        }
+        else if key == PAGE_DOWN_KEY {
+            self.scroll_by(self.page_size());
+        }
+        else if key == HOME_KEY {
+            self.set_scroll_offset_y(0);
+        }
+        else if key == END_KEY {
+            self.scroll_to_last_visible_line();
+        }
+        else if key == NETWORK_STATUS_HOTKEY {
+            self.toggle_network_status();
+        }
+        else if key == SEARCH_HOTKEY {
+            self.toggle_search();
+        }
+        else if key == PREVIOUS_BUFFER_HOTKEY {
+            self.switch_buffer_by(-1);
+        }
+        else if key == NEXT_BUFFER_HOTKEY {
+            self.switch_buffer_by(1);
+        }
+        // libgui only understands characters, so any other special keys are dropped
+        else if is_character_key(key) {
+            // Inform the window that a new key is held
+            self.window.handle_key_pressed(key);
+            // Typing only goes to the input box
+            self.set_needs_display(ViewId::InputBox);
+            // And update our state to track that this key is currently held
+            self.currently_held_key.replace(Some(key));
+        }
+    }
+
+    pub fn pointer_position(&self) -> Point {
+        *self.current_pointer_pos.borrow()
+    }
+
+    pub fn handle_mouse_moved_to(&self, position: Point) {
+        let orig_mouse_position = *self.current_pointer_pos.borrow();
+        // Bind the mouse to the screen resolution
+        let window_size = self.window.frame().size;
+        let bound_position = Point::new(
+            max(0, min(window_size.width - self.cursor_size.width, position.x)),
+            max(0, min(window_size.height - self.cursor_size.height, position.y)),
+        );
+
+        // And dispatch events to our view tree, if anything changed
+        if bound_position != orig_mouse_position {
+            *self.current_pointer_pos.borrow_mut() = bound_position;
+            self.window.handle_mouse_moved(bound_position);
+            // The button is the only view that reacts to the mouse hovering over it
+            self.set_needs_display(ViewId::SendButton);
+        }
+    }
+
+    pub fn handle_left_click_state(&self, is_left_click_down_now: bool) {
+        let mouse_position = *self.current_pointer_pos.borrow();
+        let orig_is_left_click_down = *self.is_left_click_down.borrow();
+        if !orig_is_left_click_down && is_left_click_down_now {
+            // We just entered a left click
+            self.window.handle_mouse_left_click_down(mouse_position);
+        }
+        else if orig_is_left_click_down && !is_left_click_down_now {
+            // We just exited a left click
+            self.window.handle_mouse_left_click_up(mouse_position);
+        }
+        if orig_is_left_click_down != is_left_click_down_now {
+            // Clicks can move focus around the window
+            self.needs_full_redraw.set(true);
+        }
+        *self.is_left_click_down.borrow_mut() = is_left_click_down_now;
+    }
+
+    fn view(&self, id: ViewId) -> Rc<dyn UIElement> {
+        match id {
+            ViewId::Title => Rc::clone(&self.title_view) as Rc<dyn UIElement>,
+            ViewId::Content => Rc::clone(&self.content_view) as Rc<dyn UIElement>,
+            ViewId::InputBox => Rc::clone(&self.input_box_view) as Rc<dyn UIElement>,
+            ViewId::SendButton => Rc::clone(&self.send_button) as Rc<dyn UIElement>,
+            ViewId::NetworkStatus => Rc::clone(&self.network_status_view) as Rc<dyn UIElement>,
+            ViewId::SearchBox => Rc::clone(&self.search_box_view) as Rc<dyn UIElement>,
+        }
+    }
+
+    /// Mark the view as needing to be drawn again on the next frame
+    fn set_needs_display(&self, id: ViewId) {
+        let mut dirty_views = self.dirty_views.borrow_mut();
+        if !dirty_views.contains(&id) {
+            dirty_views.push(id);
+        }
+    }
+
+    /// Redraw the views that have changed, returning the regions of the window they cover
+    fn draw_dirty_views(&self) -> Vec<Rect> {
+        let dirty_views = core::mem::take(&mut *self.dirty_views.borrow_mut());
+        if self.needs_full_redraw.replace(false) {
+            self.window.draw();
+            return vec![self.window.frame()];
+        }
+        let mut redrawn_frames: Vec<Rect> = vec![];
+        for id in ViewId::ALL {
+            let view = self.view(id);
+            let frame = view.frame();
+            // Anything on top of a redrawn view has just been drawn over, so needs to be drawn again too
+            let is_covered_by_redraw = redrawn_frames.iter().any(|&redrawn| rects_overlap(redrawn, frame));
+            if dirty_views.contains(&id) || is_covered_by_redraw {
+                view.draw();
+                redrawn_frames.push(frame);
+            }
+        }
+        redrawn_frames
+    }
+
+    fn framebuffer_offset(&self, point: Point) -> usize {
+        // The window's framebuffer is packed with 4 bytes per pixel
+        ((point.y * self.window.frame().width() + point.x) * 4) as usize
+    }
+
+    /// Put back the pixels that the cursor was covering up, returning where it was
+    fn erase_cursor(&self) -> Option<Rect> {
+        let (cursor_frame, covered_pixels) = self.drawn_cursor.borrow_mut().take()?;
+        let layer = self.window.layer.borrow();
+        let mut framebuffer = layer.framebuffer.borrow_mut();
+        let row_len = (cursor_frame.width() * 4) as usize;
+        for (i, row) in covered_pixels.chunks_exact(row_len).enumerate() {
+            let offset = self.framebuffer_offset(Point::new(cursor_frame.min_x(), cursor_frame.min_y() + i as isize));
+            framebuffer[offset..offset + row_len].copy_from_slice(row);
+        }
+        Some(cursor_frame)
+    }
+
+    /// Draw the cursor, remembering what it covers up so that it can move without redrawing the views beneath it
+    fn draw_cursor(&self) -> Rect {
+        let cursor_frame = clamp_rect(
+            Rect::from_parts(*self.current_pointer_pos.borrow(), self.cursor_size),
+            self.window.frame().size,
+        );
+        let covered_pixels = {
+            let layer = self.window.layer.borrow();
+            let framebuffer = layer.framebuffer.borrow();
+            let row_len = (cursor_frame.width() * 4) as usize;
+            let mut covered_pixels = Vec::with_capacity(row_len * cursor_frame.height() as usize);
+            for y in cursor_frame.min_y()..cursor_frame.max_y() {
+                let offset = self.framebuffer_offset(Point::new(cursor_frame.min_x(), y));
+                covered_pixels.extend_from_slice(&framebuffer[offset..offset + row_len]);
+            }
+            covered_pixels
+        };
+
+        let theme = self.theme.borrow();
+        let window_slice = self.window.get_slice();
+        // Inner cursor
+        window_slice.fill_rect(
+            cursor_frame,
+            theme.cursor_fill,
+            StrokeThickness::Filled,
         );
+        // Outline
+        window_slice.fill_rect(
+            cursor_frame,
+            theme.cursor_outline,
+            StrokeThickness::Width(3),
+        );
+        *self.drawn_cursor.borrow_mut() = Some((cursor_frame, covered_pixels));
+        cursor_frame
+    }
 
-        let connection = TcpConnection::new(
-            self.boot_services,
-            tcp_protocol,
-            ip_address,
-            port,
+    /// Show where the content view is scrolled to within the history, along its right-hand edge
+    fn draw_scrollbar(&self) {
+        let content_frame = self.content_view.frame();
+        let viewport_height = content_frame.height();
+        let max_scroll_offset = self.max_scroll_offset();
+        // Everything fits on screen
+        if max_scroll_offset == 0 {
+            return;
+        }
+        let content_height = max_scroll_offset + viewport_height;
+        let scroll_offset = self.content_view.view.view.layer.scroll_offset().y.clamp(0, max_scroll_offset);
+
+        let track_frame = Rect::from_parts(
+            Point::new(content_frame.max_x() - SCROLLBAR_WIDTH_PX - 2, content_frame.min_y() + 2),
+            Size::new(SCROLLBAR_WIDTH_PX, viewport_height - 4),
+        );
+        // The thumb is sized by how much of the history is on screen
+        let thumb_height = max(20, track_frame.height() * viewport_height / content_height);
+        let thumb_y = (track_frame.height() - thumb_height) * scroll_offset / max_scroll_offset;
+        let thumb_frame = Rect::from_parts(
+            Point::new(track_frame.min_x(), track_frame.min_y() + thumb_y),
+            Size::new(SCROLLBAR_WIDTH_PX, thumb_height),
         );
-        self.active_connection = Some(connection);
-        self.set_nickname(nickname);
-        self.set_user(nickname, real_name);
+
+        let theme = self.theme.borrow();
+        let window_slice = self.window.get_slice();
+        window_slice.fill_rect(track_frame, theme.scrollbar_track, StrokeThickness::Filled);
+        window_slice.fill_rect(thumb_frame, theme.scrollbar_thumb, StrokeThickness::Filled);
+    }
+
+    /// Render whatever's changed in the view tree, and the cursor, into the window's framebuffer.
+    /// Returns the regions of the window that changed, which is empty when there's nothing new to show.
+    /// Pushing those regions to the display is left to the platform glue.
+    pub fn draw(&self) -> Vec<Rect> {
+        let drawn_cursor_origin = self.drawn_cursor.borrow().as_ref().map(|(frame, _)| Point::new(frame.min_x(), frame.min_y()));
+        let has_cursor_moved = drawn_cursor_origin != Some(*self.current_pointer_pos.borrow());
+        let has_dirty_views = self.needs_full_redraw.get() || !self.dirty_views.borrow().is_empty();
+        if !has_cursor_moved && !has_dirty_views {
+            return vec![];
+        }
+
+        // Take the cursor off the screen first, so that it isn't captured in anything that's redrawn
+        let mut damage: Vec<Rect> = self.erase_cursor().into_iter().collect();
+        let redrawn_frames = self.draw_dirty_views();
+        // The scrollbar sits on top of the content view
+        let content_frame = self.content_view.frame();
+        if redrawn_frames.iter().any(|&frame| rects_overlap(frame, content_frame)) {
+            self.draw_scrollbar();
+        }
+        damage.extend(redrawn_frames);
+
+        // Draw the cursor on top of everything else
+        damage.push(self.draw_cursor());
+
+        let window_size = self.window.frame().size;
+        damage.into_iter().map(|rect| clamp_rect(rect, window_size)).collect()
+    }
+
+    fn render_connection_event(&self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::Disconnected { retry_in_ms, reason } => {
+                let reason = match reason {
+                    None => String::new(),
+                    Some(reason) => format!(" ({reason})"),
+                };
+                self.render_error(&format!("Disconnected from the server{reason}. Reconnecting in {}s...", retry_in_ms / 1000));
+            }
+            ConnectionEvent::ReconnectFailed { retry_in_ms } => {
+                self.render_error(&format!("Failed to reconnect. Trying again in {}s...", retry_in_ms / 1000));
+            }
+            ConnectionEvent::Reconnected => {
+                self.render_structured_server_notice("Connection", "Reconnected to the server");
+            }
+        }
     }
 
-    pub fn send_line_command(&mut self, command: &str) {
-        let data = format!("{command}\r\n").into_bytes();
-        let conn = self.active_connection.as_mut();
-        conn.unwrap().transmit(&data);
+    fn connection_status_description(&self) -> String {
+        let irc_client = self.irc_client.borrow();
+        match irc_client.connection_state() {
+            ConnectionState::Connecting => "Connecting...".to_string(),
+            ConnectionState::Connected => "Connected".to_string(),
+            ConnectionState::Closing => "Disconnecting...".to_string(),
+            ConnectionState::Closed => {
+                let reason = match irc_client.last_error() {
+                    None => "Disconnected".to_string(),
+                    Some(error) => format!("Disconnected: {error}"),
+                };
+                match irc_client.is_awaiting_reconnect() {
+                    true => format!("{reason}, reconnecting..."),
+                    false => reason,
+                }
+            }
+        }
     }
 
-    pub fn set_nickname(&mut self, nickname: &str) {
-        self.send_line_command(&format!("NICK {nickname}"))
+    fn update_connection_status(&self) {
+        let status = self.connection_status_description();
+        let mut displayed_status = self.displayed_connection_status.borrow_mut();
+        if *displayed_status != status {
+            self.title_view.set_connection_status(&status);
+            self.set_needs_display(ViewId::Title);
+            *displayed_status = status;
+        }
     }
 
-    pub fn send_message_to_user(&mut self, user: &str, message: &str) {
-        self.send_line_command(&format!("PRIVMSG {user} :{message}"))
+    /// Show how the network ended up being configured, once per connection
+    fn report_network_info(&self) {
+        if *self.has_reported_network_info.borrow() {
+            return;
+        }
+        if let Some(network_info) = self.irc_client.borrow().network_info() {
+            self.render_structured_server_notice("Network", &network_info.to_string());
+            *self.has_reported_network_info.borrow_mut() = true;
+        }
     }
 
-    pub fn send_message_to_channel(&mut self, channel: &str, message: &str) {
-        // TODO(PT): Auto-join the channel if not already joined?
-        self.send_line_command(&format!("PRIVMSG #{channel} :{message}"))
+    /// The rows of the network status panel.
+    /// Covers each layer that a hung connection could be stuck at: the network configuration, routing,
+    /// the TCP connection, and the IRC traffic on top of it.
+    fn network_status_lines(&self) -> Vec<(&'static str, String)> {
+        let mut lines = vec![("Connection", self.connection_status_description())];
+        let irc_client = self.irc_client.borrow();
+        match irc_client.network_info() {
+            // DHCP hasn't finished, or there's no connection to ask
+            None => lines.push(("Network", "Not configured".to_string())),
+            Some(network_info) => {
+                let source = match network_info.is_static {
+                    true => "static",
+                    false => "DHCP",
+                };
+                lines.push(("Address", format!("{} ({source})", network_info.station_address)));
+                lines.push(("Subnet mask", network_info.subnet_mask.to_string()));
+                let gateway = match network_info.gateway_address {
+                    None => "None".to_string(),
+                    Some(gateway_address) => gateway_address.to_string(),
+                };
+                lines.push(("Gateway", gateway));
+                if network_info.routes.is_empty() {
+                    lines.push(("Routes", "None".to_string()));
+                }
+                for route in network_info.routes.iter() {
+                    lines.push(("Route", route.to_string()));
+                }
+            }
+        }
+        let protocol_state = irc_client.protocol_state().unwrap_or_else(|| "Unknown".to_string());
+        lines.push(("State", protocol_state));
+
+        let stats = irc_client.connection_stats();
+        lines.push(("Sent", format!("{} bytes", stats.bytes_sent)));
+        lines.push(("Received", format!("{} bytes", stats.bytes_received)));
+        lines.push(("Lines parsed", stats.lines_parsed.to_string()));
+        lines
     }
 
-    pub fn join_channel(&mut self, channel: &str) {
-        // TODO(PT): Block if we've already joined this channel?
-        self.send_line_command(&format!("JOIN #{channel}"))
+    fn toggle_network_status(&self) {
+        let is_visible = !self.network_status_view.view.is_visible();
+        self.network_status_view.view.set_visible(is_visible);
+        self.set_needs_display(ViewId::NetworkStatus);
+        if !is_visible {
+            // Uncover what was underneath
+            self.set_needs_display(ViewId::Content);
+        }
+        // Make sure the panel is filled in the next time it's refreshed
+        self.displayed_network_status.borrow_mut().clear();
     }
 
-    pub fn set_user(&mut self, nickname: &str, real_name: &str) {
-        self.send_line_command(&format!("USER {nickname} 0 * :{real_name}"))
+    fn refresh_network_status(&self) {
+        if !self.network_status_view.view.is_visible() {
+            return;
+        }
+        let lines = self.network_status_lines();
+        let mut displayed_lines = self.displayed_network_status.borrow_mut();
+        if *displayed_lines != lines {
+            self.network_status_view.set_lines(&lines);
+            self.set_needs_display(ViewId::NetworkStatus);
+            *displayed_lines = lines;
+        }
     }
 
-    // TODO(PT): Add an 'info bar' on the right that shows available channels/users
-    // The primary cost is drawing, so we can only draw the first N channels
+    /// Whether there's work left over from the last step, such as received lines that didn't fit in its time budget.
+    /// If not, the platform glue can sleep until something happens.
+    pub fn has_pending_work(&self) -> bool {
+        self.response_parser.borrow().has_buffered_line()
+    }
+
+    pub fn step(&self) {
+        let connection_event = self.irc_client.borrow_mut().maintain_connection();
+        if let Some(connection_event) = connection_event {
+            if connection_event == ConnectionEvent::Reconnected {
+                // The new connection may have been configured differently
+                *self.has_reported_network_info.borrow_mut() = false;
+            }
+            self.render_connection_event(connection_event);
+        }
+        self.update_connection_status();
+        self.update_buffer_list();
+        self.report_network_info();
+        self.refresh_network_status();
+        if let Some(chat_logger) = &self.chat_logger {
+            chat_logger.borrow_mut().maybe_flush();
+        }
+        if self.scrollback_store.as_ref().is_some_and(|store| store.borrow().is_save_due()) {
+            self.save_scrollback();
+        }
+
+        let recv_data = self.irc_client.borrow().poll_received();
+        let mut response_parser = self.response_parser.borrow_mut();
+        response_parser.ingest(&recv_data);
+
+        // If the user was currently scrolled to the bottom, scroll to keep them at the bottom
+        let was_at_scroll_bottom = self.is_scrolled_to_bottom();
+        let mut did_render = false;
+        // Catch up on as much as the frame's budget allows, so that a large influx such as a MOTD or
+        // a busy channel's backlog takes a few frames rather than one line per frame
+        let deadline = Deadline::after(self.clock.as_ref(), RENDER_BUDGET_MS);
+        while let Some(msg) = response_parser.parse_next_line() {
+            self.irc_client.borrow_mut().handle_message(&msg);
+            // Prefer the time the server says the message was sent, as it may have been held up, or be replayed history
+            let server_time = msg.server_time.map(|time| time.offset_by_minutes(self.wall_clock.utc_offset_minutes()));
+            self.message_time.set(server_time);
+            self.render_message(msg);
+            self.message_time.set(None);
+            did_render = true;
+            if deadline.has_passed() {
+                break;
+            }
+        }
+        // Scroll once for the whole batch, rather than for every line.
+        // Don't pull the view away from the search hit being looked at.
+        if did_render && was_at_scroll_bottom && !self.is_searching() {
+            self.scroll_to_last_visible_line();
+        }
+    }
 }
@@ -0,0 +1,285 @@
+use alloc::collections::VecDeque;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Identifies a buffer for as long as it's open. IDs aren't reused, so a stale ID never refers to a different buffer.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BufferId(u64);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BufferKind {
+    /// Everything that isn't routed elsewhere. Always open.
+    Server,
+    /// A private conversation with one user
+    Query,
+}
+
+/// A scrollback that's shown on its own, such as a conversation with one user
+#[derive(Debug)]
+pub struct Buffer<T> {
+    id: BufferId,
+    kind: BufferKind,
+    name: String,
+    lines: VecDeque<T>,
+    has_unread: bool,
+}
+
+impl<T> Buffer<T> {
+    fn new(id: BufferId, kind: BufferKind, name: &str) -> Self {
+        Self {
+            id,
+            kind,
+            name: name.to_string(),
+            lines: VecDeque::new(),
+            has_unread: false,
+        }
+    }
+
+    pub fn id(&self) -> BufferId {
+        self.id
+    }
+
+    pub fn kind(&self) -> BufferKind {
+        self.kind
+    }
+
+    /// For a query, the nickname of the other user
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn lines(&self) -> &VecDeque<T> {
+        &self.lines
+    }
+
+    /// Whether anything was added since the buffer was last shown
+    pub fn has_unread(&self) -> bool {
+        self.has_unread
+    }
+
+    /// Drops the oldest line once the buffer is full. Returns whether a line was dropped.
+    fn push(&mut self, line: T, max_lines: usize) -> bool {
+        self.lines.push_back(line);
+        let did_evict = self.lines.len() > max_lines;
+        if did_evict {
+            self.lines.pop_front();
+        }
+        did_evict
+    }
+}
+
+/// What happened when a line was added to a buffer
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PushedLine {
+    /// Whether the buffer is the one being shown, in which case the line should be drawn
+    pub is_shown: bool,
+    /// Whether the buffer's oldest line was dropped to make room
+    pub did_evict: bool,
+}
+
+/// The open buffers, in the order they were opened, and which of them is shown
+#[derive(Debug)]
+pub struct BufferList<T> {
+    buffers: Vec<Buffer<T>>,
+    active: BufferId,
+    next_id: u64,
+    /// Each buffer's scrollback is capped at this many lines, evicting the oldest
+    max_lines_per_buffer: usize,
+}
+
+impl<T> BufferList<T> {
+    pub fn new(server_buffer_name: &str, max_lines_per_buffer: usize) -> Self {
+        let server_id = BufferId(0);
+        Self {
+            buffers: vec![Buffer::new(server_id, BufferKind::Server, server_buffer_name)],
+            active: server_id,
+            next_id: 1,
+            max_lines_per_buffer,
+        }
+    }
+
+    pub fn server_id(&self) -> BufferId {
+        self.buffers[0].id
+    }
+
+    pub fn active_id(&self) -> BufferId {
+        self.active
+    }
+
+    pub fn active(&self) -> &Buffer<T> {
+        self.get(self.active).expect("Active buffer should be open")
+    }
+
+    pub fn get(&self, id: BufferId) -> Option<&Buffer<T>> {
+        self.buffers.iter().find(|buffer| buffer.id == id)
+    }
+
+    fn get_mut(&mut self, id: BufferId) -> Option<&mut Buffer<T>> {
+        self.buffers.iter_mut().find(|buffer| buffer.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Buffer<T>> {
+        self.buffers.iter()
+    }
+
+    /// The query with the given user, if one is open.
+    /// Nicknames are case-insensitive
+    pub fn find_query(&self, nickname: &str) -> Option<BufferId> {
+        self.buffers
+            .iter()
+            .find(|buffer| buffer.kind == BufferKind::Query && buffer.name.eq_ignore_ascii_case(nickname))
+            .map(|buffer| buffer.id)
+    }
+
+    /// Returns the query with the given user, opening one if necessary. Doesn't switch to it.
+    pub fn open_query(&mut self, nickname: &str) -> BufferId {
+        if let Some(id) = self.find_query(nickname) {
+            return id;
+        }
+        let id = BufferId(self.next_id);
+        self.next_id += 1;
+        self.buffers.push(Buffer::new(id, BufferKind::Query, nickname));
+        id
+    }
+
+    /// The server buffer can't be closed. Returns whether the buffer was closed.
+    pub fn close(&mut self, id: BufferId) -> bool {
+        if id == self.server_id() {
+            return false;
+        }
+        let index = match self.buffers.iter().position(|buffer| buffer.id == id) {
+            None => return false,
+            Some(index) => index,
+        };
+        self.buffers.remove(index);
+        if self.active == id {
+            // Fall back to the buffer that was before it
+            self.active = self.buffers[index - 1].id;
+            self.buffers[index - 1].has_unread = false;
+        }
+        true
+    }
+
+    /// Returns whether the active buffer changed
+    pub fn switch_to(&mut self, id: BufferId) -> bool {
+        if self.active == id {
+            return false;
+        }
+        match self.get_mut(id) {
+            None => false,
+            Some(buffer) => {
+                buffer.has_unread = false;
+                self.active = id;
+                true
+            }
+        }
+    }
+
+    /// Switch to the buffer `offset` places away from the active one, wrapping around at either end
+    pub fn switch_by(&mut self, offset: isize) -> bool {
+        let count = self.buffers.len() as isize;
+        let active_index = self.buffers.iter().position(|buffer| buffer.id == self.active).unwrap() as isize;
+        let new_index = (active_index + offset).rem_euclid(count);
+        let id = self.buffers[new_index as usize].id;
+        self.switch_to(id)
+    }
+
+    /// Follow a user's nickname change. Returns the query that was renamed, if there was one.
+    pub fn rename_query(&mut self, old_nickname: &str, new_nickname: &str) -> Option<BufferId> {
+        let id = self.find_query(old_nickname)?;
+        let buffer = self.get_mut(id).unwrap();
+        buffer.name = new_nickname.to_string();
+        Some(id)
+    }
+
+    /// Add a line that isn't new to the user, such as one restored from a previous session, so it doesn't mark the buffer as unread
+    pub fn push_history(&mut self, id: BufferId, line: T) {
+        let max_lines = self.max_lines_per_buffer;
+        let buffer = self.get_mut(id).expect("Tried to add a line to a closed buffer");
+        buffer.push(line, max_lines);
+    }
+
+    pub fn push_line(&mut self, id: BufferId, line: T) -> PushedLine {
+        let is_active = self.active == id;
+        let max_lines = self.max_lines_per_buffer;
+        let buffer = self.get_mut(id).expect("Tried to add a line to a closed buffer");
+        let did_evict = buffer.push(line, max_lines);
+        if !is_active {
+            buffer.has_unread = true;
+        }
+        PushedLine { is_shown: is_active, did_evict }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::buffers::{BufferKind, BufferList, PushedLine};
+
+    #[test]
+    fn test_queries() {
+        let mut buffers: BufferList<&str> = BufferList::new("Server", 100);
+        let server = buffers.server_id();
+        assert_eq!(buffers.active().kind(), BufferKind::Server);
+
+        let alice = buffers.open_query("alice");
+        assert_ne!(alice, server);
+        // Opening an existing query returns the same buffer, regardless of case
+        assert_eq!(buffers.open_query("Alice"), alice);
+        // Opening doesn't switch to it
+        assert_eq!(buffers.active_id(), server);
+
+        // Lines in the background mark the buffer as unread until it's shown
+        buffers.push_history(alice, "earlier");
+        assert!(!buffers.get(alice).unwrap().has_unread());
+        assert!(!buffers.push_line(alice, "hi").is_shown);
+        assert!(buffers.get(alice).unwrap().has_unread());
+        assert!(buffers.switch_to(alice));
+        assert!(!buffers.get(alice).unwrap().has_unread());
+        assert!(buffers.push_line(alice, "how are you?").is_shown);
+        assert_eq!(buffers.active().lines(), &["earlier", "hi", "how are you?"]);
+
+        assert_eq!(buffers.rename_query("ALICE", "alice_away"), Some(alice));
+        assert_eq!(buffers.find_query("alice"), None);
+        assert_eq!(buffers.get(alice).unwrap().name(), "alice_away");
+        assert_eq!(buffers.rename_query("bob", "robert"), None);
+
+        // Closing the active query falls back to the buffer before it
+        assert!(!buffers.close(server));
+        assert!(buffers.close(alice));
+        assert_eq!(buffers.active_id(), server);
+        assert!(buffers.get(alice).is_none());
+    }
+
+    #[test]
+    fn test_oldest_lines_are_evicted() {
+        let mut buffers: BufferList<u32> = BufferList::new("Server", 3);
+        let server = buffers.server_id();
+        for i in 0..3 {
+            assert_eq!(buffers.push_line(server, i), PushedLine { is_shown: true, did_evict: false });
+        }
+        for i in 3..5 {
+            assert_eq!(buffers.push_line(server, i), PushedLine { is_shown: true, did_evict: true });
+        }
+        assert_eq!(buffers.active().lines(), &[2, 3, 4]);
+        buffers.push_history(server, 5);
+        assert_eq!(buffers.active().lines(), &[3, 4, 5]);
+    }
+
+    #[test]
+    fn test_switch_by_wraps() {
+        let mut buffers: BufferList<()> = BufferList::new("Server", 100);
+        let server = buffers.server_id();
+        let alice = buffers.open_query("alice");
+        let bob = buffers.open_query("bob");
+        assert!(buffers.switch_by(-1));
+        assert_eq!(buffers.active_id(), bob);
+        assert!(buffers.switch_by(1));
+        assert_eq!(buffers.active_id(), server);
+        assert!(buffers.switch_by(1));
+        assert_eq!(buffers.active_id(), alice);
+        // With a single buffer, there's nowhere to switch to
+        let mut lone: BufferList<()> = BufferList::new("Server", 100);
+        assert!(!lone.switch_by(1));
+    }
+}
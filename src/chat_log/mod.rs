@@ -0,0 +1,247 @@
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use log::info;
+use crate::clock::{Clock, WallClock};
+use crate::storage::FileStorage;
+
+/// How many old logs are kept for each buffer, as `<name>.log.1` (the newest) to `<name>.log.N`
+const ROTATED_LOG_COUNT: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatLogOptions {
+    pub is_enabled: bool,
+    pub directory: String,
+    /// Once a log would grow past this, it's rotated out and a fresh one is started
+    pub max_file_size: u64,
+    /// Lines are buffered in memory for up to this long before being written out
+    pub flush_interval_ms: u64,
+}
+
+impl Default for ChatLogOptions {
+    fn default() -> Self {
+        Self {
+            // Off by default, as not everyone wants their ESP written to
+            is_enabled: false,
+            directory: "EFI\\Boot\\logs".to_string(),
+            max_file_size: 1024 * 1024,
+            flush_interval_ms: 5_000,
+        }
+    }
+}
+
+/// Lines waiting to be appended to one log file
+#[derive(Debug)]
+struct PendingLog {
+    path: String,
+    data: String,
+}
+
+/// Appends timestamped, per-buffer plain-text logs, in the style of irssi and weechat.
+/// Lines are batched up and written out periodically, as every write to the ESP is slow.
+pub struct ChatLogger<'a> {
+    storage: Rc<dyn FileStorage + 'a>,
+    clock: Rc<dyn Clock + 'a>,
+    wall_clock: Rc<dyn WallClock + 'a>,
+    options: ChatLogOptions,
+    pending: Vec<PendingLog>,
+    /// The logs we've written a session header to
+    opened_paths: Vec<String>,
+    has_created_directory: bool,
+    last_flush_ms: u64,
+}
+
+/// Replace anything that FAT doesn't allow in a file name
+fn sanitized_file_name(name: &str) -> String {
+    name.chars()
+        .map(|ch| match ch {
+            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            ch if ch.is_control() => '_',
+            ch => ch,
+        })
+        .collect()
+}
+
+impl<'a> ChatLogger<'a> {
+    pub fn new(
+        storage: Rc<dyn FileStorage + 'a>,
+        clock: Rc<dyn Clock + 'a>,
+        wall_clock: Rc<dyn WallClock + 'a>,
+        options: ChatLogOptions,
+    ) -> Self {
+        let last_flush_ms = clock.now_ms();
+        Self {
+            storage,
+            clock,
+            wall_clock,
+            options,
+            pending: vec![],
+            opened_paths: vec![],
+            has_created_directory: false,
+            last_flush_ms,
+        }
+    }
+
+    fn path_for_buffer(&self, buffer_name: &str) -> String {
+        format!("{}\\{}.log", self.options.directory, sanitized_file_name(buffer_name))
+    }
+
+    /// Record a line in the given buffer's log
+    pub fn log(&mut self, buffer_name: &str, leading_text: &str, message: &str) {
+        let path = self.path_for_buffer(buffer_name);
+        let now = self.wall_clock.now();
+        let mut data = String::new();
+        if !self.opened_paths.contains(&path) {
+            // Mark where each session starts, as the log is appended to across reboots
+            data.push_str(&format!("--- Log opened {now}\n"));
+            self.opened_paths.push(path.clone());
+        }
+        data.push_str(&format!("{now}\t{leading_text}\t{message}\n"));
+
+        match self.pending.iter_mut().find(|pending| pending.path == path) {
+            Some(pending) => pending.data.push_str(&data),
+            None => self.pending.push(PendingLog { path, data }),
+        }
+    }
+
+    /// Write out buffered lines, if it's been long enough since the last write.
+    /// Should be called regularly from the event loop.
+    pub fn maybe_flush(&mut self) {
+        if self.clock.now_ms() - self.last_flush_ms >= self.options.flush_interval_ms {
+            self.flush();
+        }
+    }
+
+    /// Write out all buffered lines now
+    pub fn flush(&mut self) {
+        self.last_flush_ms = self.clock.now_ms();
+        if self.pending.is_empty() {
+            return;
+        }
+        if !self.has_created_directory {
+            if let Err(e) = self.storage.create_dir_all(&self.options.directory) {
+                info!("Failed to create chat log directory {}: {e}", self.options.directory);
+            }
+            self.has_created_directory = true;
+        }
+        for pending in core::mem::take(&mut self.pending) {
+            let existing_size = self.storage.file_size(&pending.path).unwrap_or(0);
+            // A single flush that's larger than the limit still goes into one file, rather than being split up
+            if existing_size > 0 && existing_size + pending.data.len() as u64 > self.options.max_file_size {
+                self.rotate(&pending.path);
+            }
+            // If the write fails, the lines are dropped rather than piling up in memory
+            if let Err(e) = self.storage.append(&pending.path, pending.data.as_bytes()) {
+                info!("Failed to write chat log {}: {e}", pending.path);
+            }
+        }
+    }
+
+    /// Shift the older logs along, discarding the oldest, and move the current log out of the way
+    fn rotate(&self, path: &str) {
+        let rotated_path = |i: usize| format!("{path}.{i}");
+        if self.storage.file_size(&rotated_path(ROTATED_LOG_COUNT)).is_some() {
+            if let Err(e) = self.storage.remove(&rotated_path(ROTATED_LOG_COUNT)) {
+                info!("Failed to remove old chat log: {e}");
+            }
+        }
+        for i in (1..ROTATED_LOG_COUNT).rev() {
+            if self.storage.file_size(&rotated_path(i)).is_some() {
+                if let Err(e) = self.storage.rename(&rotated_path(i), &rotated_path(i + 1)) {
+                    info!("Failed to rotate chat log: {e}");
+                }
+            }
+        }
+        if let Err(e) = self.storage.rename(path, &rotated_path(1)) {
+            info!("Failed to rotate chat log {path}: {e}");
+        }
+    }
+}
+
+impl Drop for ChatLogger<'_> {
+    fn drop(&mut self) {
+        // Don't lose whatever was logged since the last flush
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::rc::Rc;
+    use alloc::string::ToString;
+    use crate::chat_log::{ChatLogOptions, ChatLogger};
+    use crate::storage::{FileStorage, MemoryFileStorage};
+    use crate::clock::{Clock, DateTime, FakeClock, FakeWallClock, WallClock};
+
+    fn logger(storage: &Rc<MemoryFileStorage>, clock: &Rc<FakeClock>, max_file_size: u64) -> ChatLogger<'static> {
+        let wall_clock = Rc::new(FakeWallClock::new(DateTime::new(2024, 3, 7, 9, 5, 0)));
+        ChatLogger::new(
+            Rc::clone(storage) as Rc<dyn FileStorage>,
+            Rc::clone(clock) as Rc<dyn Clock>,
+            wall_clock as Rc<dyn WallClock>,
+            ChatLogOptions {
+                is_enabled: true,
+                directory: "logs".to_string(),
+                max_file_size,
+                flush_interval_ms: 1_000,
+            },
+        )
+    }
+
+    #[test]
+    fn test_lines_are_flushed_periodically() {
+        let storage = Rc::new(MemoryFileStorage::new());
+        let clock = Rc::new(FakeClock::new());
+        let mut logger = logger(&storage, &clock, 1024 * 1024);
+        logger.log("Server", "Notice", "Welcome");
+        logger.log("alice", "alice", "hi");
+        logger.maybe_flush();
+        assert_eq!(storage.contents("logs\\Server.log"), None);
+
+        clock.advance(1_000);
+        logger.maybe_flush();
+        assert_eq!(
+            storage.contents("logs\\Server.log").unwrap(),
+            "--- Log opened 2024-03-07 09:05:00\n2024-03-07 09:05:00\tNotice\tWelcome\n",
+        );
+        assert_eq!(
+            storage.contents("logs\\alice.log").unwrap(),
+            "--- Log opened 2024-03-07 09:05:00\n2024-03-07 09:05:00\talice\thi\n",
+        );
+
+        // Later lines are appended, without another header
+        logger.log("alice", "You", "hello");
+        drop(logger);
+        assert!(storage.contents("logs\\alice.log").unwrap().ends_with("\talice\thi\n2024-03-07 09:05:00\tYou\thello\n"));
+    }
+
+    #[test]
+    fn test_rotation() {
+        let storage = Rc::new(MemoryFileStorage::new());
+        let clock = Rc::new(FakeClock::new());
+        let mut logger = logger(&storage, &clock, 40);
+        // Each line is around 25 bytes, so every flush after the first rotates
+        for i in 0..5 {
+            logger.log("bob", "bob", &i.to_string());
+            logger.flush();
+        }
+        assert!(storage.contents("logs\\bob.log").unwrap().ends_with("\tbob\t4\n"));
+        assert!(storage.contents("logs\\bob.log.1").unwrap().ends_with("\tbob\t3\n"));
+        assert!(storage.contents("logs\\bob.log.3").unwrap().ends_with("\tbob\t1\n"));
+        // The oldest log was discarded
+        assert!(storage.paths().iter().all(|path| !path.ends_with(".4")));
+        assert_eq!(storage.paths().len(), 4);
+    }
+
+    #[test]
+    fn test_file_names_are_sanitized() {
+        let storage = Rc::new(MemoryFileStorage::new());
+        let clock = Rc::new(FakeClock::new());
+        let mut logger = logger(&storage, &clock, 1024);
+        logger.log("we|rd*nick", "x", "y");
+        logger.flush();
+        assert!(storage.contents("logs\\we_rd_nick.log").is_some());
+    }
+}
@@ -0,0 +1,387 @@
+#[cfg(test)]
+use core::cell::Cell;
+use alloc::format;
+use alloc::string::String;
+use core::fmt::{Display, Formatter};
+#[cfg(feature = "run_in_uefi")]
+use alloc::rc::Rc;
+#[cfg(feature = "run_in_uefi")]
+use core::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "run_in_uefi")]
+use uefi::prelude::BootServices;
+#[cfg(feature = "run_in_uefi")]
+use uefi::table::boot::{EventType, TimerTrigger};
+#[cfg(feature = "run_in_uefi")]
+use uefi::table::runtime::RuntimeServices;
+#[cfg(feature = "run_in_uefi")]
+use crate::event::ManagedEvent;
+
+/// A monotonic source of time.
+/// The epoch is arbitrary, so values are only meaningful relative to each other.
+pub trait Clock {
+    fn now_ms(&self) -> u64;
+}
+
+/// UEFI's wall clock (GetTime) can jump around, so instead we count ticks of a periodic timer event.
+#[cfg(feature = "run_in_uefi")]
+pub struct TimerClock {
+    elapsed_ms: Rc<AtomicU64>,
+    // Held so that the timer keeps firing for as long as the clock is alive
+    _tick_event: ManagedEvent<'static>,
+}
+
+#[cfg(feature = "run_in_uefi")]
+impl TimerClock {
+    const TICK_MS: u64 = 10;
+
+    pub fn new(bs: &'static BootServices) -> Self {
+        let elapsed_ms = Rc::new(AtomicU64::new(0));
+        let elapsed_ms_for_cb = Rc::clone(&elapsed_ms);
+        let tick_event = ManagedEvent::new(
+            bs,
+            EventType::TIMER | EventType::NOTIFY_SIGNAL,
+            move |_| {
+                elapsed_ms_for_cb.fetch_add(Self::TICK_MS, Ordering::Relaxed);
+            },
+        );
+        // Timer periods are specified in units of 100ns
+        bs.set_timer(
+            &tick_event.event,
+            TimerTrigger::Periodic(Self::TICK_MS * 10_000),
+        ).expect("Failed to start the clock timer");
+        Self {
+            elapsed_ms,
+            _tick_event: tick_event,
+        }
+    }
+}
+
+#[cfg(feature = "run_in_uefi")]
+impl Clock for TimerClock {
+    fn now_ms(&self) -> u64 {
+        self.elapsed_ms.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(not(feature = "run_in_uefi"))]
+pub struct HostClock {
+    epoch: std::time::Instant,
+}
+
+#[cfg(not(feature = "run_in_uefi"))]
+impl HostClock {
+    pub fn new() -> Self {
+        Self {
+            epoch: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(not(feature = "run_in_uefi"))]
+impl Clock for HostClock {
+    fn now_ms(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+}
+
+/// Clock that only moves when told to, so tests can control the passage of time
+#[cfg(test)]
+pub struct FakeClock {
+    now_ms: Cell<u64>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub fn new() -> Self {
+        Self {
+            now_ms: Cell::new(0),
+        }
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now_ms.set(self.now_ms.get() + ms)
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.get()
+    }
+}
+
+/// A point in time that work should stop by, such as the end of a frame's time budget
+pub struct Deadline<'a> {
+    clock: &'a dyn Clock,
+    at_ms: u64,
+}
+
+impl<'a> Deadline<'a> {
+    pub fn after(clock: &'a dyn Clock, duration_ms: u64) -> Self {
+        Self {
+            clock,
+            at_ms: clock.now_ms() + duration_ms,
+        }
+    }
+
+    pub fn has_passed(&self) -> bool {
+        self.clock.now_ms() >= self.at_ms
+    }
+}
+
+/// A calendar date and time of day
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn new(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> Self {
+        Self {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    /// Convert seconds since the Unix epoch, in UTC
+    pub fn from_unix_secs(secs: u64) -> Self {
+        let days = (secs / 86_400) as i64;
+        let secs_of_day = secs % 86_400;
+        // Howard Hinnant's days-to-civil algorithm, with eras of 400 years starting from March 1st
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+        Self::new(
+            year as u16,
+            month as u8,
+            day as u8,
+            (secs_of_day / 3_600) as u8,
+            (secs_of_day % 3_600 / 60) as u8,
+            (secs_of_day % 60) as u8,
+        )
+    }
+
+    /// Seconds since the Unix epoch, treating this as UTC
+    pub fn to_unix_secs(self) -> u64 {
+        // The inverse of the algorithm in from_unix_secs
+        let year = self.year as i64 - if self.month <= 2 { 1 } else { 0 };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let shifted_month = (self.month as i64 + 9) % 12;
+        let day_of_year = (153 * shifted_month + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        (days * 86_400) as u64 + self.hour as u64 * 3_600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    /// Shift by a time zone offset, such as to convert from UTC to local time
+    pub fn offset_by_minutes(&self, minutes: i32) -> Self {
+        let secs = self.to_unix_secs() as i64 + minutes as i64 * 60;
+        Self::from_unix_secs(secs.max(0) as u64)
+    }
+
+    pub fn is_same_day(&self, other: &DateTime) -> bool {
+        (self.year, self.month, self.day) == (other.year, other.month, other.day)
+    }
+
+    /// Parse an ISO 8601 timestamp, such as `2024-03-07T09:05:00.123Z` from an IRCv3 `time` tag.
+    /// Fractional seconds and the zone designator are ignored. A space is also accepted between the date and time.
+    pub fn parse_iso8601(s: &str) -> Option<Self> {
+        let (date, time) = s.split_once(['T', ' '])?;
+        let mut date_parts = date.splitn(3, '-');
+        let year = date_parts.next()?.parse::<u16>().ok()?;
+        let month = date_parts.next()?.parse::<u8>().ok()?;
+        let day = date_parts.next()?.parse::<u8>().ok()?;
+
+        let time = time.trim_end_matches('Z');
+        let time = time.split_once('.').map_or(time, |(whole_secs, _fraction)| whole_secs);
+        let mut time_parts = time.splitn(3, ':');
+        let hour = time_parts.next()?.parse::<u8>().ok()?;
+        let minute = time_parts.next()?.parse::<u8>().ok()?;
+        let second = time_parts.next()?.parse::<u8>().ok()?;
+
+        let is_valid = (1..=12).contains(&month) && (1..=31).contains(&day) && hour < 24 && minute < 60 && second <= 60;
+        is_valid.then(|| Self::new(year, month, day, hour, minute, second))
+    }
+
+    /// Format with strftime-style specifiers: `%Y`, `%m`, `%d`, `%H`, `%M`, `%S` and `%%`.
+    /// Anything else is copied through as-is.
+    pub fn format(&self, format: &str) -> String {
+        let mut out = String::with_capacity(format.len() + 8);
+        let mut chars = format.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '%' {
+                out.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{:04}", self.year)),
+                Some('m') => out.push_str(&format!("{:02}", self.month)),
+                Some('d') => out.push_str(&format!("{:02}", self.day)),
+                Some('H') => out.push_str(&format!("{:02}", self.hour)),
+                Some('M') => out.push_str(&format!("{:02}", self.minute)),
+                Some('S') => out.push_str(&format!("{:02}", self.second)),
+                Some('%') => out.push('%'),
+                Some(other) => {
+                    out.push('%');
+                    out.push(other);
+                }
+                None => out.push('%'),
+            }
+        }
+        out
+    }
+}
+
+impl Display for DateTime {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second,
+        )
+    }
+}
+
+/// The current calendar time. Unlike `Clock`, this can jump around, so it's only suitable for showing to people.
+pub trait WallClock {
+    fn now(&self) -> DateTime;
+
+    /// How far the times returned by `now()` are ahead of UTC, so that times from elsewhere can be shown consistently
+    fn utc_offset_minutes(&self) -> i32 {
+        0
+    }
+}
+
+/// The firmware's real-time clock, via GetTime
+#[cfg(feature = "run_in_uefi")]
+pub struct RuntimeWallClock {
+    runtime_services: &'static RuntimeServices,
+}
+
+#[cfg(feature = "run_in_uefi")]
+impl RuntimeWallClock {
+    pub fn new(runtime_services: &'static RuntimeServices) -> Self {
+        Self {
+            runtime_services,
+        }
+    }
+}
+
+#[cfg(feature = "run_in_uefi")]
+impl WallClock for RuntimeWallClock {
+    fn now(&self) -> DateTime {
+        let time = self.runtime_services.get_time().expect("Failed to read the real-time clock");
+        DateTime::new(time.year(), time.month(), time.day(), time.hour(), time.minute(), time.second())
+    }
+
+    fn utc_offset_minutes(&self) -> i32 {
+        // Firmware often leaves the time zone unspecified, in which case we can only assume the RTC is in UTC.
+        // Per UEFI 2.7 onwards, local time = UTC + TimeZone.
+        match self.runtime_services.get_time() {
+            Ok(time) => time.time_zone().map_or(0, |minutes| minutes as i32),
+            Err(_) => 0,
+        }
+    }
+}
+
+#[cfg(not(feature = "run_in_uefi"))]
+pub struct HostWallClock;
+
+#[cfg(not(feature = "run_in_uefi"))]
+impl WallClock for HostWallClock {
+    fn now(&self) -> DateTime {
+        let since_epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .expect("System time is before the Unix epoch");
+        DateTime::from_unix_secs(since_epoch.as_secs())
+    }
+}
+
+/// Wall clock that shows whatever it's told to
+#[cfg(test)]
+pub struct FakeWallClock {
+    now: Cell<DateTime>,
+}
+
+#[cfg(test)]
+impl FakeWallClock {
+    pub fn new(now: DateTime) -> Self {
+        Self {
+            now: Cell::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime) {
+        self.now.set(now)
+    }
+}
+
+#[cfg(test)]
+impl WallClock for FakeWallClock {
+    fn now(&self) -> DateTime {
+        self.now.get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+    use crate::clock::{DateTime, Deadline, FakeClock};
+
+    #[test]
+    fn test_deadline() {
+        let clock = FakeClock::new();
+        clock.advance(500);
+        let deadline = Deadline::after(&clock, 20);
+        assert!(!deadline.has_passed());
+        clock.advance(19);
+        assert!(!deadline.has_passed());
+        clock.advance(1);
+        assert!(deadline.has_passed());
+    }
+
+    #[test]
+    fn test_from_unix_secs() {
+        assert_eq!(DateTime::from_unix_secs(0), DateTime::new(1970, 1, 1, 0, 0, 0));
+        // A leap day
+        assert_eq!(DateTime::from_unix_secs(951_782_400 + 3_661), DateTime::new(2000, 2, 29, 1, 1, 1));
+        assert_eq!(DateTime::from_unix_secs(1_704_067_199), DateTime::new(2023, 12, 31, 23, 59, 59));
+        assert_eq!(DateTime::new(2024, 3, 7, 9, 5, 0).to_string(), "2024-03-07 09:05:00");
+        for secs in [0, 951_782_400 + 3_661, 1_704_067_199] {
+            assert_eq!(DateTime::from_unix_secs(secs).to_unix_secs(), secs);
+        }
+        assert_eq!(DateTime::new(2023, 12, 31, 23, 30, 0).offset_by_minutes(60), DateTime::new(2024, 1, 1, 0, 30, 0));
+    }
+
+    #[test]
+    fn test_parse_iso8601() {
+        assert_eq!(DateTime::parse_iso8601("2019-02-28T19:30:01.123Z"), Some(DateTime::new(2019, 2, 28, 19, 30, 1)));
+        assert_eq!(DateTime::parse_iso8601("2024-03-07 09:05:00"), Some(DateTime::new(2024, 3, 7, 9, 5, 0)));
+        assert_eq!(DateTime::parse_iso8601("2024-13-07T09:05:00Z"), None);
+        assert_eq!(DateTime::parse_iso8601("yesterday"), None);
+    }
+
+    #[test]
+    fn test_format() {
+        let time = DateTime::new(2024, 3, 7, 9, 5, 2);
+        assert_eq!(time.format("%H:%M"), "09:05");
+        assert_eq!(time.format("[%d/%m/%Y %H:%M:%S] 100%%"), "[07/03/2024 09:05:02] 100%");
+        assert_eq!(time.format("%Q%"), "%Q%");
+    }
+}
@@ -0,0 +1,418 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::chat_log::ChatLogOptions;
+use crate::scrollback::ScrollbackOptions;
+use crate::resolution::Resolution;
+use crate::theme::DEFAULT_THEME_NAME;
+use crate::ipv4::{IPv4Address, StaticIpConfig};
+use crate::irc::{DEFAULT_SEND_BURST, DEFAULT_SEND_REFILL_INTERVAL_MS};
+use crate::transport::{KeepAlive, TcpOptions};
+
+/// Path of the config file, relative to the root of the EFI filesystem
+pub const CONFIG_FILE_PATH: &str = "EFI\\Boot\\config.txt";
+
+const DEFAULT_RX_BUFFER_SIZE: usize = 32 * 1024;
+const DEFAULT_RX_BUFFER_COUNT: usize = 4;
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%H:%M";
+/// Used for whichever keep-alive settings aren't given, once keep-alive has been turned on by setting any of them
+const DEFAULT_KEEP_ALIVE: KeepAlive = KeepAlive {
+    idle_secs: 60,
+    interval_secs: 15,
+    probes: 4,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub server_ip: IPv4Address,
+    pub server_port: u16,
+    pub nickname: String,
+    pub real_name: String,
+    /// Size of each buffer that the TCP stack receives into
+    pub rx_buffer_size: usize,
+    /// How many receives are kept queued with the TCP stack at once
+    pub rx_buffer_count: usize,
+    /// How many lines can be sent back-to-back before the rate limit kicks in
+    pub send_burst: u32,
+    /// Once the burst is used up, how long to wait between lines
+    pub send_refill_interval_ms: u64,
+    pub tcp_options: TcpOptions,
+    /// Used instead of DHCP, if given
+    pub static_ip: Option<StaticIpConfig>,
+    pub chat_log: ChatLogOptions,
+    pub scrollback: ScrollbackOptions,
+    /// How message timestamps are shown, with strftime-style specifiers. Empty to hide them.
+    pub timestamp_format: String,
+    /// Used instead of picking a screen mode automatically, if the display supports it
+    pub resolution: Option<Resolution>,
+    /// Either a built-in theme, or the name of a file in the theme directory
+    pub theme: String,
+}
+
+fn parse_u32(key: &str, value: &str) -> u32 {
+    match value.parse::<u32>() {
+        Ok(num) => num,
+        Err(_) => panic!("Failed to parse {key}: expected a number, got {value}"),
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> bool {
+    match value {
+        "true" => true,
+        "false" => false,
+        _ => panic!("Failed to parse {key}: expected true or false, got {value}"),
+    }
+}
+
+fn parse_ipv4_address(s: &str) -> IPv4Address {
+    let ip_parts: Vec<&str> = s.split('.').collect();
+    if ip_parts.len() != 4 {
+        panic!("IP string does not contain exactly 4 octets");
+    }
+    let mut octets = [0u8; 4];
+    for (i, part) in ip_parts.iter().enumerate() {
+        match part.parse::<u8>() {
+            Ok(num) => octets[i] = num,
+            Err(_) => panic!("Invalid octet {part}"),
+        }
+    }
+    IPv4Address::new(octets[0], octets[1], octets[2], octets[3])
+}
+
+/// A subnet mask must be a contiguous run of ones followed by zeroes
+fn is_valid_subnet_mask(mask: IPv4Address) -> bool {
+    let bits = u32::from_be_bytes(mask.0);
+    bits.leading_ones() + bits.trailing_zeros() == 32
+}
+
+impl Config {
+    pub fn parse(config_str: &str) -> Self {
+        // PT: Not going to bother making an ergonomic parse here for now - this is intentionally basic
+        let mut server_ip = None;
+        let mut server_port = None;
+        let mut nickname = None;
+        let mut real_name = None;
+        let mut rx_buffer_size = DEFAULT_RX_BUFFER_SIZE;
+        let mut rx_buffer_count = DEFAULT_RX_BUFFER_COUNT;
+        let mut send_burst = DEFAULT_SEND_BURST;
+        let mut send_refill_interval_ms = DEFAULT_SEND_REFILL_INTERVAL_MS;
+        let mut tcp_options = TcpOptions::default();
+        let mut station_ip = None;
+        let mut subnet_mask = None;
+        let mut gateway_ip = None;
+        let mut chat_log = ChatLogOptions::default();
+        let mut scrollback = ScrollbackOptions::default();
+        let mut timestamp_format = DEFAULT_TIMESTAMP_FORMAT.to_string();
+        let mut resolution = None;
+        let mut theme = DEFAULT_THEME_NAME.to_string();
+        for line in config_str.lines() {
+            // Skip comments and blank lines
+            if line.starts_with('#') || line.trim().is_empty() {
+                continue;
+            }
+            let line_parts: Vec<&str> = line.split('=').collect();
+            let (&prefix, &suffix) = match line_parts.as_slice() {
+                [prefix, suffix] => (prefix, suffix),
+                _ => panic!("Expected exactly two parts"),
+            };
+            match prefix {
+                "server_ip_address" => server_ip = Some(parse_ipv4_address(suffix)),
+                "server_port" => {
+                    server_port = Some(suffix.parse::<u16>().expect("Failed to parse a port"));
+                },
+                "nickname" => nickname = Some(suffix.to_string()),
+                "real_name" => real_name = Some(suffix.to_string()),
+                "rx_buffer_size" => {
+                    rx_buffer_size = suffix.parse::<usize>().expect("Failed to parse an RX buffer size");
+                    if rx_buffer_size == 0 || rx_buffer_size > u32::MAX as usize {
+                        panic!("RX buffer size must be between 1 and {} bytes", u32::MAX);
+                    }
+                },
+                "rx_buffer_count" => {
+                    rx_buffer_count = suffix.parse::<usize>().expect("Failed to parse an RX buffer count");
+                    if rx_buffer_count == 0 {
+                        panic!("Need at least one RX buffer");
+                    }
+                },
+                "send_burst" => {
+                    send_burst = suffix.parse::<u32>().expect("Failed to parse a send burst");
+                    if send_burst == 0 {
+                        panic!("Send burst must allow at least one line");
+                    }
+                },
+                "send_refill_interval_ms" => {
+                    send_refill_interval_ms = suffix.parse::<u64>().expect("Failed to parse a send refill interval");
+                    if send_refill_interval_ms == 0 {
+                        panic!("Send refill interval must be non-zero");
+                    }
+                },
+                "tcp_receive_buffer_size" => tcp_options.receive_buffer_size = Some(parse_u32(prefix, suffix)),
+                "tcp_send_buffer_size" => tcp_options.send_buffer_size = Some(parse_u32(prefix, suffix)),
+                "tcp_connection_timeout_secs" => tcp_options.connection_timeout_secs = Some(parse_u32(prefix, suffix)),
+                "tcp_data_retries" => tcp_options.data_retries = Some(parse_u32(prefix, suffix)),
+                "tcp_fin_timeout_secs" => tcp_options.fin_timeout_secs = Some(parse_u32(prefix, suffix)),
+                "tcp_time_wait_timeout_secs" => tcp_options.time_wait_timeout_secs = Some(parse_u32(prefix, suffix)),
+                "tcp_keep_alive_idle_secs" => {
+                    tcp_options.keep_alive.get_or_insert(DEFAULT_KEEP_ALIVE).idle_secs = parse_u32(prefix, suffix);
+                },
+                "tcp_keep_alive_interval_secs" => {
+                    tcp_options.keep_alive.get_or_insert(DEFAULT_KEEP_ALIVE).interval_secs = parse_u32(prefix, suffix);
+                },
+                "tcp_keep_alive_probes" => {
+                    tcp_options.keep_alive.get_or_insert(DEFAULT_KEEP_ALIVE).probes = parse_u32(prefix, suffix);
+                },
+                "station_ip_address" => station_ip = Some(parse_ipv4_address(suffix)),
+                "subnet_mask" => subnet_mask = Some(parse_ipv4_address(suffix)),
+                "gateway_ip_address" => gateway_ip = Some(parse_ipv4_address(suffix)),
+                "tcp_nagle" => tcp_options.enable_nagle = parse_bool(prefix, suffix),
+                "chat_log" => chat_log.is_enabled = parse_bool(prefix, suffix),
+                "chat_log_directory" => {
+                    // Paths are relative to the root of the ESP
+                    chat_log.directory = suffix.trim_matches('\\').to_string();
+                    if chat_log.directory.is_empty() {
+                        panic!("Chat log directory can't be the root of the filesystem");
+                    }
+                },
+                "chat_log_max_file_size" => {
+                    chat_log.max_file_size = suffix.parse::<u64>().expect("Failed to parse a chat log size");
+                    if chat_log.max_file_size == 0 {
+                        panic!("Chat log size must be non-zero");
+                    }
+                },
+                "chat_log_flush_interval_secs" => {
+                    chat_log.flush_interval_ms = parse_u32(prefix, suffix) as u64 * 1000;
+                },
+                "scrollback_persistence" => scrollback.is_enabled = parse_bool(prefix, suffix),
+                "scrollback_lines" => {
+                    scrollback.line_count = suffix.parse::<usize>().expect("Failed to parse a scrollback line count");
+                    if scrollback.line_count == 0 {
+                        panic!("Scrollback line count must be non-zero. Use scrollback_persistence=false to turn it off");
+                    }
+                },
+                "timestamp_format" => timestamp_format = suffix.to_string(),
+                "resolution" => {
+                    resolution = Some(Resolution::parse(suffix).expect("Failed to parse a resolution, expected <width>x<height>"));
+                },
+                "theme" => {
+                    theme = suffix.trim().to_string();
+                    if theme.is_empty() {
+                        panic!("Theme name can't be empty");
+                    }
+                },
+                "scrollback_save_interval_secs" => {
+                    scrollback.save_interval_ms = parse_u32(prefix, suffix) as u64 * 1000;
+                },
+                _ => panic!("Unrecognized config key {prefix}"),
+            }
+        }
+        if let Err(e) = tcp_options.validate() {
+            panic!("Invalid TCP options in config: {e}");
+        }
+        let static_ip = match (station_ip, subnet_mask) {
+            (None, None) => {
+                if gateway_ip.is_some() {
+                    panic!("A gateway was specified without a static IP address");
+                }
+                None
+            }
+            (Some(station_address), Some(subnet_mask)) => {
+                if station_address.is_zero() {
+                    panic!("Static IP address can't be 0.0.0.0");
+                }
+                if !is_valid_subnet_mask(subnet_mask) {
+                    panic!("Invalid subnet mask {subnet_mask}");
+                }
+                Some(
+                    StaticIpConfig {
+                        station_address,
+                        subnet_mask,
+                        gateway_address: gateway_ip,
+                    }
+                )
+            }
+            _ => panic!("A static IP address and subnet mask must be specified together"),
+        };
+        Self {
+            server_ip: server_ip.expect("No server IP address specified"),
+            server_port: server_port.expect("No server port specified"),
+            nickname: nickname.expect("No nickname specified"),
+            real_name: real_name.expect("No real name specified"),
+            rx_buffer_size,
+            rx_buffer_count,
+            send_burst,
+            send_refill_interval_ms,
+            tcp_options,
+            static_ip,
+            chat_log,
+            scrollback,
+            timestamp_format,
+            resolution,
+            theme,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::format;
+    use crate::chat_log::ChatLogOptions;
+    use crate::config::Config;
+    use crate::scrollback::ScrollbackOptions;
+    use crate::resolution::Resolution;
+    use crate::ipv4::{IPv4Address, StaticIpConfig};
+    use crate::transport::KeepAlive;
+
+    /// Parse the settings every config needs, followed by the given lines
+    fn parse_with(extra_lines: &str) -> Config {
+        Config::parse(&format!("server_ip_address=10.0.0.1\nserver_port=6667\nnickname=a\nreal_name=b\n{extra_lines}"))
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "# The IP(v4) address of the IRC server\nserver_ip_address=109.74.200.93\nserver_port=6667\n\nnickname=phill\nreal_name=phillip@axleos.com"
+        );
+        assert_eq!(config.server_ip, IPv4Address::new(109, 74, 200, 93));
+        assert_eq!(config.server_port, 6667);
+        assert_eq!(config.nickname, "phill");
+        assert_eq!(config.real_name, "phillip@axleos.com");
+        assert_eq!(config.rx_buffer_size, 32 * 1024);
+        assert_eq!(config.rx_buffer_count, 4);
+        assert_eq!(config.timestamp_format, "%H:%M");
+        assert_eq!(config.resolution, None);
+    }
+
+    #[test]
+    fn test_parse_resolution() {
+        let config = parse_with("resolution=1920x1080");
+        assert_eq!(config.resolution, Some(Resolution::new(1920, 1080)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_invalid_resolution() {
+        parse_with("resolution=big");
+    }
+
+    #[test]
+    fn test_parse_theme() {
+        let config = parse_with("");
+        assert_eq!(config.theme, "light");
+        let config = parse_with("theme=dark");
+        assert_eq!(config.theme, "dark");
+    }
+
+    #[test]
+    fn test_parse_timestamp_format() {
+        let config = parse_with("timestamp_format=[%H:%M:%S]");
+        assert_eq!(config.timestamp_format, "[%H:%M:%S]");
+        // Can be turned off
+        let config = parse_with("timestamp_format=");
+        assert_eq!(config.timestamp_format, "");
+    }
+
+    #[test]
+    fn test_parse_rx_buffers() {
+        let config = parse_with("rx_buffer_size=4096\nrx_buffer_count=8");
+        assert_eq!(config.rx_buffer_size, 4096);
+        assert_eq!(config.rx_buffer_count, 8);
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        let config = parse_with("send_burst=10\nsend_refill_interval_ms=500");
+        assert_eq!(config.send_burst, 10);
+        assert_eq!(config.send_refill_interval_ms, 500);
+    }
+
+    #[test]
+    fn test_parse_tcp_options() {
+        let config = parse_with("tcp_keep_alive_idle_secs=90\ntcp_nagle=true\ntcp_receive_buffer_size=65536");
+        let options = config.tcp_options;
+        assert_eq!(options.receive_buffer_size, Some(65536));
+        assert_eq!(options.send_buffer_size, None);
+        assert!(options.enable_nagle);
+        // The keep-alive settings that weren't given are filled in with defaults
+        assert_eq!(options.keep_alive, Some(KeepAlive { idle_secs: 90, interval_secs: 15, probes: 4 }));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_invalid_tcp_options() {
+        parse_with("tcp_keep_alive_probes=0");
+    }
+
+    #[test]
+    fn test_parse_static_ip() {
+        let config = parse_with("station_ip_address=10.0.0.5\nsubnet_mask=255.255.255.0\ngateway_ip_address=10.0.0.254");
+        assert_eq!(
+            config.static_ip,
+            Some(
+                StaticIpConfig {
+                    station_address: IPv4Address::new(10, 0, 0, 5),
+                    subnet_mask: IPv4Address::new(255, 255, 255, 0),
+                    gateway_address: Some(IPv4Address::new(10, 0, 0, 254)),
+                }
+            ),
+        );
+        // DHCP is used by default
+        assert_eq!(parse_with("").static_ip, None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_static_ip_without_subnet_mask() {
+        parse_with("station_ip_address=10.0.0.5");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_non_contiguous_subnet_mask() {
+        parse_with("station_ip_address=10.0.0.5\nsubnet_mask=255.0.255.0");
+    }
+
+    #[test]
+    fn test_parse_chat_log() {
+        let config = parse_with("");
+        assert_eq!(config.chat_log, ChatLogOptions::default());
+        assert!(!config.chat_log.is_enabled);
+
+        let config = parse_with("chat_log=true\nchat_log_directory=\\irc\\logs\\\nchat_log_max_file_size=65536\nchat_log_flush_interval_secs=30");
+        assert_eq!(
+            config.chat_log,
+            ChatLogOptions {
+                is_enabled: true,
+                directory: "irc\\logs".into(),
+                max_file_size: 65536,
+                flush_interval_ms: 30_000,
+            },
+        );
+    }
+
+    #[test]
+    fn test_parse_scrollback() {
+        let config = parse_with("");
+        assert_eq!(config.scrollback, ScrollbackOptions::default());
+
+        let config = parse_with("scrollback_persistence=false\nscrollback_lines=500\nscrollback_save_interval_secs=10");
+        assert_eq!(
+            config.scrollback,
+            ScrollbackOptions {
+                is_enabled: false,
+                line_count: 500,
+                save_interval_ms: 10_000,
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_zero_refill_interval() {
+        parse_with("send_refill_interval_ms=0");
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_parse_bad_octet() {
+        Config::parse("server_ip_address=109.74.200.930\nserver_port=6667\nnickname=a\nreal_name=b");
+    }
+}
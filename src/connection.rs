@@ -1,20 +1,22 @@
 use alloc::boxed::Box;
-use alloc::rc::Rc;
+use alloc::collections::VecDeque;
+use alloc::rc::{Rc, Weak};
+use alloc::format;
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
-use core::cell::RefCell;
+use core::cell::{Cell, RefCell};
 use core::fmt::{Debug, Formatter};
 use core::mem::transmute;
-use core::str;
 use log::info;
 use spin::mutex::SpinMutex;
 use uefi::prelude::BootServices;
-use uefi::{Handle, StatusExt};
+use uefi::{Event, Handle, Status, StatusExt};
 use uefi::table::boot::{EventType, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol, TimerTrigger};
 use uefi_services::println;
 use crate::event::ManagedEvent;
-use crate::ipv4::IPv4Address;
-use crate::tcpv4::{TCPv4ClientConnectionModeParams, TCPv4ConnectionMode, TCPv4IoToken, TCPv4Protocol, TCPv4ReceiveData, TCPv4ReceiveDataHandle, TCPv4ServiceBindingProtocol};
+use crate::tcpv4::{RxBufferPool, TCPv4ClientConnectionModeParams, TCPv4ConnectionLifecycleManager, TCPv4ConnectionMode, TCPv4ConnectionState, TCPv4IoToken, TCPv4Protocol, TCPv4ReceiveDataHandle, TCPv4ServiceBindingProtocol, TCPv4TransmitData, TCPv4TransmitDataHandle};
+use crate::transport::{ConnectionState, NetworkInfo, SendError, Transport, TransportError};
 
 pub fn get_tcp_service_binding_protocol(bs: &BootServices) -> ScopedProtocol<TCPv4ServiceBindingProtocol> {
     let tcp_service_binding_handle = bs.get_handle_for_protocol::<TCPv4ServiceBindingProtocol>().unwrap();
@@ -60,103 +62,473 @@ pub fn get_tcp_protocol<'a>(
 }
 
 
+/// Translate the status of a failed TCP operation into something the client can act on
+fn transport_error_from_status(status: Status) -> TransportError {
+    match status {
+        Status::CONNECTION_FIN => TransportError::ConnectionFin,
+        Status::CONNECTION_RESET | Status::CONNECTION_REFUSED => TransportError::ConnectionReset,
+        Status::TIMEOUT => TransportError::Timeout,
+        Status::NETWORK_UNREACHABLE |
+        Status::HOST_UNREACHABLE |
+        Status::PROTOCOL_UNREACHABLE |
+        Status::PORT_UNREACHABLE |
+        Status::ICMP_ERROR => TransportError::NetworkUnreachable,
+        _ => TransportError::Other(format!("TCP error: {status:?}")),
+    }
+}
+
+/// How many transmits we'll hand to the stack at once. Further sends wait in our own queue.
+const MAX_TRANSMITS_IN_FLIGHT: usize = 4;
+/// Once this much data is waiting to go out, further sends are refused until some of it has been sent
+const MAX_QUEUED_TX_BYTES: usize = 64 * 1024;
+
+/// A transmit that's been handed to the TCP stack and hasn't completed yet
+struct PendingTransmit<'a> {
+    id: u64,
+    len: usize,
+    // Declared first so that it's dropped before the event and data that it refers to
+    io_token: Box<TCPv4IoToken<'a>>,
+    tx_data_handle: Box<TCPv4TransmitDataHandle>,
+    event: Box<ManagedEvent<'a>>,
+}
+
+/// A receive that's been handed to the TCP stack and hasn't completed yet
+struct PendingReceive<'a> {
+    id: u64,
+    // Declared first so that it's dropped before the event and data that it refers to
+    io_token: Box<TCPv4IoToken<'a>>,
+    rx_data_handle: Box<TCPv4ReceiveDataHandle<'a>>,
+    event: Box<ManagedEvent<'a>>,
+}
+
 pub struct TcpConnection<'a> {
     boot_services: &'static BootServices,
+    weak_self: Weak<TcpConnection<'a>>,
     tcp: SpinMutex<RefCell<ScopedProtocol<'a, TCPv4Protocol>>>,
-    active_rx: RefCell<Option<(Box<ManagedEvent<'a>>, Box<TCPv4ReceiveDataHandle<'a>>, &'a TCPv4ReceiveData, Box<TCPv4IoToken<'a>>)>>,
+    /// Receives queued with the stack, in the order they were issued. There's one per buffer lent out of rx_buffers.
+    pending_rx: RefCell<Vec<PendingReceive<'a>>>,
+    /// Receives whose cancellation completed them while close() was still going through pending_rx.
+    /// close() tidies up after them once it's done.
+    receives_completed_during_close: RefCell<Vec<u64>>,
+    /// Events of completed receives and transmits. They can't be freed from within their own notify function,
+    /// so they're parked here until the next poll.
+    retired_events: RefCell<Vec<Box<ManagedEvent<'a>>>>,
+    rx_buffers: RefCell<RxBufferPool>,
+    next_rx_id: Cell<u64>,
+    /// Data accepted by send() that hasn't been handed to the stack yet
+    tx_queue: RefCell<VecDeque<Vec<u8>>>,
+    in_flight_tx: RefCell<Vec<PendingTransmit<'a>>>,
+    lifecycle: RefCell<TCPv4ConnectionLifecycleManager>,
+    next_tx_id: Cell<u64>,
     pub recv_buffer: SpinMutex<RefCell<Vec<u8>>>,
+    is_closed: Cell<bool>,
+    /// Set when the stack reports that the connection has failed.
+    /// Once set, we stop issuing receives, as they'd only fail again.
+    failure: RefCell<Option<TransportError>>,
+    /// Whether we configured the station address ourselves, rather than getting it from DHCP
+    has_static_ip: bool,
+    /// Signalled whenever a receive completes, so that the main loop can sleep until there's data
+    receive_signal: RefCell<Option<Event>>,
 }
 
 impl<'a> TcpConnection<'a> {
     pub fn new(
         boot_services: &'static BootServices,
         mut tcp: ScopedProtocol<'a, TCPv4Protocol>,
-        remote_ip: IPv4Address,
-        remote_port: u16,
-    ) -> Rc<Self> {
+        connection_params: TCPv4ClientConnectionModeParams,
+        rx_buffers: RxBufferPool,
+    ) -> uefi::Result<Rc<Self>, String> {
+        let has_static_ip = connection_params.static_ip().is_some();
         tcp.configure(
             boot_services,
-            TCPv4ConnectionMode::Client(
-                TCPv4ClientConnectionModeParams::new(remote_ip, remote_port),
-            )
-        ).expect("Failed to configure the TCP connection");
-        tcp.connect(boot_services);
+            TCPv4ConnectionMode::Client(connection_params),
+        )?;
+        tcp.connect(boot_services).map_err(|e| {
+            uefi::Error::new(e.status(), "Failed to connect".to_string())
+        })?;
 
-        let _self = Rc::new(
+        let _self = Rc::new_cyclic(|weak_self|
             Self {
                 boot_services,
+                weak_self: Weak::clone(weak_self),
                 tcp: SpinMutex::new(RefCell::new(tcp)),
-                active_rx: RefCell::new(None),
+                pending_rx: RefCell::new(vec![]),
+                receives_completed_during_close: RefCell::new(vec![]),
+                retired_events: RefCell::new(vec![]),
+                rx_buffers: RefCell::new(rx_buffers),
+                next_rx_id: Cell::new(0),
+                tx_queue: RefCell::new(VecDeque::new()),
+                in_flight_tx: RefCell::new(vec![]),
+                lifecycle: RefCell::new(TCPv4ConnectionLifecycleManager::new()),
+                next_tx_id: Cell::new(0),
                 recv_buffer: SpinMutex::new(RefCell::new(vec![])),
+                is_closed: Cell::new(false),
+                failure: RefCell::new(None),
+                has_static_ip,
+                receive_signal: RefCell::new(None),
             }
         );
-        _self
+        Ok(_self)
+    }
+
+    /// Signal the event whenever a receive completes, whether or not it succeeded.
+    /// The event mustn't be of the NOTIFY_SIGNAL type, so that it can be waited on.
+    pub fn signal_on_receive(&self, event: Event) {
+        *self.receive_signal.borrow_mut() = Some(event);
     }
 
+    /// Queue up a receive for every free RX buffer, so the stack always has somewhere to put incoming data
     pub fn set_up_receive_signal_handler(self: Rc<Self>) {
+        // This also runs from receive callbacks, which may fire while the main loop holds the TCP lock.
+        // If so, leave the buffers in the pool, and the next poll will queue them up.
+        let tcp = match self.tcp.try_lock() {
+            None => return,
+            Some(tcp) => tcp,
+        };
+        let tcp = tcp.borrow();
+        while self.failure.borrow().is_none() {
+            let buffer = match self.rx_buffers.borrow_mut().acquire() {
+                None => break,
+                Some(buffer) => buffer,
+            };
+            Rc::clone(&self).set_up_receive(&tcp, buffer);
+        }
+    }
+
+    fn handle_receive_completed(self: Rc<Self>, id: u64) {
+        let pending = match self.pending_rx.try_borrow_mut() {
+            Ok(mut pending_rx) => {
+                let index = pending_rx.iter().position(|p| p.id == id).expect("Completed receive wasn't pending");
+                pending_rx.remove(index)
+            }
+            // close() is cancelling this receive, and will tidy up after it
+            Err(_) => {
+                self.receives_completed_during_close.borrow_mut().push(id);
+                return;
+            }
+        };
+
+        // The receive was cancelled because we're closing the connection. Tidy up after it, but don't re-arm it.
+        if self.is_closed.get() {
+            self.release_receive(pending);
+            return;
+        }
+
+        let status = pending.io_token.completion_token.status();
+        if status.is_error() {
+            // Don't close the connection from here, as the main loop might be holding the TCP lock.
+            // Just record what happened, and let the client notice via state().
+            let error = transport_error_from_status(status);
+            info!("Receive failed with {status:?}, connection is dead: {error}");
+            *self.failure.borrow_mut() = Some(error);
+        }
+        else {
+            // Copy straight out of the fragments into the receive buffer
+            let rx_data = pending.rx_data_handle.get_data_ref();
+            let recv_buffer = self.recv_buffer.lock();
+            let mut recv_buffer = recv_buffer.borrow_mut();
+            for fragment in rx_data.fragments() {
+                recv_buffer.extend_from_slice(fragment);
+            }
+        }
+
+        // Hand the buffer back so the next receive can reuse it
+        self.release_receive(pending);
+
+        // Wake up the main loop
+        if let Some(receive_signal) = self.receive_signal.borrow().as_ref() {
+            if let Err(e) = self.boot_services.signal_event(receive_signal) {
+                info!("Failed to signal receive: {e:?}");
+            }
+        }
+
+        // And set up the next receive operation
+        self.set_up_receive_signal_handler();
+    }
+
+    fn set_up_receive(self: Rc<Self>, tcp: &TCPv4Protocol, buffer: Box<[u8]>) {
+        let id = self.next_rx_id.get();
+        self.next_rx_id.set(id + 1);
+
         // Set up a signal handler to receive data
         let clone_for_cb = Rc::clone(&self);
         let self_ptr = Rc::into_raw(clone_for_cb);
         let raw_self_ptr = self_ptr as *const usize;
         let cb = move |_| {
             let self_rc = unsafe { Rc::from_raw(raw_self_ptr as *const TcpConnection) };
-
-            // Scoped so that we release active_rx before enqueueing the next receive operation
-            {
-                let active_rx = self_rc.active_rx.borrow();
-                let active_rx = active_rx.as_ref().expect("Expected an active receive operation");
-                let (_, rx_data_handle, _, _) = active_rx;
-                // Read the buffered data
-                let received_data = rx_data_handle.get_data_ref().read_buffers();
-                let recv_buffer = &self_rc.recv_buffer;
-                recv_buffer.lock().borrow_mut().extend_from_slice(&received_data);
-                match str::from_utf8(&received_data) {
-                    Ok(v) => {
-                        //info!("RX {v}");
-                    },
-                    Err(_) => {
-                        info!("RX (no decode) {0:?}", received_data);
-                    }
-                };
-            }
-
-            // And set up the next receive operation
-            Rc::clone(&self_rc).set_up_receive_signal_handler();
-
-            // Allow self_rc to be dropped, as we create another clone on the next call to the outer method.
+            self_rc.handle_receive_completed(id);
+            // Allow self_rc to be dropped, as we create another clone on the next call to set_up_receive.
         };
         let rx_event = Box::new(ManagedEvent::new(
             self.boot_services,
             EventType::NOTIFY_SIGNAL,
             cb,
         ));
-        let rx_data_handle = Box::new(TCPv4ReceiveDataHandle::<'a>::new());
+        let rx_data_handle = Box::new(TCPv4ReceiveDataHandle::<'a>::new(vec![buffer]));
         let rx_data = rx_data_handle.get_data_ref();
         let io_token = Box::new(TCPv4IoToken::new(&rx_event, None, Some(rx_data)));
-
-        let io_token_ptr = Box::into_raw(io_token);
+        let io_token_ptr = &*io_token as *const TCPv4IoToken;
 
         // Set this before initiating the receive so that if it's triggered immediately we'll still be ready
-        unsafe {
-            let mut active_rx = self.active_rx.borrow_mut();
-            let reconstructed_io_token = Box::from_raw(io_token_ptr);
-            *active_rx = Some((rx_event, rx_data_handle, rx_data, reconstructed_io_token));
-        }
+        self.pending_rx.borrow_mut().push(
+            PendingReceive {
+                id,
+                io_token,
+                rx_data_handle,
+                event: rx_event,
+            }
+        );
 
-        // PT: Scoped to hold the lock on the TCP connection for as shortly as we can
         let result = unsafe {
-            let tcp = self.tcp.lock();
-            let tcp = tcp.borrow_mut();
             (tcp.receive_fn)(
-                &tcp,
+                tcp,
                 &*io_token_ptr,
             )
         };
-        result.to_result().expect("Failed to set up receive handler");
+        if let Err(e) = result.to_result() {
+            // This can run from a firmware callback, so record the failure rather than panicking,
+            // and let the client notice via state()
+            let error = transport_error_from_status(e.status());
+            info!("Failed to set up receive: {e:?}, connection is dead: {error}");
+            *self.failure.borrow_mut() = Some(error);
+
+            // The callback will never run, so take back everything that was lent to it
+            unsafe { drop(Rc::from_raw(self_ptr)) };
+            let pending = {
+                let mut pending_rx = self.pending_rx.borrow_mut();
+                let index = pending_rx.iter().position(|p| p.id == id).expect("Receive should be pending");
+                pending_rx.remove(index)
+            };
+            self.release_receive(pending);
+        }
+    }
+
+    /// Return a finished receive's buffers to the pool, and park its event until the next poll
+    fn release_receive(&self, pending: PendingReceive<'a>) {
+        let PendingReceive { io_token, rx_data_handle, event, .. } = pending;
+        drop(io_token);
+        let mut rx_buffers = self.rx_buffers.borrow_mut();
+        for buffer in rx_data_handle.into_buffers() {
+            rx_buffers.release(buffer);
+        }
+        self.retired_events.borrow_mut().push(event);
+    }
+
+    /// Queue data to be sent, without waiting for it to go out.
+    /// Refused if the connection is dead, or if too much data is already waiting.
+    pub fn transmit(&self, data: &[u8]) -> Result<(), SendError> {
+        if self.state() == ConnectionState::Closed {
+            return Err(SendError::NotConnected);
+        }
+        let queued_bytes = self.queued_tx_bytes();
+        // Always let a send through when nothing is queued, so that oversized sends can't get stuck
+        if queued_bytes != 0 && queued_bytes + data.len() > MAX_QUEUED_TX_BYTES {
+            return Err(SendError::QueueFull);
+        }
+        self.tx_queue.borrow_mut().push_back(data.to_vec());
+        self.pump_transmits();
+        Ok(())
+    }
+
+    /// Bytes that have been accepted by send() but haven't made it out yet
+    fn queued_tx_bytes(&self) -> usize {
+        let queued: usize = self.tx_queue.borrow().iter().map(|data| data.len()).sum();
+        let in_flight: usize = self.in_flight_tx.borrow().iter().map(|tx| tx.len).sum();
+        queued + in_flight
+    }
+
+    fn pump_transmits(&self) {
+        // This also runs from completion callbacks, which may fire while the main loop holds the TCP lock.
+        // If so, leave the queue alone, and the next poll will pick it up.
+        let tcp = match self.tcp.try_lock() {
+            None => return,
+            Some(tcp) => tcp,
+        };
+        self.pump_transmits_locked(&tcp.borrow(), MAX_TRANSMITS_IN_FLIGHT);
+    }
+
+    /// Hand queued data to the stack until max_in_flight transmits are outstanding
+    fn pump_transmits_locked(&self, tcp: &TCPv4Protocol, max_in_flight: usize) {
+        while self.failure.borrow().is_none() && self.lifecycle.borrow().transmits_in_flight() < max_in_flight {
+            let data = match self.tx_queue.borrow_mut().pop_front() {
+                None => break,
+                Some(data) => data,
+            };
+            self.start_transmit(tcp, &data);
+        }
+    }
+
+    fn start_transmit(&self, tcp: &TCPv4Protocol, data: &[u8]) {
+        let id = self.next_tx_id.get();
+        self.next_tx_id.set(id + 1);
+
+        let weak_self = Weak::clone(&self.weak_self);
+        let event = Box::new(ManagedEvent::new(
+            self.boot_services,
+            EventType::NOTIFY_SIGNAL,
+            move |_| {
+                if let Some(self_rc) = weak_self.upgrade() {
+                    self_rc.handle_transmit_completed(id);
+                }
+            },
+        ));
+        let tx_data_handle = Box::new(TCPv4TransmitDataHandle::new(data));
+        // Safety: The handle lives alongside the token in in_flight_tx, until the transmit completes
+        let tx_data: &'a TCPv4TransmitData = unsafe { &*(tx_data_handle.get_data_ref() as *const _) };
+        let io_token = Box::new(TCPv4IoToken::new(&event, Some(tx_data), None));
+        let io_token_ptr = &*io_token as *const TCPv4IoToken;
+
+        // Set this before initiating the transmit so that if it completes immediately we'll still be ready
+        self.lifecycle.borrow_mut().register_started_transmitting(id);
+        self.in_flight_tx.borrow_mut().push(
+            PendingTransmit {
+                id,
+                len: data.len(),
+                io_token,
+                tx_data_handle,
+                event,
+            }
+        );
+
+        if let Err(e) = tcp.start_transmit(unsafe { &*io_token_ptr }) {
+            // The stack refused the transmit outright, so its event will never be signalled
+            let error = transport_error_from_status(e.status());
+            info!("Transmit #{id} of {} bytes was refused with {:?}: {error}", data.len(), e.status());
+            self.lifecycle.borrow_mut().register_transmitting_complete(id);
+            self.in_flight_tx.borrow_mut().retain(|tx| tx.id != id);
+            *self.failure.borrow_mut() = Some(error);
+        }
+    }
+
+    fn handle_transmit_completed(&self, id: u64) {
+        let pending = {
+            let mut in_flight_tx = self.in_flight_tx.borrow_mut();
+            match in_flight_tx.iter().position(|tx| tx.id == id) {
+                None => return,
+                Some(index) => in_flight_tx.remove(index),
+            }
+        };
+        self.lifecycle.borrow_mut().register_transmitting_complete(id);
+        let PendingTransmit { len, io_token, tx_data_handle, event, .. } = pending;
+
+        let status = io_token.completion_token.status();
+        if status.is_error() {
+            let error = transport_error_from_status(status);
+            info!("Transmit #{id} of {len} bytes failed with {status:?}: {error}");
+            let mut failure = self.failure.borrow_mut();
+            if failure.is_none() {
+                *failure = Some(error);
+            }
+        }
+
+        drop(io_token);
+        drop(tx_data_handle);
+        self.retired_events.borrow_mut().push(event);
+
+        // A slot has freed up, so send whatever's next
+        if !self.is_closed.get() {
+            self.pump_transmits();
+        }
+    }
+
+    /// Gracefully shut down the connection.
+    /// Anything still queued is handed to the stack first, so that it goes out before the FIN.
+    /// The pending receives are cancelled, so that their completion doesn't set up another receive.
+    pub fn close(&self) {
+        if self.is_closed.replace(true) {
+            // Already closed
+            return;
+        }
+        let tcp = self.tcp.lock();
+        let tcp = tcp.borrow();
+        self.pump_transmits_locked(&tcp, usize::MAX);
+        for pending in self.pending_rx.borrow().iter() {
+            // The receive may have completed in the meantime, so failing to cancel it isn't a problem
+            let _ = tcp.cancel(Some(&pending.io_token.completion_token));
+        }
+        for id in self.receives_completed_during_close.take() {
+            let pending = {
+                let mut pending_rx = self.pending_rx.borrow_mut();
+                let index = pending_rx.iter().position(|p| p.id == id).expect("Cancelled receive wasn't pending");
+                pending_rx.remove(index)
+            };
+            self.release_receive(pending);
+        }
+        if let Err(e) = tcp.close(self.boot_services, false) {
+            info!("Failed to close the connection gracefully ({e:?}), resetting the TCP stack");
+            tcp.reset_stack();
+        }
+    }
+}
+
+impl Transport for TcpConnection<'_> {
+    fn send(&self, data: &[u8]) -> Result<(), SendError> {
+        self.transmit(data)
+    }
+
+    fn poll_received(&self) -> Vec<u8> {
+        self.retired_events.borrow_mut().clear();
+        // Catch up on any transmits and receives that a completion callback couldn't start
+        if !self.is_closed.get() {
+            self.pump_transmits();
+            if let Some(this) = self.weak_self.upgrade() {
+                this.set_up_receive_signal_handler();
+            }
+        }
+        self.recv_buffer.lock().borrow_mut().drain(..).collect()
+    }
+
+    fn state(&self) -> ConnectionState {
+        if self.is_closed.get() || self.failure.borrow().is_some() {
+            return ConnectionState::Closed;
+        }
+        let tcp_state = self.tcp.lock().borrow().get_tcp_connection_state();
+        match tcp_state {
+            TCPv4ConnectionState::Listen |
+            TCPv4ConnectionState::SynSent |
+            TCPv4ConnectionState::SynReceived => ConnectionState::Connecting,
+            TCPv4ConnectionState::Established => ConnectionState::Connected,
+            TCPv4ConnectionState::FinWait1 |
+            TCPv4ConnectionState::FinWait2 |
+            TCPv4ConnectionState::Closing |
+            TCPv4ConnectionState::CloseWait |
+            TCPv4ConnectionState::LastAck => ConnectionState::Closing,
+            TCPv4ConnectionState::TimeWait |
+            TCPv4ConnectionState::Closed => ConnectionState::Closed,
+        }
+    }
+
+    fn last_error(&self) -> Option<TransportError> {
+        self.failure.borrow().clone()
+    }
+
+    fn network_info(&self) -> Option<NetworkInfo> {
+        let mode_data = self.tcp.lock().borrow().get_ipv4_mode_data();
+        if !mode_data.is_configured() {
+            return None;
+        }
+        Some(
+            NetworkInfo {
+                station_address: mode_data.station_address(),
+                subnet_mask: mode_data.subnet_mask(),
+                gateway_address: mode_data.gateway_address(),
+                routes: mode_data.routes(),
+                is_static: self.has_static_ip,
+            }
+        )
+    }
+
+    fn protocol_state(&self) -> Option<String> {
+        // Once we've closed, the stack may have been torn down, so there's nothing to ask it
+        if self.is_closed.get() {
+            return None;
+        }
+        let tcp_state = self.tcp.lock().borrow().get_tcp_connection_state();
+        Some(format!("TCP {tcp_state:?}"))
     }
 
-    pub fn transmit(&self, data: &[u8]) {
-        self.tcp.lock().borrow_mut().transmit(&self.boot_services, data)
+    fn close(&self) {
+        TcpConnection::close(self)
     }
 }
 
@@ -50,11 +50,18 @@ impl<'a> ManagedEvent<'a> {
     }
 
     pub fn wait_for_events(bs: &BootServices, events: &[&Self]) -> usize {
-        // Safety: The event clone is discarded after being passed to the UEFI function.
+        Self::wait_for_raw_events(bs, &events.iter().map(|e| &e.event).collect::<Vec<&Event>>())
+    }
+
+    /// Like wait_for_events, but also accepts events that are owned elsewhere, such as by a protocol.
+    /// None of the events can be of the NOTIFY_SIGNAL type.
+    /// Returns the index of the event that was signalled.
+    pub fn wait_for_raw_events(bs: &BootServices, events: &[&Event]) -> usize {
+        // Safety: The event clones are discarded after being passed to the UEFI function.
         unsafe {
             bs.wait_for_event(
-                &mut events.iter().map(|e| e.event.unsafe_clone()).collect::<Vec<Event>>()
-            ).expect("Failed to wait for transmit to complete")
+                &mut events.iter().map(|e| e.unsafe_clone()).collect::<Vec<Event>>()
+            ).expect("Failed to wait for events")
         }
     }
 }
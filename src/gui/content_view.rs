@@ -1,6 +1,8 @@
 use alloc::boxed::Box;
 use alloc::rc::Rc;
-use agx_definitions::{Color, LikeLayerSlice, Rect, RectInsets, Size, Point, PixelByteLayout};
+use agx_definitions::{Color, LikeLayerSlice, Rect, RectInsets, Size, Point};
+use crate::gui::WINDOW_PIXEL_LAYOUT;
+use crate::theme::Theme;
 use libgui::bordered::Bordered;
 use libgui::text_view::TextView;
 use agx_definitions::{Drawable, NestedLayerSlice};
@@ -21,16 +23,16 @@ impl ContentView {
     pub fn new<F: Fn(&View, Size) -> Rect + 'static>(
         font: Font,
         font_size: Size,
+        theme: &Theme,
         sizer: F,
     ) -> Rc<Self> {
         let view = TextView::new_with_font(
-            Color::white(),
+            theme.content_background,
             font.clone(),
             font_size,
             RectInsets::new(2, 2, 2, 2),
             sizer,
-            // PT: My emulated UEFI environment uses BGRA
-            PixelByteLayout::BGRA,
+            WINDOW_PIXEL_LAYOUT,
         );
 
         Rc::new(
@@ -40,6 +42,11 @@ impl ContentView {
         )
     }
 
+    /// Lines that have already been drawn keep their colours until they're drawn again
+    pub fn set_theme(&self, theme: &Theme) {
+        self.view.set_background_color(theme.content_background);
+    }
+
     pub fn add_component(self: Rc<Self>, elem: Rc<dyn UIElement>) {
         Rc::clone(&self.view).add_component(elem)
     }
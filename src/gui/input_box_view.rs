@@ -1,6 +1,7 @@
 use alloc::boxed::Box;
 use alloc::rc::Rc;
-use agx_definitions::{LikeLayerSlice, Rect, RectInsets, Size, Point, PixelByteLayout, Color};
+use agx_definitions::{LikeLayerSlice, Rect, RectInsets, Size, Point, Color};
+use crate::gui::WINDOW_PIXEL_LAYOUT;
 use libgui::bordered::Bordered;
 use agx_definitions::{Drawable, NestedLayerSlice};
 use libgui::KeyCode;
@@ -28,8 +29,7 @@ impl InputBoxView {
             font_size,
             RectInsets::new(2, 2, 2, 2),
             sizer,
-            // PT: My UEFI environment uses BGRA
-            PixelByteLayout::BGRA,
+            WINDOW_PIXEL_LAYOUT,
         );
 
         let _self = Rc::new(
@@ -1,7 +1,18 @@
+use agx_definitions::PixelByteLayout;
+
 mod title_view;
 mod content_view;
 mod input_box_view;
+mod overlay_view;
+mod network_status_view;
+mod search_box_view;
 
 pub use title_view::TitleView;
 pub use content_view::ContentView;
 pub use input_box_view::InputBoxView;
+pub use network_status_view::NetworkStatusView;
+pub use search_box_view::SearchBoxView;
+
+/// How the window's framebuffer is laid out. libgui fills shapes in BGRA, so text is rendered to match.
+/// Displays that want something else are handled when the window is copied to the screen
+pub const WINDOW_PIXEL_LAYOUT: PixelByteLayout = PixelByteLayout::BGRA;
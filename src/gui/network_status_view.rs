@@ -0,0 +1,65 @@
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use agx_definitions::{Rect, RectInsets, Size};
+use crate::gui::overlay_view::{OverlayColors, OverlayView};
+use crate::theme::Theme;
+use libgui::bordered::Bordered;
+use agx_definitions::{Drawable, NestedLayerSlice};
+use libgui::ui_elements::UIElement;
+use libgui::view::View;
+use libgui_derive::{Bordered, Drawable, NestedLayerSlice, UIElement};
+use ttf_renderer::Font;
+
+/// Diagnostics panel that sits on top of the content view, showing how the network and connection are doing.
+/// Hidden until toggled on.
+#[derive(Drawable, NestedLayerSlice, UIElement, Bordered)]
+pub struct NetworkStatusView {
+    pub view: Rc<OverlayView>,
+}
+
+impl NetworkStatusView {
+    pub fn new<F: Fn(&View, Size) -> Rect + 'static>(
+        font: Font,
+        font_size: Size,
+        theme: &Theme,
+        sizer: F,
+    ) -> Rc<Self> {
+        let view = OverlayView::new(
+            font,
+            font_size,
+            RectInsets::new(8, 8, 8, 8),
+            Self::colors(theme),
+            sizer,
+        );
+
+        Rc::new(
+            Self {
+                view: Rc::clone(&view),
+            }
+        )
+    }
+
+    fn colors(theme: &Theme) -> OverlayColors {
+        OverlayColors {
+            background: theme.panel_background,
+            label: theme.panel_label,
+            text: theme.panel_text,
+        }
+    }
+
+    /// Takes effect the next time the contents are set
+    pub fn set_theme(&self, theme: &Theme) {
+        self.view.set_colors(Self::colors(theme));
+    }
+
+    /// Replace the panel's contents
+    pub fn set_lines(&self, lines: &[(&str, String)]) {
+        self.view.clear();
+        for (title, value) in lines.iter() {
+            self.view.draw_label(&format!("{title}: "));
+            self.view.draw_text(&format!("{value}\n"));
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use agx_definitions::{Color, Rect, RectInsets, Size};
+use crate::gui::WINDOW_PIXEL_LAYOUT;
+use libgui::bordered::Bordered;
+use libgui::text_view::TextView;
+use agx_definitions::{Drawable, NestedLayerSlice};
+use libgui::ui_elements::UIElement;
+use libgui::view::View;
+use libgui_derive::{Bordered, NestedLayerSlice, UIElement};
+use ttf_renderer::Font;
+
+#[derive(Debug, Copy, Clone)]
+pub struct OverlayColors {
+    pub background: Color,
+    pub label: Color,
+    pub text: Color,
+}
+
+/// Text panel that sits on top of the other views, made up of labels and the text they describe.
+/// Hidden until shown, so that the views underneath show through.
+#[derive(NestedLayerSlice, UIElement, Bordered)]
+pub struct OverlayView {
+    pub view: Rc<TextView>,
+    is_visible: Cell<bool>,
+    colors: Cell<OverlayColors>,
+}
+
+impl OverlayView {
+    pub fn new<F: Fn(&View, Size) -> Rect + 'static>(
+        font: Font,
+        font_size: Size,
+        insets: RectInsets,
+        colors: OverlayColors,
+        sizer: F,
+    ) -> Rc<Self> {
+        let view = TextView::new_with_font(
+            colors.background,
+            font.clone(),
+            font_size,
+            insets,
+            sizer,
+            WINDOW_PIXEL_LAYOUT,
+        );
+
+        Rc::new(
+            Self {
+                view: Rc::clone(&view),
+                is_visible: Cell::new(false),
+                colors: Cell::new(colors),
+            }
+        )
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.is_visible.get()
+    }
+
+    pub fn set_visible(&self, is_visible: bool) {
+        self.is_visible.set(is_visible)
+    }
+
+    /// Label and text colours take effect the next time the contents are set
+    pub fn set_colors(&self, colors: OverlayColors) {
+        self.view.set_background_color(colors.background);
+        self.colors.set(colors);
+    }
+
+    pub fn clear(&self) {
+        self.view.clear();
+    }
+
+    pub fn draw_label(&self, label: &str) {
+        self.view.draw_string(label, self.colors.get().label);
+    }
+
+    pub fn draw_text(&self, text: &str) {
+        self.view.draw_string(text, self.colors.get().text);
+    }
+}
+
+impl Drawable for OverlayView {
+    fn frame(&self) -> Rect {
+        self.view.frame()
+    }
+
+    fn content_frame(&self) -> Rect {
+        self.view.content_frame()
+    }
+
+    fn draw(&self) -> Vec<Rect> {
+        // While hidden, draw nothing so that the views underneath show through
+        if !self.is_visible.get() {
+            return Vec::new();
+        }
+        self.view.draw()
+    }
+}
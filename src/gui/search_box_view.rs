@@ -0,0 +1,65 @@
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use agx_definitions::{Rect, RectInsets, Size};
+use crate::gui::overlay_view::{OverlayColors, OverlayView};
+use crate::theme::Theme;
+use libgui::bordered::Bordered;
+use agx_definitions::{Drawable, NestedLayerSlice};
+use libgui::ui_elements::UIElement;
+use libgui::view::View;
+use libgui_derive::{Bordered, Drawable, NestedLayerSlice, UIElement};
+use ttf_renderer::Font;
+
+/// Small field that sits at the bottom of the content view while searching the scrollback,
+/// showing what's being searched for and which hit is selected.
+/// Hidden until a search is started.
+#[derive(Drawable, NestedLayerSlice, UIElement, Bordered)]
+pub struct SearchBoxView {
+    pub view: Rc<OverlayView>,
+}
+
+impl SearchBoxView {
+    pub fn new<F: Fn(&View, Size) -> Rect + 'static>(
+        font: Font,
+        font_size: Size,
+        theme: &Theme,
+        sizer: F,
+    ) -> Rc<Self> {
+        let view = OverlayView::new(
+            font,
+            font_size,
+            RectInsets::new(6, 6, 6, 6),
+            Self::colors(theme),
+            sizer,
+        );
+
+        Rc::new(
+            Self {
+                view: Rc::clone(&view),
+            }
+        )
+    }
+
+    fn colors(theme: &Theme) -> OverlayColors {
+        OverlayColors {
+            background: theme.search_background,
+            label: theme.search_label,
+            text: theme.search_text,
+        }
+    }
+
+    /// Takes effect the next time the contents are set
+    pub fn set_theme(&self, theme: &Theme) {
+        self.view.set_colors(Self::colors(theme));
+    }
+
+    /// Replace the field's contents
+    pub fn set_query(&self, query: &str, status: &str) {
+        self.view.clear();
+        self.view.draw_label("Search: ");
+        // Underscore stands in for a text cursor
+        self.view.draw_text(&format!("{query}_"));
+        self.view.draw_label(&format!("    {status}"));
+    }
+}
@@ -11,34 +11,68 @@ use libgui_derive::{Bordered, Drawable, NestedLayerSlice, UIElement};
 use alloc::vec::Vec;
 use libgui::label::Label;
 use ttf_renderer::Font;
+use crate::theme::Theme;
 
 #[derive(Drawable, NestedLayerSlice, UIElement, Bordered)]
 pub struct TitleView {
     pub view: Rc<View>,
+    connection_status: Rc<Label>,
+    buffer_list: Rc<Label>,
+    title: Rc<Label>,
+    slogan: Rc<Label>,
 }
 
 impl TitleView {
     pub fn new<F: Fn(&View, Size) -> Rect + 'static>(
         font: Font,
         font_size: Size,
+        theme: &Theme,
         sizer: F,
     ) -> Rc<Self> {
         let view = Rc::new(
             View::new(
-                Color::white(),
+                theme.title_background,
                 sizer,
             )
         );
 
-        let _self = Rc::new(
-            Self {
-                view: Rc::clone(&view),
+        // Shows how the connection to the server is doing
+        let connection_status = Label::new_with_font(
+            "Connecting...",
+            theme.title_status_text,
+            font.clone(),
+            Size::new(20, 20),
+            move |_v, superview_size| {
+                Rect::from_parts(
+                    Point::new(
+                        (superview_size.width as f64 * 0.3) as _,
+                        ((superview_size.height as f64 / 2.0) - (font_size.height as f64 / 2.0)) as _,
+                    ),
+                    Size::new((superview_size.width as f64 * 0.2) as _, superview_size.height),
+                )
+            }
+        );
+
+        // The open buffers, with the active one highlighted
+        let buffer_list = Label::new_with_font(
+            "",
+            theme.title_buffer_list_text,
+            font.clone(),
+            Size::new(20, 20),
+            move |_v, superview_size| {
+                Rect::from_parts(
+                    Point::new(
+                        (superview_size.width as f64 * 0.5) as _,
+                        ((superview_size.height as f64 / 2.0) - (font_size.height as f64 / 2.0)) as _,
+                    ),
+                    Size::new((superview_size.width as f64 * 0.22) as _, superview_size.height),
+                )
             }
         );
 
         let title = Label::new_with_font(
             "UEFIRC",
-            Color::black(),
+            theme.title_text,
             font.clone(),
             Size::new(32, 32),
             move |_v, superview_size| {
@@ -51,11 +85,10 @@ impl TitleView {
                 )
             }
         );
-        Rc::clone(&_self).add_component(Rc::clone(&title) as Rc<dyn UIElement>);
 
         let slogan = Label::new_with_font(
             "No operating system... No limits...",
-            Color::black(),
+            theme.title_text,
             font.clone(),
             Size::new(24, 24),
             move |_v, superview_size| {
@@ -68,11 +101,40 @@ impl TitleView {
                 )
             }
         );
+
+        let _self = Rc::new(
+            Self {
+                view: Rc::clone(&view),
+                connection_status: Rc::clone(&connection_status),
+                buffer_list: Rc::clone(&buffer_list),
+                title: Rc::clone(&title),
+                slogan: Rc::clone(&slogan),
+            }
+        );
+        Rc::clone(&_self).add_component(Rc::clone(&connection_status) as Rc<dyn UIElement>);
+        Rc::clone(&_self).add_component(Rc::clone(&buffer_list) as Rc<dyn UIElement>);
+        Rc::clone(&_self).add_component(Rc::clone(&title) as Rc<dyn UIElement>);
         Rc::clone(&_self).add_component(Rc::clone(&slogan) as Rc<dyn UIElement>);
 
         _self
     }
 
+    pub fn set_theme(&self, theme: &Theme) {
+        self.view.set_background_color(theme.title_background);
+        self.connection_status.set_text_color(theme.title_status_text);
+        self.buffer_list.set_text_color(theme.title_buffer_list_text);
+        self.title.set_text_color(theme.title_text);
+        self.slogan.set_text_color(theme.title_text);
+    }
+
+    pub fn set_connection_status(&self, status: &str) {
+        self.connection_status.set_text(status);
+    }
+
+    pub fn set_buffer_list(&self, buffer_list: &str) {
+        self.buffer_list.set_text(buffer_list);
+    }
+
     pub fn add_component(self: Rc<Self>, elem: Rc<dyn UIElement>) {
         Rc::clone(&self.view).add_component(elem)
     }
@@ -1,3 +1,6 @@
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter};
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[repr(C)]
 // PT: Cannot use the type from uefi-rs because it's always 16 bytes, which messes up alignment in TCPv4AccessPoint
@@ -11,21 +14,70 @@ impl IPv4Address {
     pub fn zero() -> Self {
         Self([0, 0, 0, 0])
     }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0, 0, 0, 0]
+    }
+}
+
+impl Display for IPv4Address {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let [b1, b2, b3, b4] = self.0;
+        write!(f, "{b1}.{b2}.{b3}.{b4}")
+    }
+}
+
+/// Addressing to use instead of asking DHCP, for networks that don't have a DHCP server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StaticIpConfig {
+    pub station_address: IPv4Address,
+    pub subnet_mask: IPv4Address,
+    /// Without a gateway, only hosts on the local subnet are reachable
+    pub gateway_address: Option<IPv4Address>,
 }
 
 #[derive(Debug)]
 #[repr(C)]
-pub struct IPv4ModeData<'a> {
+pub struct IPv4ModeData {
     is_started: bool,
     max_packet_size: u32,
     config_data: IPv4ConfigData,
     is_configured: bool,
-    group_count: bool,
-    group_table: &'a [IPv4Address; 0],
+    group_count: u32,
+    // These tables are owned by the IP driver, and are null when their counts are zero
+    group_table: *const IPv4Address,
     route_count: u32,
-    ip4_route_table: &'a [IPv4RouteTable; 0],
+    ip4_route_table: *const IPv4RouteTable,
     icmp_type_count: u32,
-    icmp_type_list: &'a [IPv4IcmpType; 0],
+    icmp_type_list: *const IPv4IcmpType,
+}
+
+impl IPv4ModeData {
+    pub fn is_configured(&self) -> bool {
+        self.is_configured
+    }
+
+    pub fn station_address(&self) -> IPv4Address {
+        self.config_data.station_address
+    }
+
+    pub fn subnet_mask(&self) -> IPv4Address {
+        self.config_data.subnet_mask
+    }
+
+    pub fn routes(&self) -> Vec<IPv4RouteTable> {
+        if self.ip4_route_table.is_null() {
+            return Vec::new();
+        }
+        // Safety: The driver provides route_count entries
+        let routes = unsafe { core::slice::from_raw_parts(self.ip4_route_table, self.route_count as usize) };
+        routes.to_vec()
+    }
+
+    /// The gateway of the default route, if there is one
+    pub fn gateway_address(&self) -> Option<IPv4Address> {
+        self.routes().iter().find(|route| route.is_default_route()).map(|route| route.gateway_address)
+    }
 }
 
 #[derive(Debug)]
@@ -47,12 +99,45 @@ pub struct IPv4ConfigData {
     transmit_timeout: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct IPv4RouteTable {
-    subnet_address: IPv4Address,
-    subnet_mask: IPv4Address,
-    gateway_address: IPv4Address,
+    pub subnet_address: IPv4Address,
+    pub subnet_mask: IPv4Address,
+    pub gateway_address: IPv4Address,
+}
+
+impl IPv4RouteTable {
+    pub fn new(subnet_address: IPv4Address, subnet_mask: IPv4Address, gateway_address: IPv4Address) -> Self {
+        Self {
+            subnet_address,
+            subnet_mask,
+            gateway_address,
+        }
+    }
+
+    /// Route everything that isn't on a directly-connected subnet via the gateway
+    pub fn default_route(gateway_address: IPv4Address) -> Self {
+        Self::new(IPv4Address::zero(), IPv4Address::zero(), gateway_address)
+    }
+
+    pub fn is_default_route(&self) -> bool {
+        self.subnet_address.is_zero() && self.subnet_mask.is_zero()
+    }
+}
+
+impl Display for IPv4RouteTable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.is_default_route() {
+            true => write!(f, "default")?,
+            false => write!(f, "{}/{}", self.subnet_address, self.subnet_mask)?,
+        };
+        // A zero gateway means the subnet is on the local link
+        match self.gateway_address.is_zero() {
+            true => write!(f, " directly connected"),
+            false => write!(f, " via {}", self.gateway_address),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -61,3 +146,21 @@ pub struct IPv4IcmpType {
     _type: u8,
     code: u8,
 }
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+    use crate::ipv4::{IPv4Address, IPv4RouteTable};
+
+    #[test]
+    fn test_route_display() {
+        let gateway = IPv4Address::new(10, 0, 2, 2);
+        assert_eq!(IPv4RouteTable::default_route(gateway).to_string(), "default via 10.0.2.2");
+        let local_subnet = IPv4RouteTable::new(
+            IPv4Address::new(10, 0, 2, 0),
+            IPv4Address::new(255, 255, 255, 0),
+            IPv4Address::zero(),
+        );
+        assert_eq!(local_subnet.to_string(), "10.0.2.0/255.255.255.0 directly connected");
+    }
+}
@@ -0,0 +1,80 @@
+/// Exponential backoff with jitter, used to space out reconnection attempts.
+/// The jitter keeps a fleet of clients from reconnecting in lockstep after a server restart.
+#[derive(Debug)]
+pub struct ReconnectBackoff {
+    base_delay_ms: u64,
+    max_delay_ms: u64,
+    attempt: u32,
+    rng_state: u64,
+}
+
+impl ReconnectBackoff {
+    pub fn new(base_delay_ms: u64, max_delay_ms: u64, seed: u64) -> Self {
+        Self {
+            base_delay_ms,
+            max_delay_ms,
+            attempt: 0,
+            // xorshift gets stuck at zero
+            rng_state: seed | 1,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng_state = x;
+        x
+    }
+
+    /// The ceiling doubles on each call, up to the maximum delay.
+    /// The returned delay is picked uniformly from the upper half of the ceiling.
+    pub fn next_delay_ms(&mut self) -> u64 {
+        let ceiling = self.base_delay_ms
+            .saturating_mul(1 << self.attempt.min(32))
+            .min(self.max_delay_ms);
+        self.attempt = self.attempt.saturating_add(1);
+        let floor = ceiling / 2;
+        floor + (self.next_random() % (ceiling - floor + 1))
+    }
+
+    /// Called once we've successfully reconnected, so the next outage starts from the base delay
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::irc::backoff::ReconnectBackoff;
+
+    #[test]
+    fn test_delays_grow_within_bounds() {
+        let mut backoff = ReconnectBackoff::new(1_000, 60_000, 1234);
+        let ceilings = [1_000, 2_000, 4_000, 8_000, 16_000, 32_000, 60_000, 60_000];
+        for ceiling in ceilings {
+            let delay = backoff.next_delay_ms();
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "{delay} not within {ceiling}");
+        }
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut backoff = ReconnectBackoff::new(1_000, 60_000, 5678);
+        for _ in 0..10 {
+            backoff.next_delay_ms();
+        }
+        backoff.reset();
+        assert!(backoff.next_delay_ms() <= 1_000);
+    }
+
+    #[test]
+    fn test_many_attempts_dont_overflow() {
+        let mut backoff = ReconnectBackoff::new(1_000, 300_000, 0);
+        for _ in 0..100 {
+            assert!(backoff.next_delay_ms() <= 300_000);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use alloc::string::{String, ToString};
+use core::fmt::{Display, Formatter};
+
+/// The channel prefixes to assume until the server advertises its own via ISUPPORT CHANTYPES
+pub const DEFAULT_CHANTYPES: &str = "#&";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel(pub String);
+
+/// Why a channel name was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidChannelName {
+    Empty,
+    /// The name doesn't start with one of the server's channel prefixes
+    UnknownPrefix(char),
+    /// Spaces, commas and control characters would break up the command the name is sent in
+    IllegalCharacter(char),
+}
+
+impl Display for InvalidChannelName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            InvalidChannelName::Empty => f.write_str("Channel name is empty"),
+            InvalidChannelName::UnknownPrefix(prefix) => {
+                write!(f, "Channel names on this server can't start with '{prefix}'")
+            }
+            InvalidChannelName::IllegalCharacter(ch) => {
+                write!(f, "Channel names can't contain {ch:?}")
+            }
+        }
+    }
+}
+
+impl Channel {
+    /// Validate a channel name, such as one typed by the user.
+    /// `chantypes` is the set of prefixes the server accepts.
+    pub fn parse(name: &str, chantypes: &str) -> Result<Self, InvalidChannelName> {
+        let prefix = name.chars().next().ok_or(InvalidChannelName::Empty)?;
+        if !chantypes.contains(prefix) {
+            return Err(InvalidChannelName::UnknownPrefix(prefix));
+        }
+        // Per RFC 2812, plus the other characters that would terminate the line
+        if let Some(ch) = name.chars().find(|&ch| matches!(ch, ' ' | ',' | '\x07' | '\0' | '\r' | '\n')) {
+            return Err(InvalidChannelName::IllegalCharacter(ch));
+        }
+        Ok(Self(name.to_string()))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+
+    /// Channel names are case-insensitive
+    pub fn matches(&self, other: &Channel) -> bool {
+        self.0.eq_ignore_ascii_case(&other.0)
+    }
+}
+
+impl Display for Channel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::irc::{Channel, InvalidChannelName, DEFAULT_CHANTYPES};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Channel::parse("#uefi", DEFAULT_CHANTYPES).unwrap().name(), "#uefi");
+        assert_eq!(Channel::parse("&local", DEFAULT_CHANTYPES).unwrap().name(), "&local");
+        // Only prefixes that the server advertised are accepted
+        assert_eq!(Channel::parse("+modeless", DEFAULT_CHANTYPES), Err(InvalidChannelName::UnknownPrefix('+')));
+        assert!(Channel::parse("+modeless", "#&!+").is_ok());
+        assert_eq!(Channel::parse("uefi", DEFAULT_CHANTYPES), Err(InvalidChannelName::UnknownPrefix('u')));
+
+        assert_eq!(Channel::parse("", DEFAULT_CHANTYPES), Err(InvalidChannelName::Empty));
+        assert_eq!(Channel::parse("#a,#b", DEFAULT_CHANTYPES), Err(InvalidChannelName::IllegalCharacter(',')));
+        assert_eq!(Channel::parse("#a b", DEFAULT_CHANTYPES), Err(InvalidChannelName::IllegalCharacter(' ')));
+    }
+
+    #[test]
+    fn test_matches_ignores_case() {
+        let channel = Channel::parse("#UEFI", DEFAULT_CHANTYPES).unwrap();
+        assert!(channel.matches(&Channel("#uefi".into())));
+        assert!(!channel.matches(&Channel("#uefirc".into())));
+    }
+}
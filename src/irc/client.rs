@@ -0,0 +1,971 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use log::info;
+use crate::clock::Clock;
+use crate::irc::backoff::ReconnectBackoff;
+use crate::irc::rate_limiter::RateLimiter;
+use crate::irc::{Channel, InvalidChannelName, IrcCommand, IrcMessage, DEFAULT_CHANTYPES};
+use crate::transport::{ConnectionState, NetworkInfo, SendError, Transport, TransportError};
+
+const DEFAULT_QUIT_REASON: &str = "No operating system... No limits...";
+const RECONNECT_BASE_DELAY_MS: u64 = 1_000;
+const RECONNECT_MAX_DELAY_MS: u64 = 5 * 60 * 1_000;
+/// Roughly what irssi does by default, which keeps us clear of most servers' flood limits
+pub const DEFAULT_SEND_BURST: u32 = 5;
+pub const DEFAULT_SEND_REFILL_INTERVAL_MS: u64 = 2_000;
+
+/// IRC lines are limited to 512 bytes, including the CRLF
+const MAX_COMMAND_LEN: usize = 510;
+/// A join that the server hasn't confirmed by now has probably been refused, such as because the channel is
+/// invite-only. After this, the join can be retried.
+const PENDING_JOIN_TIMEOUT_MS: u64 = 30_000;
+
+/// Opens a fresh transport to the server, or returns None if the connection couldn't be established.
+/// Called once to connect initially, and again whenever we need to reconnect.
+pub type Connector<'a> = Box<dyn FnMut() -> Option<Rc<dyn Transport + 'a>> + 'a>;
+
+/// Notable changes in the health of the connection, for the UI to surface
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// The connection dropped, for the given reason if the transport knew why
+    Disconnected { retry_in_ms: u64, reason: Option<TransportError> },
+    ReconnectFailed { retry_in_ms: u64 },
+    Reconnected,
+}
+
+/// Traffic on the current connection, for diagnostics
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub lines_parsed: u64,
+}
+
+/// A channel to join, along with its key if it has one
+#[derive(Debug, Clone, PartialEq)]
+pub struct JoinTarget {
+    pub channel: Channel,
+    pub key: Option<String>,
+}
+
+impl JoinTarget {
+    pub fn new(channel: &Channel, key: Option<&str>) -> Self {
+        Self {
+            channel: channel.clone(),
+            key: key.map(|key| key.to_string()),
+        }
+    }
+}
+
+/// A JOIN that's been sent, but not yet confirmed by the server
+#[derive(Debug, Clone)]
+struct PendingJoin {
+    target: JoinTarget,
+    requested_at_ms: u64,
+}
+
+/// Build a JOIN for several channels at once.
+/// Keys are matched up with channels by position, so the channels with keys need to come first.
+fn join_command(targets: &[&JoinTarget]) -> String {
+    let channels = targets.iter().map(|target| target.channel.name()).collect::<Vec<&str>>().join(",");
+    let keys = targets.iter().filter_map(|target| target.key.as_deref()).collect::<Vec<&str>>().join(",");
+    match keys.is_empty() {
+        true => format!("JOIN {channels}"),
+        false => format!("JOIN {channels} {keys}"),
+    }
+}
+
+pub struct IrcClient<'a> {
+    pub active_connection: Option<Rc<dyn Transport + 'a>>,
+    connector: Connector<'a>,
+    clock: Rc<dyn Clock + 'a>,
+    nickname: String,
+    real_name: String,
+    /// The channels we're in, along with the keys we joined them with, so we can rejoin after reconnecting
+    joined_channels: Vec<JoinTarget>,
+    pending_joins: Vec<PendingJoin>,
+    /// The channel prefixes the server supports
+    chantypes: String,
+    has_quit: bool,
+    backoff: ReconnectBackoff,
+    next_reconnect_at_ms: Option<u64>,
+    last_error: Option<TransportError>,
+    /// Lines waiting to be sent, either because of the rate limit or because the transport's send queue was full
+    outbox: VecDeque<String>,
+    /// Like the outbox, but always drained first. Used for PONGs, so that a paste can't get us ping-timed-out.
+    priority_outbox: VecDeque<String>,
+    rate_limiter: RateLimiter<'a>,
+    /// Reset whenever a new connection is established
+    stats: Cell<ConnectionStats>,
+}
+
+impl<'a> IrcClient<'a> {
+    /// The seed should differ between machines, so that clients that lost their connection at the same time
+    /// don't all retry in lockstep
+    pub fn new(
+        clock: Rc<dyn Clock + 'a>,
+        connector: Connector<'a>,
+        reconnect_jitter_seed: u64,
+    ) -> Self {
+        let rate_limiter = RateLimiter::new(Rc::clone(&clock), DEFAULT_SEND_BURST, DEFAULT_SEND_REFILL_INTERVAL_MS);
+        Self {
+            active_connection: None,
+            connector,
+            clock,
+            nickname: String::new(),
+            real_name: String::new(),
+            joined_channels: vec![],
+            pending_joins: vec![],
+            chantypes: DEFAULT_CHANTYPES.to_string(),
+            has_quit: false,
+            backoff: ReconnectBackoff::new(RECONNECT_BASE_DELAY_MS, RECONNECT_MAX_DELAY_MS, reconnect_jitter_seed),
+            next_reconnect_at_ms: None,
+            last_error: None,
+            outbox: VecDeque::new(),
+            priority_outbox: VecDeque::new(),
+            rate_limiter,
+            stats: Cell::new(ConnectionStats::default()),
+        }
+    }
+
+    /// Allow `burst` lines to be sent back-to-back, and one more every `refill_interval_ms` after that
+    pub fn set_rate_limit(&mut self, burst: u32, refill_interval_ms: u64) {
+        self.rate_limiter = RateLimiter::new(Rc::clone(&self.clock), burst, refill_interval_ms);
+    }
+
+    /// Returns whether the connection was established.
+    /// If it wasn't, a reconnection attempt is scheduled.
+    pub fn connect_and_register(
+        &mut self,
+        nickname: &str,
+        real_name: &str,
+    ) -> bool {
+        self.nickname = nickname.to_string();
+        self.real_name = real_name.to_string();
+        if !self.try_connect_and_register() {
+            self.schedule_reconnect();
+            return false;
+        }
+        true
+    }
+
+    fn try_connect_and_register(&mut self) -> bool {
+        info!("Registering with IRC server...");
+        let transport = match (self.connector)() {
+            None => return false,
+            Some(transport) => transport,
+        };
+        self.active_connection = Some(transport);
+        self.last_error = None;
+        // Anything left over from the previous connection would arrive before we've registered
+        self.outbox.clear();
+        self.priority_outbox.clear();
+        // The server's flood accounting starts over with the new connection
+        self.rate_limiter.reset();
+        self.stats.set(ConnectionStats::default());
+        // The new server might support different channel types
+        self.chantypes = DEFAULT_CHANTYPES.to_string();
+        // Joins sent to the old connection won't be answered, but they weren't refused either, so try them again
+        // once we've registered, along with everything we were in
+        for pending in core::mem::take(&mut self.pending_joins) {
+            if !self.is_joined(&pending.target.channel) {
+                self.joined_channels.push(pending.target);
+            }
+        }
+        let nickname = self.nickname.clone();
+        let real_name = self.real_name.clone();
+        // Ask for message timestamps. Registration is held until we end the negotiation,
+        // whereas servers that don't know CAP just reject it and carry on.
+        self.send_line_command("CAP REQ :server-time");
+        self.set_nickname(&nickname);
+        self.set_user(&nickname, &real_name);
+        true
+    }
+
+    fn schedule_reconnect(&mut self) -> u64 {
+        let delay_ms = self.backoff.next_delay_ms();
+        self.next_reconnect_at_ms = Some(self.clock.now_ms() + delay_ms);
+        delay_ms
+    }
+
+    /// Notice when the connection has dropped, and reconnect once the backoff has elapsed.
+    /// Should be called regularly from the event loop.
+    pub fn maintain_connection(&mut self) -> Option<ConnectionEvent> {
+        if self.has_quit {
+            return None;
+        }
+        match self.next_reconnect_at_ms {
+            None => {
+                self.flush_outbox();
+                if self.active_connection.is_some() && self.connection_state() == ConnectionState::Closed {
+                    let conn = self.active_connection.take().unwrap();
+                    let reason = conn.last_error();
+                    info!("Connection lost: {reason:?}");
+                    // Release whatever the transport is still holding on to. This is a no-op if it's already closed.
+                    conn.close();
+                    self.last_error = reason.clone();
+                    let retry_in_ms = self.schedule_reconnect();
+                    return Some(ConnectionEvent::Disconnected { retry_in_ms, reason });
+                }
+                None
+            }
+            Some(reconnect_at_ms) => {
+                if self.clock.now_ms() < reconnect_at_ms {
+                    return None;
+                }
+                self.next_reconnect_at_ms = None;
+                match self.try_connect_and_register() {
+                    true => Some(ConnectionEvent::Reconnected),
+                    false => {
+                        let retry_in_ms = self.schedule_reconnect();
+                        Some(ConnectionEvent::ReconnectFailed { retry_in_ms })
+                    }
+                }
+            }
+        }
+    }
+
+    /// Update our view of the session based on a message from the server
+    pub fn handle_message(&mut self, msg: &IrcMessage) {
+        self.update_stats(|stats| stats.lines_parsed += 1);
+        match &msg.command {
+            IrcCommand::ReplyWelcome(_) => {
+                // Registration succeeded, so we can rejoin whatever we were in before the connection dropped.
+                // The list is rebuilt from the server's confirmations, so that a channel that refuses us this time,
+                // such as because we've been banned, is forgotten. Until then, the joins are pending, and are carried
+                // over if the connection drops again.
+                self.backoff.reset();
+                let rejoin_targets = core::mem::take(&mut self.joined_channels);
+                self.join_channels(&rejoin_targets);
+            }
+            IrcCommand::ReplyISupport(p) => {
+                for entry in p.entries.iter() {
+                    match entry.split_once('=') {
+                        Some(("CHANTYPES", chantypes)) => self.chantypes = chantypes.to_string(),
+                        // An empty value means the server doesn't support channels at all
+                        None if entry == "CHANTYPES" => self.chantypes = String::new(),
+                        // The server is withdrawing what it advertised earlier
+                        None if entry == "-CHANTYPES" => self.chantypes = DEFAULT_CHANTYPES.to_string(),
+                        _ => {}
+                    }
+                }
+            }
+            IrcCommand::Join(p) => {
+                let is_own_join = match &msg.origin {
+                    None => false,
+                    Some(origin) => origin.split('!').next().is_some_and(|nick| nick.eq_ignore_ascii_case(&self.nickname)),
+                };
+                if is_own_join {
+                    // Remember the key we asked to join with, if this is a join we requested
+                    let pending_index = self.pending_joins.iter().position(|pending| pending.target.channel.matches(&p.channel));
+                    let key = pending_index.and_then(|index| self.pending_joins.remove(index).target.key);
+                    if !self.is_joined(&p.channel) {
+                        self.joined_channels.push(JoinTarget { channel: p.channel.clone(), key });
+                    }
+                }
+            }
+            IrcCommand::ErrorCannotJoin(p) => {
+                self.pending_joins.retain(|pending| !pending.target.channel.matches(&p.channel));
+            }
+            // Forget channels we've left, so that we don't rejoin them on reconnect, and so that joining again isn't skipped
+            IrcCommand::Part(p) if p.user.0.eq_ignore_ascii_case(&self.nickname) => {
+                self.forget_channel(&p.channel);
+            }
+            IrcCommand::Kick(p) if p.kicked.to_string().eq_ignore_ascii_case(&self.nickname) => {
+                self.forget_channel(&p.channel);
+            }
+            // Keep track of our own nickname, so that we can recognise messages addressed to us
+            IrcCommand::Nick(p) if p.user.0.eq_ignore_ascii_case(&self.nickname) => {
+                self.nickname = p.nickname.to_string();
+            }
+            IrcCommand::Cap(p) if p.subcommand == "ACK" || p.subcommand == "NAK" => {
+                // We only request one set of capabilities, so whatever the answer, we're done
+                self.send_line_command("CAP END");
+            }
+            IrcCommand::Ping(p) => {
+                self.send_priority_line_command(&format!("PONG :{}", p.server));
+            }
+            IrcCommand::Error(_) => {
+                // The server is about to drop us. Close our side so that we reconnect.
+                if let Some(conn) = &self.active_connection {
+                    conn.close();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn connection_state(&self) -> ConnectionState {
+        match &self.active_connection {
+            None => ConnectionState::Closed,
+            Some(conn) => conn.state(),
+        }
+    }
+
+    pub fn nickname(&self) -> &str {
+        &self.nickname
+    }
+
+    /// Why the most recent connection failed, if it did
+    pub fn last_error(&self) -> Option<TransportError> {
+        match &self.active_connection {
+            None => self.last_error.clone(),
+            Some(conn) => conn.last_error(),
+        }
+    }
+
+    pub fn network_info(&self) -> Option<NetworkInfo> {
+        self.active_connection.as_ref().and_then(|conn| conn.network_info())
+    }
+
+    /// The underlying protocol's state, such as the TCP state machine's, if there's an active connection
+    pub fn protocol_state(&self) -> Option<String> {
+        self.active_connection.as_ref().and_then(|conn| conn.protocol_state())
+    }
+
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.stats.get()
+    }
+
+    fn update_stats<F: FnOnce(&mut ConnectionStats)>(&self, f: F) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+
+    /// Whether we're waiting out the backoff before reconnecting
+    pub fn is_awaiting_reconnect(&self) -> bool {
+        self.next_reconnect_at_ms.is_some()
+    }
+
+    /// Drain any data that's arrived on the active connection since the last poll
+    pub fn poll_received(&self) -> Vec<u8> {
+        match &self.active_connection {
+            None => vec![],
+            Some(conn) => {
+                let data = conn.poll_received();
+                self.update_stats(|stats| stats.bytes_received += data.len() as u64);
+                data
+            }
+        }
+    }
+
+    pub fn send_line_command(&mut self, command: &str) {
+        if self.active_connection.is_none() {
+            info!("Dropping command because we're not connected: {command}");
+            return;
+        }
+        self.outbox.push_back(format!("{command}\r\n"));
+        self.flush_outbox();
+    }
+
+    /// Send a line ahead of anything that's waiting in the outbox
+    fn send_priority_line_command(&mut self, command: &str) {
+        if self.active_connection.is_none() {
+            info!("Dropping command because we're not connected: {command}");
+            return;
+        }
+        self.priority_outbox.push_back(format!("{command}\r\n"));
+        self.flush_outbox();
+    }
+
+    /// Hand queued lines to the transport, priority lines first, for as long as the rate limit and
+    /// the transport allow
+    fn flush_outbox(&mut self) {
+        let conn = match &self.active_connection {
+            None => return,
+            Some(conn) => conn,
+        };
+        loop {
+            let is_priority = !self.priority_outbox.is_empty();
+            let line = match is_priority {
+                true => self.priority_outbox.front(),
+                false => self.outbox.front(),
+            };
+            let line = match line {
+                None => break,
+                Some(line) => line,
+            };
+            // We'll try again on the next call to maintain_connection()
+            if !self.rate_limiter.is_ready() {
+                break;
+            }
+            match conn.send(line.as_bytes()) {
+                Ok(()) => {
+                    self.rate_limiter.consume();
+                    let len = line.len() as u64;
+                    self.update_stats(|stats| stats.bytes_sent += len);
+                    match is_priority {
+                        true => self.priority_outbox.pop_front(),
+                        false => self.outbox.pop_front(),
+                    };
+                }
+                // Back off until the transport has drained. We'll try again on the next call.
+                Err(SendError::QueueFull) => break,
+                // The connection is going away, which maintain_connection() will notice
+                Err(SendError::NotConnected) => break,
+            }
+        }
+    }
+
+    /// The number of lines waiting to be sent
+    pub fn outbox_len(&self) -> usize {
+        self.priority_outbox.len() + self.outbox.len()
+    }
+
+    /// Leave the server for good. We won't try to reconnect after this.
+    pub fn quit(&mut self, reason: Option<&str>) {
+        let reason = reason.unwrap_or(DEFAULT_QUIT_REASON);
+        self.has_quit = true;
+        self.next_reconnect_at_ms = None;
+        // We're closing the connection straight away, so there's no point waiting on the rate limit.
+        // Anything still in the outbox is abandoned.
+        self.outbox.clear();
+        self.priority_outbox.clear();
+        if let Some(conn) = self.active_connection.take() {
+            if let Err(e) = conn.send(format!("QUIT :{reason}\r\n").as_bytes()) {
+                info!("Failed to send QUIT: {e:?}");
+            }
+            conn.close();
+        }
+    }
+
+    pub fn set_nickname(&mut self, nickname: &str) {
+        self.send_line_command(&format!("NICK {nickname}"))
+    }
+
+    pub fn send_message_to_user(&mut self, user: &str, message: &str) {
+        self.send_line_command(&format!("PRIVMSG {user} :{message}"))
+    }
+
+    /// Joins the channel first if we aren't already in it, as otherwise the server may refuse the message
+    pub fn send_message_to_channel(&mut self, channel: &Channel, message: &str) {
+        if !self.is_joined(channel) {
+            self.join_channel(channel, None);
+        }
+        self.send_line_command(&format!("PRIVMSG {channel} :{message}"))
+    }
+
+    /// The channel prefixes the server supports, as advertised in ISUPPORT
+    pub fn chantypes(&self) -> &str {
+        &self.chantypes
+    }
+
+    /// Validate a channel name against what the server supports
+    pub fn parse_channel(&self, name: &str) -> Result<Channel, InvalidChannelName> {
+        Channel::parse(name, &self.chantypes)
+    }
+
+    pub fn is_joined(&self, channel: &Channel) -> bool {
+        self.joined_channels.iter().any(|joined| joined.channel.matches(channel))
+    }
+
+    fn forget_channel(&mut self, channel: &Channel) {
+        self.joined_channels.retain(|joined| !joined.channel.matches(channel));
+        self.pending_joins.retain(|pending| !pending.target.channel.matches(channel));
+    }
+
+    fn is_join_pending(&self, channel: &Channel) -> bool {
+        let now_ms = self.clock.now_ms();
+        self.pending_joins.iter().any(|pending| {
+            pending.target.channel.matches(channel) && now_ms < pending.requested_at_ms + PENDING_JOIN_TIMEOUT_MS
+        })
+    }
+
+    pub fn join_channel(&mut self, channel: &Channel, key: Option<&str>) {
+        self.join_channels(&[JoinTarget::new(channel, key)])
+    }
+
+    /// Join several channels at once.
+    /// Channels that we're already in, or are already waiting to get into, are skipped.
+    pub fn join_channels(&mut self, targets: &[JoinTarget]) {
+        let mut new_targets: Vec<JoinTarget> = vec![];
+        for target in targets.iter() {
+            let is_duplicate = new_targets.iter().any(|new_target| new_target.channel.matches(&target.channel));
+            if is_duplicate || self.is_joined(&target.channel) || self.is_join_pending(&target.channel) {
+                continue;
+            }
+            new_targets.push(target.clone());
+        }
+
+        let now_ms = self.clock.now_ms();
+        // Forget about any joins that timed out, as we're about to retry them
+        self.pending_joins.retain(|pending| !new_targets.iter().any(|target| target.channel.matches(&pending.target.channel)));
+        self.pending_joins.extend(new_targets.iter().map(|target| PendingJoin { target: target.clone(), requested_at_ms: now_ms }));
+        self.send_join(&new_targets);
+    }
+
+    /// Send as few JOINs as possible, while keeping each within the line length limit
+    fn send_join(&mut self, targets: &[JoinTarget]) {
+        let mut sorted_targets: Vec<&JoinTarget> = targets.iter().collect();
+        // Stable, so the channels otherwise stay in the order they were given
+        sorted_targets.sort_by_key(|target| target.key.is_none());
+
+        let mut batch: Vec<&JoinTarget> = vec![];
+        for target in sorted_targets {
+            batch.push(target);
+            if batch.len() > 1 && join_command(&batch).len() > MAX_COMMAND_LEN {
+                let overflow = batch.pop().unwrap();
+                self.send_line_command(&join_command(&batch));
+                batch = vec![overflow];
+            }
+        }
+        if !batch.is_empty() {
+            self.send_line_command(&join_command(&batch));
+        }
+    }
+
+    pub fn set_user(&mut self, nickname: &str, real_name: &str) {
+        self.send_line_command(&format!("USER {nickname} 0 * :{real_name}"))
+    }
+
+    // TODO(PT): Add an 'info bar' on the right that shows available channels/users
+    // The primary cost is drawing, so we can only draw the first N channels
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::boxed::Box;
+    use alloc::format;
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+    use crate::clock::{Clock, FakeClock};
+    use crate::irc::{Channel, ConnectionEvent, IrcClient, JoinTarget, ResponseParser, DEFAULT_CHANTYPES};
+    use crate::transport::{ConnectionState, LoopbackTransport, Transport, TransportError};
+
+    /// Hands out the queued transports in order, and fails to connect once they run out
+    fn client_with_transports(
+        clock: &Rc<FakeClock>,
+        transports: &[Rc<LoopbackTransport>],
+    ) -> IrcClient<'static> {
+        let queued_transports = Rc::new(RefCell::new(transports.to_vec()));
+        IrcClient::new(
+            Rc::clone(clock) as Rc<dyn Clock>,
+            Box::new(move || {
+                let mut queued_transports = queued_transports.borrow_mut();
+                match queued_transports.is_empty() {
+                    true => None,
+                    false => Some(queued_transports.remove(0) as Rc<dyn Transport>),
+                }
+            }),
+            0,
+        )
+    }
+
+    fn connected_client(transport: &Rc<LoopbackTransport>) -> IrcClient<'static> {
+        let mut client = client_with_transports(&Rc::new(FakeClock::new()), &[Rc::clone(transport)]);
+        assert!(client.connect_and_register("phill", "Phillip Tennen"));
+        client
+    }
+
+    fn feed_line(client: &mut IrcClient, line: &str) {
+        let mut parser = ResponseParser::new();
+        parser.ingest(line.as_bytes());
+        let msg = parser.parse_next_line().unwrap();
+        client.handle_message(&msg);
+    }
+
+    #[test]
+    fn test_register() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let _client = connected_client(&transport);
+        assert_eq!(
+            transport.sent_lines(),
+            vec!["CAP REQ :server-time", "NICK phill", "USER phill 0 * :Phillip Tennen"],
+        );
+    }
+
+    #[test]
+    fn test_capability_negotiation_ends() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        transport.clear_sent();
+        feed_line(&mut client, ":irc.example.com CAP * LS :server-time\r\n");
+        assert!(transport.sent_lines().is_empty());
+        feed_line(&mut client, ":irc.example.com CAP * NAK :server-time\r\n");
+        assert_eq!(transport.sent_lines(), vec!["CAP END"]);
+    }
+
+    #[test]
+    fn test_send_message_to_user() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        transport.clear_sent();
+        client.send_message_to_user("somebody", "Hello from UEFI");
+        assert_eq!(transport.sent_lines(), vec!["PRIVMSG somebody :Hello from UEFI"]);
+    }
+
+    #[test]
+    fn test_poll_received() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let client = connected_client(&transport);
+        assert!(client.poll_received().is_empty());
+        transport.push_received(b"PING :irc.example.com\r\n");
+        assert_eq!(client.poll_received(), b"PING :irc.example.com\r\n".to_vec());
+        assert!(client.poll_received().is_empty());
+    }
+
+    #[test]
+    fn test_connection_state() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let client = connected_client(&transport);
+        assert_eq!(client.connection_state(), ConnectionState::Connected);
+        transport.close();
+        assert_eq!(client.connection_state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    fn test_remote_disconnect() {
+        let clock = Rc::new(FakeClock::new());
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = client_with_transports(&clock, &[Rc::clone(&transport)]);
+        client.connect_and_register("phill", "Phillip Tennen");
+
+        transport.fail(TransportError::ConnectionReset);
+        assert_eq!(client.connection_state(), ConnectionState::Closed);
+        match client.maintain_connection() {
+            Some(ConnectionEvent::Disconnected { reason, .. }) => assert_eq!(reason, Some(TransportError::ConnectionReset)),
+            e => panic!("Expected a disconnection, got {e:?}"),
+        }
+        // The reason sticks around for the UI after the transport has been dropped
+        assert_eq!(client.last_error(), Some(TransportError::ConnectionReset));
+        assert!(client.is_awaiting_reconnect());
+        // And we don't keep reporting the same disconnection
+        assert_eq!(client.maintain_connection(), None);
+    }
+
+    #[test]
+    fn test_send_back_pressure() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        transport.clear_sent();
+
+        transport.set_accepting_sends(false);
+        client.send_message_to_user("somebody", "first");
+        client.send_message_to_user("somebody", "second");
+        assert!(transport.sent_lines().is_empty());
+        assert_eq!(client.outbox_len(), 2);
+
+        // Once the transport drains, the held lines go out in order
+        transport.set_accepting_sends(true);
+        assert_eq!(client.maintain_connection(), None);
+        assert_eq!(transport.sent_lines(), vec!["PRIVMSG somebody :first", "PRIVMSG somebody :second"]);
+        assert_eq!(client.outbox_len(), 0);
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let clock = Rc::new(FakeClock::new());
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = client_with_transports(&clock, &[Rc::clone(&transport)]);
+        client.set_rate_limit(4, 1_000);
+        // Registration uses up 3 of the 4 lines in the burst
+        client.connect_and_register("phill", "Phillip Tennen");
+        transport.clear_sent();
+
+        for i in 0..3 {
+            client.send_message_to_user("somebody", &format!("line {i}"));
+        }
+        assert_eq!(transport.sent_lines(), vec!["PRIVMSG somebody :line 0"]);
+
+        // A PING that arrives in the meantime is answered before the rest of the paste
+        feed_line(&mut client, "PING :irc.example.com\r\n");
+        clock.advance(1_000);
+        client.maintain_connection();
+        assert_eq!(transport.sent_lines(), vec!["PRIVMSG somebody :line 0", "PONG :irc.example.com"]);
+
+        clock.advance(2_000);
+        client.maintain_connection();
+        assert_eq!(
+            transport.sent_lines(),
+            vec!["PRIVMSG somebody :line 0", "PONG :irc.example.com", "PRIVMSG somebody :line 1", "PRIVMSG somebody :line 2"],
+        );
+        assert_eq!(client.outbox_len(), 0);
+    }
+
+    #[test]
+    fn test_connection_stats() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let clock = Rc::new(FakeClock::new());
+        let mut client = client_with_transports(&clock, &[Rc::clone(&transport), Rc::new(LoopbackTransport::new())]);
+        assert!(client.connect_and_register("phill", "Phillip Tennen"));
+        // Each line also went out with its CRLF
+        let registration_len = transport.sent_lines().iter().map(|line| line.len() as u64 + 2).sum::<u64>();
+        assert_eq!(client.connection_stats().bytes_sent, registration_len);
+
+        transport.push_received(b":irc.test 001 phill :Welcome\r\n");
+        let received = client.poll_received();
+        assert_eq!(client.connection_stats().bytes_received, received.len() as u64);
+        feed_line(&mut client, core::str::from_utf8(&received).unwrap());
+        assert_eq!(client.connection_stats().lines_parsed, 1);
+
+        // A fresh connection starts counting from zero
+        transport.fail(TransportError::ConnectionReset);
+        client.maintain_connection();
+        clock.advance(10 * 60 * 1_000);
+        assert_eq!(client.maintain_connection(), Some(ConnectionEvent::Reconnected));
+        assert_eq!(client.connection_stats().lines_parsed, 0);
+        assert_eq!(client.connection_stats().bytes_received, 0);
+    }
+
+    #[test]
+    fn test_tracks_own_nickname() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        feed_line(&mut client, ":someone!s@host NICK :someone_else\r\n");
+        assert_eq!(client.nickname(), "phill");
+        feed_line(&mut client, ":phill!p@host NICK :phill_away\r\n");
+        assert_eq!(client.nickname(), "phill_away");
+    }
+
+    fn channel(name: &str) -> Channel {
+        Channel::parse(name, DEFAULT_CHANTYPES).unwrap()
+    }
+
+    #[test]
+    fn test_join_multiple_channels_with_keys() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        transport.clear_sent();
+        client.join_channels(&[
+            JoinTarget::new(&channel("#open"), None),
+            JoinTarget::new(&channel("&secret"), Some("hunter2")),
+            // Duplicates are only joined once
+            JoinTarget::new(&channel("#OPEN"), None),
+        ]);
+        // The keyed channel comes first, so that its key lines up with it
+        assert_eq!(transport.sent_lines(), vec!["JOIN &secret,#open hunter2"]);
+
+        // Asking again while the join is outstanding doesn't send another JOIN
+        client.join_channel(&channel("#open"), None);
+        feed_line(&mut client, ":phill!p@host JOIN #open\r\n");
+        client.join_channel(&channel("#open"), None);
+        assert_eq!(transport.sent_lines().len(), 1);
+        assert!(client.is_joined(&channel("#Open")));
+    }
+
+    #[test]
+    fn test_rejoin_after_part() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        client.join_channel(&channel("#uefi"), None);
+        feed_line(&mut client, ":phill!p@host JOIN #uefi\r\n");
+        // Someone else leaving doesn't affect us
+        feed_line(&mut client, ":someone!s@host PART #uefi :bye\r\n");
+        assert!(client.is_joined(&channel("#uefi")));
+
+        feed_line(&mut client, ":phill!p@host PART #uefi\r\n");
+        assert!(!client.is_joined(&channel("#uefi")));
+        transport.clear_sent();
+        client.join_channel(&channel("#uefi"), None);
+        assert_eq!(transport.sent_lines(), vec!["JOIN #uefi"]);
+        feed_line(&mut client, ":phill!p@host JOIN #uefi\r\n");
+        assert!(client.is_joined(&channel("#uefi")));
+    }
+
+    #[test]
+    fn test_rejoin_after_kick() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        client.join_channel(&channel("#uefi"), None);
+        feed_line(&mut client, ":phill!p@host JOIN #uefi\r\n");
+        feed_line(&mut client, ":op!o@host KICK #uefi someone :spam\r\n");
+        assert!(client.is_joined(&channel("#uefi")));
+
+        feed_line(&mut client, ":op!o@host KICK #uefi Phill :spam\r\n");
+        assert!(!client.is_joined(&channel("#uefi")));
+        transport.clear_sent();
+        client.join_channel(&channel("#uefi"), None);
+        assert_eq!(transport.sent_lines(), vec!["JOIN #uefi"]);
+        feed_line(&mut client, ":phill!p@host JOIN #uefi\r\n");
+        assert!(client.is_joined(&channel("#uefi")));
+    }
+
+    #[test]
+    fn test_unconfirmed_join_can_be_retried() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let clock = Rc::new(FakeClock::new());
+        let mut client = client_with_transports(&clock, &[Rc::clone(&transport)]);
+        client.connect_and_register("phill", "Phillip Tennen");
+        transport.clear_sent();
+        client.set_rate_limit(100, 1);
+        client.join_channel(&channel("#invite-only"), None);
+        client.join_channel(&channel("#invite-only"), None);
+        assert_eq!(transport.sent_lines().len(), 1);
+        clock.advance(60_000);
+        client.join_channel(&channel("#invite-only"), None);
+        assert_eq!(transport.sent_lines().len(), 2);
+    }
+
+    #[test]
+    fn test_long_join_is_split() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        client.set_rate_limit(100, 1);
+        transport.clear_sent();
+        let targets: Vec<JoinTarget> = (0..60).map(|i| JoinTarget::new(&channel(&format!("#channel{i:02}")), None)).collect();
+        client.join_channels(&targets);
+        let sent_lines = transport.sent_lines();
+        assert!(sent_lines.len() > 1);
+        assert!(sent_lines.iter().all(|line| line.len() <= 510));
+        let joined_count: usize = sent_lines.iter().map(|line| line.split(',').count()).sum();
+        assert_eq!(joined_count, 60);
+    }
+
+    #[test]
+    fn test_message_to_channel_joins_first() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        // Leave room in the burst after registration
+        client.set_rate_limit(10, 1_000);
+        transport.clear_sent();
+        client.send_message_to_channel(&channel("#uefi"), "hello");
+        feed_line(&mut client, ":phill!p@host JOIN #uefi\r\n");
+        client.send_message_to_channel(&channel("#uefi"), "again");
+        assert_eq!(transport.sent_lines(), vec!["JOIN #uefi", "PRIVMSG #uefi :hello", "PRIVMSG #uefi :again"]);
+    }
+
+    #[test]
+    fn test_chantypes_from_isupport() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        assert!(client.parse_channel("+modeless").is_err());
+        feed_line(&mut client, ":irc.test 005 phill CHANTYPES=#+ NETWORK=Test :are supported by this server\r\n");
+        assert_eq!(client.chantypes(), "#+");
+        assert!(client.parse_channel("+modeless").is_ok());
+        assert!(client.parse_channel("&local").is_err());
+    }
+
+    #[test]
+    fn test_quit() {
+        let transport = Rc::new(LoopbackTransport::new());
+        let mut client = connected_client(&transport);
+        transport.clear_sent();
+        client.quit(Some("bye"));
+        assert_eq!(transport.sent_lines(), vec!["QUIT :bye"]);
+        assert_eq!(transport.state(), ConnectionState::Closed);
+        // We left deliberately, so we shouldn't try to come back
+        assert_eq!(client.maintain_connection(), None);
+    }
+
+    #[test]
+    fn test_reconnect_rejoins_channels() {
+        let clock = Rc::new(FakeClock::new());
+        let first_transport = Rc::new(LoopbackTransport::new());
+        let second_transport = Rc::new(LoopbackTransport::new());
+        let mut client = client_with_transports(
+            &clock,
+            &[Rc::clone(&first_transport), Rc::clone(&second_transport)],
+        );
+        client.connect_and_register("phill", "Phillip Tennen");
+        feed_line(&mut client, ":phill!s@localhost JOIN #uefi\r\n");
+        // Somebody else joining shouldn't be tracked as one of our channels
+        feed_line(&mut client, ":other!s@localhost JOIN #elsewhere\r\n");
+
+        // The server drops us
+        feed_line(&mut client, "ERROR :Closing Link: ping timeout\r\n");
+        let retry_in_ms = match client.maintain_connection() {
+            Some(ConnectionEvent::Disconnected { retry_in_ms, reason: None }) => retry_in_ms,
+            e => panic!("Expected a disconnection, got {e:?}"),
+        };
+        // Nothing happens until the backoff elapses
+        assert_eq!(client.maintain_connection(), None);
+        assert!(second_transport.sent_lines().is_empty());
+
+        clock.advance(retry_in_ms);
+        assert_eq!(client.maintain_connection(), Some(ConnectionEvent::Reconnected));
+        feed_line(&mut client, ":irc.example.com 001 phill :Welcome to the IRC Network, phill!s@localhost\r\n");
+        assert_eq!(
+            second_transport.sent_lines(),
+            vec!["CAP REQ :server-time", "NICK phill", "USER phill 0 * :Phillip Tennen", "JOIN #uefi"],
+        );
+    }
+
+    #[test]
+    fn test_refused_rejoin_is_forgotten() {
+        let clock = Rc::new(FakeClock::new());
+        let transports = [Rc::new(LoopbackTransport::new()), Rc::new(LoopbackTransport::new()), Rc::new(LoopbackTransport::new())];
+        let mut client = client_with_transports(&clock, &transports);
+        client.connect_and_register("phill", "Phillip Tennen");
+        feed_line(&mut client, ":phill!s@localhost JOIN #uefi\r\n");
+        feed_line(&mut client, ":phill!s@localhost JOIN #strict\r\n");
+
+        let reconnect = |client: &mut IrcClient| {
+            feed_line(client, "ERROR :Closing Link: ping timeout\r\n");
+            match client.maintain_connection() {
+                Some(ConnectionEvent::Disconnected { retry_in_ms, .. }) => clock.advance(retry_in_ms),
+                e => panic!("Expected a disconnection, got {e:?}"),
+            }
+            assert_eq!(client.maintain_connection(), Some(ConnectionEvent::Reconnected));
+            feed_line(client, ":irc.example.com 001 phill :Welcome\r\n");
+        };
+
+        reconnect(&mut client);
+        assert_eq!(transports[1].sent_lines().last().unwrap(), "JOIN #uefi,#strict");
+        // We've been banned from one of them in the meantime
+        feed_line(&mut client, ":phill!s@localhost JOIN #uefi\r\n");
+        feed_line(&mut client, ":irc.example.com 474 phill #strict :Cannot join channel (+b)\r\n");
+        assert!(client.is_joined(&channel("#uefi")));
+        assert!(!client.is_joined(&channel("#strict")));
+
+        // So it isn't tried again next time
+        reconnect(&mut client);
+        assert_eq!(transports[2].sent_lines().last().unwrap(), "JOIN #uefi");
+    }
+
+    #[test]
+    fn test_disconnect_before_rejoin_confirmed() {
+        let clock = Rc::new(FakeClock::new());
+        let transports = [Rc::new(LoopbackTransport::new()), Rc::new(LoopbackTransport::new()), Rc::new(LoopbackTransport::new())];
+        let mut client = client_with_transports(&clock, &transports);
+        client.connect_and_register("phill", "Phillip Tennen");
+        client.join_channel(&channel("#uefi"), None);
+        feed_line(&mut client, ":phill!s@localhost JOIN #uefi\r\n");
+        // Never confirmed, but not refused either
+        client.join_channel(&channel("#quiet"), None);
+
+        let reconnect = |client: &mut IrcClient| {
+            feed_line(client, "ERROR :Closing Link: ping timeout\r\n");
+            match client.maintain_connection() {
+                Some(ConnectionEvent::Disconnected { retry_in_ms, .. }) => clock.advance(retry_in_ms),
+                e => panic!("Expected a disconnection, got {e:?}"),
+            }
+            assert_eq!(client.maintain_connection(), Some(ConnectionEvent::Reconnected));
+            feed_line(client, ":irc.example.com 001 phill :Welcome\r\n");
+        };
+
+        reconnect(&mut client);
+        assert_eq!(transports[1].sent_lines().last().unwrap(), "JOIN #uefi,#quiet");
+        // The connection drops again before the server confirms the rejoins
+        reconnect(&mut client);
+        assert_eq!(transports[2].sent_lines().last().unwrap(), "JOIN #uefi,#quiet");
+        feed_line(&mut client, ":phill!s@localhost JOIN #uefi\r\n");
+        assert!(client.is_joined(&channel("#uefi")));
+    }
+
+    #[test]
+    fn test_failed_reconnect_backs_off() {
+        let clock = Rc::new(FakeClock::new());
+        let mut client = client_with_transports(&clock, &[]);
+        assert!(!client.connect_and_register("phill", "Phillip Tennen"));
+
+        let mut delays = Vec::new();
+        for _ in 0..4 {
+            clock.advance(10 * 60 * 1_000);
+            match client.maintain_connection() {
+                Some(ConnectionEvent::ReconnectFailed { retry_in_ms }) => delays.push(retry_in_ms),
+                e => panic!("Expected a failed reconnection, got {e:?}"),
+            }
+        }
+        // Each ceiling doubles, so the final delay must exceed the first attempt's ceiling
+        assert!(delays[3] > 2_000);
+    }
+}
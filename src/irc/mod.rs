@@ -1,5 +1,13 @@
 mod tokenizer;
+mod channel;
 mod response_parser;
+mod client;
+mod backoff;
+mod rate_limiter;
+mod user_command;
 
 pub use response_parser::*;
 pub use tokenizer::Tokenizer;
+pub use channel::{Channel, InvalidChannelName, DEFAULT_CHANTYPES};
+pub use client::{ConnectionEvent, ConnectionStats, Connector, IrcClient, JoinTarget, DEFAULT_SEND_BURST, DEFAULT_SEND_REFILL_INTERVAL_MS};
+pub use user_command::UserCommand;
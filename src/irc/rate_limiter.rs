@@ -0,0 +1,118 @@
+use alloc::rc::Rc;
+use core::cmp::min;
+use crate::clock::Clock;
+
+/// Token bucket that paces outgoing lines, so that the server doesn't kill us for flooding.
+/// Up to `burst` lines can go out back-to-back, after which we're limited to one line per `refill_interval_ms`.
+pub struct RateLimiter<'a> {
+    clock: Rc<dyn Clock + 'a>,
+    burst: u32,
+    refill_interval_ms: u64,
+    available: u32,
+    last_refill_ms: u64,
+}
+
+impl<'a> RateLimiter<'a> {
+    pub fn new(clock: Rc<dyn Clock + 'a>, burst: u32, refill_interval_ms: u64) -> Self {
+        assert!(burst > 0, "Rate limiter burst must allow at least one line");
+        assert!(refill_interval_ms > 0, "Rate limiter refill interval must be non-zero");
+        let now_ms = clock.now_ms();
+        Self {
+            clock,
+            burst,
+            refill_interval_ms,
+            available: burst,
+            last_refill_ms: now_ms,
+        }
+    }
+
+    fn refill(&mut self) {
+        let now_ms = self.clock.now_ms();
+        let elapsed_ms = now_ms.saturating_sub(self.last_refill_ms);
+        let earned = elapsed_ms / self.refill_interval_ms;
+        if earned == 0 {
+            return;
+        }
+        self.available = min(self.burst as u64, self.available as u64 + earned) as u32;
+        if self.available == self.burst {
+            // Time spent with a full bucket doesn't count towards the next token
+            self.last_refill_ms = now_ms;
+        }
+        else {
+            // Carry over the partial interval
+            self.last_refill_ms += earned * self.refill_interval_ms;
+        }
+    }
+
+    /// Whether a line can be sent right now
+    pub fn is_ready(&mut self) -> bool {
+        self.refill();
+        self.available > 0
+    }
+
+    /// Record that a line was sent
+    pub fn consume(&mut self) {
+        self.refill();
+        self.available = self.available.checked_sub(1).expect("Sent a line without a token available");
+    }
+
+    /// How long until the next line can be sent
+    pub fn ms_until_ready(&mut self) -> u64 {
+        match self.is_ready() {
+            true => 0,
+            false => (self.last_refill_ms + self.refill_interval_ms).saturating_sub(self.clock.now_ms()),
+        }
+    }
+
+    /// Start over with a full bucket, such as on a fresh connection
+    pub fn reset(&mut self) {
+        self.available = self.burst;
+        self.last_refill_ms = self.clock.now_ms();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::rc::Rc;
+    use crate::clock::{Clock, FakeClock};
+    use crate::irc::rate_limiter::RateLimiter;
+
+    fn drain(limiter: &mut RateLimiter) -> u32 {
+        let mut sent = 0;
+        while limiter.is_ready() {
+            limiter.consume();
+            sent += 1;
+        }
+        sent
+    }
+
+    #[test]
+    fn test_burst_then_refill() {
+        let clock = Rc::new(FakeClock::new());
+        let mut limiter = RateLimiter::new(Rc::clone(&clock) as Rc<dyn Clock>, 3, 1_000);
+        assert_eq!(drain(&mut limiter), 3);
+        assert_eq!(limiter.ms_until_ready(), 1_000);
+
+        clock.advance(999);
+        assert!(!limiter.is_ready());
+        assert_eq!(limiter.ms_until_ready(), 1);
+        clock.advance(1);
+        assert_eq!(drain(&mut limiter), 1);
+
+        // Partial intervals carry over rather than being lost
+        clock.advance(1_500);
+        assert_eq!(drain(&mut limiter), 1);
+        clock.advance(500);
+        assert_eq!(drain(&mut limiter), 1);
+    }
+
+    #[test]
+    fn test_idle_time_is_capped_at_burst() {
+        let clock = Rc::new(FakeClock::new());
+        let mut limiter = RateLimiter::new(Rc::clone(&clock) as Rc<dyn Clock>, 2, 1_000);
+        clock.advance(60_000);
+        assert_eq!(drain(&mut limiter), 2);
+        limiter.reset();
+        assert_eq!(drain(&mut limiter), 2);
+    }
+}
@@ -4,6 +4,8 @@ use alloc::{format, vec};
 use alloc::vec::Vec;
 use core::fmt::{Display, Formatter};
 use crate::irc::Tokenizer;
+use crate::irc::Channel;
+use crate::clock::DateTime;
 
 const IRC_LINE_DELIMITER: &'static str = "\r\n";
 
@@ -25,9 +27,7 @@ impl Display for Nickname {
 #[derive(Debug, Clone, PartialEq)]
 pub struct User(pub String);
 #[derive(Debug, Clone, PartialEq)]
-pub struct Channel(pub String);
-#[derive(Debug, Clone, PartialEq)]
-pub struct UserOrChannel(String);
+pub struct UserOrChannel(pub String);
 
 
 #[derive(Debug, Clone, PartialEq)]
@@ -73,6 +73,24 @@ impl DescriptorAndReasonParams {
     }
 }
 
+/// The server refused to let us into a channel, such as because we're banned or it's invite-only
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorCannotJoinParams {
+    pub nick: Nickname,
+    pub channel: Channel,
+    pub reason: String,
+}
+
+impl ErrorCannotJoinParams {
+    fn new(nick: &Nickname, channel: &Channel, reason: &str) -> Self {
+        Self {
+            nick: nick.clone(),
+            channel: channel.clone(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ErrorUnknownCommandParams {
     pub nick: Nickname,
@@ -238,7 +256,7 @@ impl ReplyGlobalUsersParams {
 #[derive(Debug, Clone, PartialEq)]
 pub struct PrivateMessageParameters {
     pub sender: User,
-    recipient: UserOrChannel,
+    pub recipient: UserOrChannel,
     pub message: String,
 }
 
@@ -256,6 +274,77 @@ impl PrivateMessageParameters {
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct NickParameters {
+    /// The user's previous nickname
+    pub user: User,
+    pub nickname: Nickname,
+}
+
+impl NickParameters {
+    fn new(user: &User, nickname: &Nickname) -> Self {
+        Self {
+            user: user.clone(),
+            nickname: nickname.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PartParameters {
+    /// Who left
+    pub user: User,
+    pub channel: Channel,
+    pub reason: Option<String>,
+}
+
+impl PartParameters {
+    fn new(user: &User, channel: &Channel, reason: Option<&str>) -> Self {
+        Self {
+            user: user.clone(),
+            channel: channel.clone(),
+            reason: reason.map(|s| s.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KickParameters {
+    /// Who did the kicking
+    pub user: User,
+    pub channel: Channel,
+    pub kicked: Nickname,
+    pub reason: Option<String>,
+}
+
+impl KickParameters {
+    fn new(user: &User, channel: &Channel, kicked: &Nickname, reason: Option<&str>) -> Self {
+        Self {
+            user: user.clone(),
+            channel: channel.clone(),
+            kicked: kicked.clone(),
+            reason: reason.map(|s| s.to_string()),
+        }
+    }
+}
+
+/// Capability negotiation, such as the server acknowledging the capabilities we requested
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapParameters {
+    /// Such as ACK or NAK
+    pub subcommand: String,
+    pub capabilities: Vec<String>,
+}
+
+impl CapParameters {
+    fn new(subcommand: &str, capabilities: Vec<String>) -> Self {
+        Self {
+            subcommand: subcommand.to_string(),
+            capabilities,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct NamesParameters {
     pub channel: String,
@@ -352,7 +441,7 @@ impl ModeParams {
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct PingParams {
-    server: String,
+    pub server: String,
 }
 
 impl PingParams {
@@ -425,13 +514,18 @@ pub enum IrcCommandName {
     ReplyMessageOfTheDayEnd,
     ErrorNoSuchNick,
     ErrorUnknownCommand,
+    ErrorCannotJoin,
     Mode,
     Ping,
     Quit,
     Error,
     Notice,
     Join,
+    Part,
+    Kick,
     PrivateMessage,
+    Nick,
+    Cap,
     Names,
     EndOfNames,
     Topic,
@@ -465,13 +559,19 @@ impl From<&str> for IrcCommandName {
             "376" => Self::ReplyMessageOfTheDayEnd,
             "401" => Self::ErrorNoSuchNick,
             "421" => Self::ErrorUnknownCommand,
+            // No such channel, too many channels, and each reason the channel itself can turn us away
+            "403" | "405" | "471" | "473" | "474" | "475" | "476" | "477" => Self::ErrorCannotJoin,
             "MODE" => Self::Mode,
             "PING" => Self::Ping,
             "QUIT" => Self::Quit,
             "ERROR" => Self::Error,
             "NOTICE" => Self::Notice,
             "JOIN" => Self::Join,
+            "PART" => Self::Part,
+            "KICK" => Self::Kick,
             "PRIVMSG" => Self::PrivateMessage,
+            "NICK" => Self::Nick,
+            "CAP" => Self::Cap,
             _ => Self::Unparseable,
         }
     }
@@ -497,13 +597,18 @@ pub enum IrcCommand {
     ReplyMessageOfTheDayEnd(ReplyWithNickAndMessageParams),
     ErrorNoSuchNick(DescriptorAndReasonParams),
     ErrorUnknownCommand(ErrorUnknownCommandParams),
+    ErrorCannotJoin(ErrorCannotJoinParams),
     Mode(ModeParams),
     Ping(PingParams),
     Quit(QuitParams),
     Error(ErrorParams),
     Notice(NoticeParams),
     Join(JoinParameters),
+    Part(PartParameters),
+    Kick(KickParameters),
     PrivateMessage(PrivateMessageParameters),
+    Nick(NickParameters),
+    Cap(CapParameters),
     Unparseable(String),
     Names(NamesParameters),
     EndOfNames(EndOfNamesParameters),
@@ -517,6 +622,8 @@ pub struct IrcMessage {
     pub origin: Option<String>,
     pub command_name: IrcCommandName,
     pub command: IrcCommand,
+    /// When the server says the message was sent, via the IRCv3 `time` tag. In UTC.
+    pub server_time: Option<DateTime>,
 }
 
 impl IrcMessage {
@@ -529,6 +636,7 @@ impl IrcMessage {
             origin,
             command_name,
             command,
+            server_time: None,
         }
     }
 }
@@ -548,10 +656,20 @@ impl ResponseParser {
         self.buffered_data.extend(data)
     }
 
+    fn next_line_delimiter_pos(&self) -> Option<usize> {
+        let irc_newline_seq = IRC_LINE_DELIMITER.as_bytes();
+        self.buffered_data.windows(2).position(|w| w == irc_newline_seq)
+    }
+
+    /// Whether there's a complete line waiting to be parsed
+    pub fn has_buffered_line(&self) -> bool {
+        self.next_line_delimiter_pos().is_some()
+    }
+
     fn read_next_line(&mut self) -> Option<String> {
         // Check whether we've got a line ready to parse
         let irc_newline_seq = IRC_LINE_DELIMITER.as_bytes();
-        let newline_pos = self.buffered_data.windows(2).position(|w| w == irc_newline_seq);
+        let newline_pos = self.next_line_delimiter_pos();
         let newline_start_idx = match newline_pos {
             // No newline ready yet
             None => return None,
@@ -578,11 +696,40 @@ impl ResponseParser {
         tokenizer.read_to_str(IRC_LINE_DELIMITER).expect("Failed to read a message")
     }
 
+    /// The final parameter, if the server sent one
+    fn parse_optional_trailing_message(tokenizer: &mut Tokenizer) -> Option<String> {
+        tokenizer.peek().map(|_| Self::parse_trailing_message(tokenizer))
+    }
+
+    /// A parameter that may be the last on the line
+    fn parse_middle_or_last_word(tokenizer: &mut Tokenizer) -> String {
+        if tokenizer.peek() == Some(':') {
+            tokenizer.match_str(":");
+        }
+        tokenizer.read_to_any(&[" ", IRC_LINE_DELIMITER]).expect("Failed to read word")
+    }
+
+    /// The nickname part of a `nick!user@host` prefix
+    fn parse_origin_nick(origin: &Option<String>) -> String {
+        let origin = origin.as_ref().expect("Expected the message to have an origin");
+        match origin.find('!') {
+            None => origin.to_string(),
+            Some(end_of_nick) => origin[..end_of_nick].to_string(),
+        }
+    }
+
     fn parse_usize(tokenizer: &mut Tokenizer) -> usize {
         let val_str = tokenizer.read_to(' ').expect("Failed to read a word");
         usize::from_str_radix(&val_str, 10).expect("Failed to parse a usize")
     }
 
+    /// Tags are `key=value` pairs separated by semicolons. We're only interested in `time`.
+    fn parse_server_time(tags: &str) -> Option<DateTime> {
+        tags.split(';')
+            .find_map(|tag| tag.strip_prefix("time="))
+            .and_then(DateTime::parse_iso8601)
+    }
+
     pub fn parse_next_line(&mut self) -> Option<IrcMessage> {
         let line = match self.read_next_line() {
             None => return None,
@@ -590,6 +737,15 @@ impl ResponseParser {
         };
 
         let mut tokenizer = Tokenizer::new(&line);
+        // Does this message include IRCv3 tags?
+        let server_time = match tokenizer.peek() == Some('@') {
+            true => {
+                tokenizer.match_str("@");
+                let tags = tokenizer.read_to(' ').expect("Failed to find space after tags?");
+                Self::parse_server_time(&tags)
+            }
+            false => None,
+        };
         // Does this message include a prefix?
         let origin = match tokenizer.peek() == Some(':') {
             true => {
@@ -801,6 +957,15 @@ impl ResponseParser {
                     )
                 )
             }
+            IrcCommandName::ErrorCannotJoin => {
+                IrcCommand::ErrorCannotJoin(
+                    ErrorCannotJoinParams::new(
+                        &Self::parse_nickname(&mut tokenizer),
+                        &Channel(Self::parse_word(&mut tokenizer)),
+                        &Self::parse_trailing_message(&mut tokenizer),
+                    )
+                )
+            }
             IrcCommandName::Mode => {
                 IrcCommand::Mode(
                     ModeParams::new(
@@ -840,6 +1005,19 @@ impl ResponseParser {
                 }
                 IrcCommand::Join(JoinParameters::new(&Channel(channel)))
             }
+            IrcCommandName::Part => {
+                let source = Self::parse_origin_nick(&origin);
+                let channel = Self::parse_middle_or_last_word(&mut tokenizer);
+                let reason = Self::parse_optional_trailing_message(&mut tokenizer);
+                IrcCommand::Part(PartParameters::new(&User(source), &Channel(channel), reason.as_deref()))
+            }
+            IrcCommandName::Kick => {
+                let source = Self::parse_origin_nick(&origin);
+                let channel = tokenizer.read_to(' ').expect("Failed to read channel");
+                let kicked = Nickname::new(&Self::parse_middle_or_last_word(&mut tokenizer));
+                let reason = Self::parse_optional_trailing_message(&mut tokenizer);
+                IrcCommand::Kick(KickParameters::new(&User(source), &Channel(channel), &kicked, reason.as_deref()))
+            }
             IrcCommandName::PrivateMessage => {
                 let source = {
                     let origin = origin.as_ref().unwrap();
@@ -855,6 +1033,28 @@ impl ResponseParser {
                     )
                 )
             },
+            IrcCommandName::Nick => {
+                let source = {
+                    let origin = origin.as_ref().expect("NICK should have an origin");
+                    match origin.find('!') {
+                        None => origin.to_string(),
+                        Some(end_of_nick) => origin[..end_of_nick].to_string(),
+                    }
+                };
+                let nickname = Nickname::new(&Self::parse_trailing_message(&mut tokenizer));
+                IrcCommand::Nick(NickParameters::new(&User(source), &nickname))
+            }
+            IrcCommandName::Cap => {
+                // Either our nickname, or * before we've registered
+                let _target = tokenizer.read_to(' ').expect("Failed to read CAP target");
+                let subcommand = tokenizer.read_to(' ').expect("Failed to read CAP subcommand");
+                let capabilities = Self::parse_trailing_message(&mut tokenizer)
+                    .split(' ')
+                    .filter(|capability| !capability.is_empty())
+                    .map(|capability| capability.to_string())
+                    .collect();
+                IrcCommand::Cap(CapParameters::new(&subcommand, capabilities))
+            }
             IrcCommandName::Names => {
                 let _me = Self::parse_nickname(&mut tokenizer);
                 let _channel_type = tokenizer.read_to(' ').expect("Failed to read channel type");
@@ -909,13 +1109,13 @@ impl ResponseParser {
             _ => IrcCommand::Unparseable(line),
         };
 
-        Some(
-            IrcMessage::new(
-                origin,
-                command_name,
-                command,
-            )
-        )
+        let mut message = IrcMessage::new(
+            origin,
+            command_name,
+            command,
+        );
+        message.server_time = server_time;
+        Some(message)
     }
 }
 
@@ -923,8 +1123,9 @@ impl ResponseParser {
 mod test {
     use alloc::string::ToString;
     use alloc::vec;
-    use crate::irc::{ReplyGlobalUsersParams, ReplyListChannelsParams, ReplyWithNickAndMessageParams, ReplyListOperatorUsersParams, ReplyListUnknownUsersParams, ReplyLocalUsersParams, ResponseParser, ModeParams, PingParams, QuitParams, ErrorParams, DescriptorAndReasonParams, ErrorUnknownCommandParams, PrivateMessageParameters, NamesParameters, EndOfNamesParameters, TopicParameters, TopicLastSetParameters};
-    use crate::irc::response_parser::{Channel, IrcCommand, IrcCommandName, IrcMessage, JoinParameters, Nickname, ReplyISupportParams, ReplyMyInfoParams, User, UserOrChannel};
+    use crate::irc::{ReplyGlobalUsersParams, ReplyListChannelsParams, ReplyWithNickAndMessageParams, ReplyListOperatorUsersParams, ReplyListUnknownUsersParams, ReplyLocalUsersParams, ResponseParser, ModeParams, PingParams, QuitParams, ErrorParams, DescriptorAndReasonParams, ErrorCannotJoinParams, ErrorUnknownCommandParams, PrivateMessageParameters, NamesParameters, EndOfNamesParameters, TopicParameters, TopicLastSetParameters};
+    use crate::clock::DateTime;
+    use crate::irc::response_parser::{CapParameters, Channel, IrcCommand, IrcCommandName, IrcMessage, JoinParameters, KickParameters, NickParameters, Nickname, PartParameters, ReplyISupportParams, ReplyMyInfoParams, User, UserOrChannel};
 
     fn parse_line(line: &str) -> IrcMessage {
         let mut p = ResponseParser::new();
@@ -938,6 +1139,7 @@ mod test {
     fn test_parse_multiple_lines() {
         let mut p = ResponseParser::new();
         p.ingest("JOIN #chan1\r\nJOIN #chan2\r\n".as_bytes());
+        assert!(p.has_buffered_line());
         let msg1 = p.parse_next_line().unwrap();
         assert_eq!(msg1.origin, None);
         assert_eq!(msg1.command_name, IrcCommandName::Join);
@@ -947,7 +1149,11 @@ mod test {
         assert_eq!(msg2.command_name, IrcCommandName::Join);
         assert_eq!(msg2.command, IrcCommand::Join(JoinParameters::new(&Channel("#chan2".to_string()))));
 
+        assert!(!p.has_buffered_line());
         assert!(p.parse_next_line().is_none());
+        // An incomplete line isn't ready yet
+        p.ingest("JOIN #ch".as_bytes());
+        assert!(!p.has_buffered_line());
     }
 
     #[test]
@@ -1334,6 +1540,22 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parse_error_cannot_join() {
+        let msg = parse_line(":copper.libera.chat 474 phill #strict :Cannot join channel (+b) - you are banned\r\n");
+        assert_eq!(msg.command_name, IrcCommandName::ErrorCannotJoin);
+        assert_eq!(
+            msg.command,
+            IrcCommand::ErrorCannotJoin(
+                ErrorCannotJoinParams::new(
+                    &Nickname("phill".to_string()),
+                    &Channel("#strict".to_string()),
+                    "Cannot join channel (+b) - you are banned",
+                )
+            )
+        )
+    }
+
     #[test]
     fn test_parse_error_unknown_command() {
         let msg = parse_line(":zirconium.libera.chat 421 test CMD :Unknown command\r\n");
@@ -1362,6 +1584,51 @@ mod test {
         )
     }
 
+    #[test]
+    fn test_parse_server_time_tag() {
+        let msg = parse_line("@msgid=abc;time=2019-02-28T19:30:01.123Z :phillipt!~phillipt@localhost PRIVMSG #uefi :hello\r\n");
+        assert_eq!(msg.server_time, Some(DateTime::new(2019, 2, 28, 19, 30, 1)));
+        assert_eq!(msg.origin, Some("phillipt!~phillipt@localhost".to_string()));
+        assert_eq!(msg.command_name, IrcCommandName::PrivateMessage);
+
+        // Tags other than the time are ignored
+        let msg = parse_line("@account=phill PING :irc.example.com\r\n");
+        assert_eq!(msg.server_time, None);
+        assert_eq!(msg.command_name, IrcCommandName::Ping);
+    }
+
+    #[test]
+    fn test_parse_cap() {
+        let msg = parse_line(":irc.example.com CAP * ACK :server-time\r\n");
+        assert_eq!(msg.command, IrcCommand::Cap(CapParameters::new("ACK", vec!["server-time".to_string()])));
+        let msg = parse_line(":irc.example.com CAP phill NAK :server-time batch \r\n");
+        assert_eq!(
+            msg.command,
+            IrcCommand::Cap(CapParameters::new("NAK", vec!["server-time".to_string(), "batch".to_string()])),
+        );
+    }
+
+    #[test]
+    fn test_parse_nick() {
+        let msg = parse_line(":phillipt!~phillipt@86.11.226.171 NICK :phillipt_away\r\n");
+        assert_eq!(msg.command_name, IrcCommandName::Nick);
+        assert_eq!(
+            msg.command,
+            IrcCommand::Nick(
+                NickParameters::new(
+                    &User("phillipt".to_string()),
+                    &Nickname("phillipt_away".to_string()),
+                )
+            )
+        );
+        // The colon is optional for a single-word parameter
+        let msg = parse_line(":phillipt NICK phill\r\n");
+        assert_eq!(
+            msg.command,
+            IrcCommand::Nick(NickParameters::new(&User("phillipt".to_string()), &Nickname("phill".to_string())))
+        );
+    }
+
     #[test]
     fn test_names() {
         let msg = parse_line(":coulomb.oftc.net 353 phillip-testing2 = #test phillip-testingz noball FloodServ\r\n");
@@ -1477,4 +1744,55 @@ mod test {
             )
         )
     }
+
+    #[test]
+    fn test_part() {
+        let msg = parse_line(":phillipt!~phillipt@86.11.226.171 PART #zzzz13 :Gone to lunch\r\n");
+        assert_eq!(msg.command_name, IrcCommandName::Part);
+        assert_eq!(
+            msg.command,
+            IrcCommand::Part(
+                PartParameters::new(
+                    &User("phillipt".to_string()),
+                    &Channel("#zzzz13".to_string()),
+                    Some("Gone to lunch"),
+                )
+            )
+        );
+        // The reason is optional
+        let msg = parse_line(":phillipt!~phillipt@86.11.226.171 PART :#zzzz13\r\n");
+        assert_eq!(
+            msg.command,
+            IrcCommand::Part(PartParameters::new(&User("phillipt".to_string()), &Channel("#zzzz13".to_string()), None))
+        );
+    }
+
+    #[test]
+    fn test_kick() {
+        let msg = parse_line(":ChanServ!services@services.oftc.net KICK #zzzz13 phillipt :Flooding\r\n");
+        assert_eq!(msg.command_name, IrcCommandName::Kick);
+        assert_eq!(
+            msg.command,
+            IrcCommand::Kick(
+                KickParameters::new(
+                    &User("ChanServ".to_string()),
+                    &Channel("#zzzz13".to_string()),
+                    &Nickname("phillipt".to_string()),
+                    Some("Flooding"),
+                )
+            )
+        );
+        let msg = parse_line(":ChanServ!services@services.oftc.net KICK #zzzz13 phillipt\r\n");
+        assert_eq!(
+            msg.command,
+            IrcCommand::Kick(
+                KickParameters::new(
+                    &User("ChanServ".to_string()),
+                    &Channel("#zzzz13".to_string()),
+                    &Nickname("phillipt".to_string()),
+                    None,
+                )
+            )
+        );
+    }
 }
@@ -0,0 +1,133 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// An action requested by the user via the input box
+#[derive(Debug, Clone, PartialEq)]
+pub enum UserCommand {
+    /// Disconnect from the server, with an optional reason
+    Quit(Option<String>),
+    /// Open a query with the given user, or switch to it if it's already open
+    Query(String),
+    /// Close the active query
+    Close,
+    /// Join channels, as in `/join #a,#b key1,key2`. Keys are matched up with channels by position.
+    Join {
+        channels: Vec<String>,
+        keys: Vec<String>,
+    },
+    /// List the screen resolutions that can be switched to, or switch to the given one, as in `/resolution 1920x1080`
+    Resolution(Option<String>),
+    /// List the themes, or switch to the given one, as in `/theme dark`
+    Theme(Option<String>),
+    /// Sent to the server verbatim
+    Raw(String),
+    /// Input that isn't a slash command. In a query, it's a message to the other user.
+    /// Otherwise, it's sent to the server verbatim.
+    Text(String),
+}
+
+impl UserCommand {
+    pub fn parse(input: &str) -> Self {
+        let slash_command = match input.strip_prefix('/') {
+            // Not a slash command, so pass it straight through
+            None => return Self::Text(input.to_string()),
+            Some(slash_command) => slash_command,
+        };
+        let (command, args) = match slash_command.split_once(' ') {
+            None => (slash_command, ""),
+            Some((command, args)) => (command, args.trim()),
+        };
+        match command.to_ascii_lowercase().as_str() {
+            "quit" => {
+                let reason = match args.is_empty() {
+                    true => None,
+                    false => Some(args.to_string()),
+                };
+                Self::Quit(reason)
+            }
+            // Without a nickname, this falls through to the server, which will tell the user what went wrong
+            "query" if !args.is_empty() => {
+                let nickname = args.split(' ').next().unwrap();
+                Self::Query(nickname.to_string())
+            }
+            "close" => Self::Close,
+            "join" if !args.is_empty() => {
+                let (channels, keys) = match args.split_once(' ') {
+                    None => (args, ""),
+                    Some((channels, keys)) => (channels, keys.trim()),
+                };
+                let split_list = |list: &str| list.split(',').filter(|s| !s.is_empty()).map(String::from).collect();
+                Self::Join {
+                    channels: split_list(channels),
+                    keys: split_list(keys),
+                }
+            }
+            "resolution" => {
+                let resolution = match args.is_empty() {
+                    true => None,
+                    false => Some(args.to_string()),
+                };
+                Self::Resolution(resolution)
+            }
+            "theme" => {
+                let theme = match args.is_empty() {
+                    true => None,
+                    false => Some(args.to_string()),
+                };
+                Self::Theme(theme)
+            }
+            // Unknown slash commands are passed through as raw IRC commands, like `/quote`
+            _ => Self::Raw(slash_command.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+    use alloc::vec;
+    use crate::irc::UserCommand;
+
+    #[test]
+    fn test_parse_quit() {
+        assert_eq!(UserCommand::parse("/quit"), UserCommand::Quit(None));
+        assert_eq!(UserCommand::parse("/QUIT  see you later"), UserCommand::Quit(Some("see you later".to_string())));
+    }
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(UserCommand::parse("/query alice"), UserCommand::Query("alice".to_string()));
+        assert_eq!(UserCommand::parse("/QUERY  bob extra"), UserCommand::Query("bob".to_string()));
+        assert_eq!(UserCommand::parse("/close"), UserCommand::Close);
+    }
+
+    #[test]
+    fn test_parse_resolution() {
+        assert_eq!(UserCommand::parse("/resolution"), UserCommand::Resolution(None));
+        assert_eq!(UserCommand::parse("/RESOLUTION 1920x1080"), UserCommand::Resolution(Some("1920x1080".to_string())));
+    }
+
+    #[test]
+    fn test_parse_theme() {
+        assert_eq!(UserCommand::parse("/theme"), UserCommand::Theme(None));
+        assert_eq!(UserCommand::parse("/theme dark"), UserCommand::Theme(Some("dark".to_string())));
+    }
+
+    #[test]
+    fn test_parse_join() {
+        assert_eq!(
+            UserCommand::parse("/join #a,&b key1"),
+            UserCommand::Join { channels: vec!["#a".to_string(), "&b".to_string()], keys: vec!["key1".to_string()] },
+        );
+        assert_eq!(
+            UserCommand::parse("/JOIN #uefi"),
+            UserCommand::Join { channels: vec!["#uefi".to_string()], keys: vec![] },
+        );
+    }
+
+    #[test]
+    fn test_parse_raw() {
+        assert_eq!(UserCommand::parse("PRIVMSG #uefi :hi"), UserCommand::Text("PRIVMSG #uefi :hi".to_string()));
+        assert_eq!(UserCommand::parse("/MODE phill +i"), UserCommand::Raw("MODE phill +i".to_string()));
+    }
+}
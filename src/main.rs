@@ -4,23 +4,34 @@
 #![cfg_attr(feature = "run_in_uefi", no_main)]
 #[allow(invalid_reference_casting)]
 
-#[cfg(feature = "run_in_uefi")]
+// The buffer management parts of the TCP stack are also built for host-run tests
+#[cfg(any(feature = "run_in_uefi", test))]
 mod tcpv4;
 #[cfg(feature = "run_in_uefi")]
-mod ipv4;
-#[cfg(feature = "run_in_uefi")]
 mod event;
 #[cfg(feature = "run_in_uefi")]
 mod connection;
 #[cfg(feature = "run_in_uefi")]
 mod ui;
 #[cfg(feature = "run_in_uefi")]
-mod app;
-#[cfg(feature = "run_in_uefi")]
 mod fs;
 
 mod gui;
 mod irc;
+mod transport;
+mod ipv4;
+mod config;
+mod clock;
+mod buffers;
+mod chat_log;
+mod scrollback;
+mod storage;
+mod rendered_line;
+mod search;
+mod resolution;
+mod pixel_format;
+mod theme;
+mod app;
 
 extern crate alloc;
 
@@ -41,11 +52,17 @@ fn main(image_handle: Handle, system_table: SystemTable<Boot>) -> Status {
 
 /* For when running in a hosted environment */
 
-#[cfg(not(feature = "run_in_uefi"))]
+#[cfg(feature = "run_hosted")]
 mod main_hosted;
 
-#[cfg(not(feature = "run_in_uefi"))]
+#[cfg(feature = "run_hosted")]
+fn main() {
+    main_hosted::main().expect("Hosted app exited with an error");
+}
+
+// Neither platform was selected, which is the case when running unit tests
+#[cfg(not(any(feature = "run_in_uefi", feature = "run_hosted")))]
 fn main() {
-    main_hosted::main();
+    panic!("Build with either the run_in_uefi or run_hosted feature enabled");
 }
 
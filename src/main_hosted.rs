@@ -1,33 +1,224 @@
-use agx_definitions::{
-    Color, LikeLayerSlice, NestedLayerSlice, Point, PointF64, Polygon, Rect, Size,
-};
-use ttf_renderer::{parse, render_glyph_onto, Codepoint, Font};
-
-use libgui::text_input_view::TextInputView;
-use libgui::ui_elements::UIElement;
-use libgui::AwmWindow;
+use agx_definitions::{Point, Rect, Size};
+use libgui::{AwmWindow, KeyCode};
 use log::info;
-use std::cell::RefCell;
+use pixels::{Pixels, SurfaceTexture};
+use std::error;
+use std::net::SocketAddr;
+use std::path::Path;
 use std::rc::Rc;
-use std::{error, fs};
+use winit::dpi::{LogicalSize, PhysicalSize};
+use winit::event::{ElementState, Event, KeyEvent, MouseButton, MouseScrollDelta, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::keyboard::{Key, NamedKey};
+use winit::window::{Window, WindowBuilder};
+use crate::app::{character_key, special_key, App, FONT_ITALIC_PATH, FONT_REGULAR_PATH};
+use crate::config::{Config, CONFIG_FILE_PATH};
+use crate::chat_log::ChatLogger;
+use crate::clock::{Clock, HostClock, HostWallClock, WallClock};
+use crate::irc::{Connector, IrcClient};
+use crate::pixel_format::DisplayPixelFormat;
+use crate::resolution::{Resolution, DEFAULT_RESOLUTION, PREFERRED_RESOLUTIONS};
+use crate::scrollback::ScrollbackStore;
+use crate::storage::{FileStorage, StdFileStorage};
+use crate::transport::{StdTcpTransport, Transport};
+
+/// The hosted build reads its resources from the same directory that's mounted as the ESP in QEMU
+const EFI_FILESYSTEM_ROOT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/efi_filesystem");
+
+fn read_efi_file(path: &str) -> Vec<u8> {
+    // Paths are written for UEFI, so translate the separators
+    let host_path = Path::new(EFI_FILESYSTEM_ROOT).join(path.replace('\\', "/"));
+    std::fs::read(&host_path).expect(&format!("Should be able to read file \"{}\"", host_path.display()))
+}
+
+/// Translate to the keys that UEFI's Simple Text Protocol produces, as that's what the app expects
+fn translate_key(key_event: &KeyEvent) -> Option<KeyCode> {
+    match &key_event.logical_key {
+        Key::Named(NamedKey::ArrowUp) => Some(special_key(0x01)),
+        Key::Named(NamedKey::ArrowDown) => Some(special_key(0x02)),
+        Key::Named(NamedKey::ArrowRight) => Some(special_key(0x03)),
+        Key::Named(NamedKey::ArrowLeft) => Some(special_key(0x04)),
+        Key::Named(NamedKey::Home) => Some(special_key(0x05)),
+        Key::Named(NamedKey::End) => Some(special_key(0x06)),
+        Key::Named(NamedKey::PageUp) => Some(special_key(0x09)),
+        Key::Named(NamedKey::PageDown) => Some(special_key(0x0a)),
+        // UEFI represents the enter key as a carriage return rather than newline
+        Key::Named(NamedKey::Enter) => Some(character_key('\r')),
+        Key::Named(NamedKey::Backspace) => Some(character_key('\x08')),
+        Key::Named(NamedKey::F2) => Some(special_key(0x0c)),
+        Key::Named(NamedKey::F4) => Some(special_key(0x0e)),
+        Key::Named(NamedKey::F5) => Some(special_key(0x0f)),
+        Key::Named(NamedKey::F6) => Some(special_key(0x10)),
+        Key::Named(NamedKey::Escape) => Some(special_key(0x17)),
+        _ => {
+            let text = key_event.text.as_ref()?;
+            text.chars().next().map(character_key)
+        }
+    }
+}
+
+/// Resize the host window and the pixels buffer behind it
+fn switch_resolution(host_window: &Window, pixels: &mut Pixels, resolution: Resolution) -> Result<Resolution, String> {
+    let size = PhysicalSize::new(resolution.width as u32, resolution.height as u32);
+    // The host window might not be resizable to exactly what was asked for, but the buffer is scaled to fit
+    let _ = host_window.request_inner_size(size);
+    let host_window_size = host_window.inner_size();
+    pixels.resize_surface(host_window_size.width, host_window_size.height).map_err(|e| format!("{e:?}"))?;
+    pixels.resize_buffer(size.width, size.height).map_err(|e| format!("{e:?}"))?;
+    Ok(resolution)
+}
+
+/// Copy the regions of the window that changed into the pixels frame
+fn render_window_to_pixels(app: &App, pixels: &mut Pixels, damage: &[Rect]) {
+    let layer = app.window.layer.borrow();
+    let framebuffer = layer.framebuffer.borrow();
+    let stride = app.window.frame().width() as usize * 4;
+    let frame = pixels.frame_mut();
+    for rect in damage.iter() {
+        for y in rect.min_y()..rect.max_y() {
+            let start = y as usize * stride + rect.min_x() as usize * 4;
+            let end = y as usize * stride + rect.max_x() as usize * 4;
+            // The window is BGRA, whereas pixels expects RGBA
+            DisplayPixelFormat::Rgb.convert_pixels(&framebuffer[start..end], &mut frame[start..end]);
+        }
+    }
+}
 
 pub fn main() -> Result<(), Box<dyn error::Error>> {
-    let window_size = Size::new(1360, 768);
-    let window = Rc::new(AwmWindow::new("Hosted UEFIRC", window_size));
+    info!("Parsing fonts...");
+    let font_regular = ttf_renderer::parse(&read_efi_file(FONT_REGULAR_PATH));
+    let font_italic = ttf_renderer::parse(&read_efi_file(FONT_ITALIC_PATH));
 
-    let font_regular = ttf_renderer::parse(&std::fs::read("/Users/philliptennen/CLionProjects/uefirc/esp/EFI/Boot/BigCaslon.ttf").expect("Failed to read font file"));
-    let font_arial = ttf_renderer::parse(&std::fs::read("/Users/philliptennen/CLionProjects/uefirc/esp/EFI/Boot/Arial.ttf").expect("Failed to read font file"));
-    /*
-    let main_view = MainView::new(
-        font_regular,
-        font_arial,
-        move |_v, superview_size| {
-            Rect::with_size(superview_size)
+    let config_bytes = read_efi_file(CONFIG_FILE_PATH);
+    let config = Config::parse(&String::from_utf8(config_bytes)?);
+
+    let server_address = SocketAddr::from((config.server_ip.0, config.server_port));
+    let connector: Connector = Box::new(move || {
+        info!("Initializing connection to IRC server...");
+        match StdTcpTransport::connect(server_address, &config.tcp_options) {
+            Ok(connection) => Some(Rc::new(connection) as Rc<dyn Transport>),
+            Err(e) => {
+                info!("Failed to connect: {e:?}");
+                None
+            }
         }
+    });
+    let clock: Rc<dyn Clock> = Rc::new(HostClock::new());
+    let reconnect_jitter_seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_nanos() as u64) ^ std::process::id() as u64;
+    let mut irc_client = IrcClient::new(
+        Rc::clone(&clock),
+        connector,
+        reconnect_jitter_seed,
     );
-    Rc::clone(&window).add_component(Rc::clone(&main_view) as Rc<dyn UIElement>);
-    */
+    irc_client.set_rate_limit(config.send_burst, config.send_refill_interval_ms);
+    irc_client.connect_and_register(&config.nickname, &config.real_name);
+
+    let storage: Rc<dyn FileStorage> = Rc::new(StdFileStorage::new(EFI_FILESYSTEM_ROOT));
+    let wall_clock: Rc<dyn WallClock> = Rc::new(HostWallClock);
+    let chat_logger = config.chat_log.is_enabled.then(|| {
+        ChatLogger::new(
+            Rc::clone(&storage),
+            Rc::clone(&clock),
+            Rc::clone(&wall_clock),
+            config.chat_log.clone(),
+        )
+    });
+    let scrollback_store = config.scrollback.is_enabled.then(|| {
+        ScrollbackStore::new(Rc::clone(&storage), Rc::clone(&clock), config.scrollback.clone())
+    });
+
+    let resolution = config.resolution.unwrap_or(DEFAULT_RESOLUTION);
+    let resolution = Size::new(resolution.width as _, resolution.height as _);
+    let event_loop = EventLoop::new()?;
+    let host_window = WindowBuilder::new()
+        .with_title("UEFIRC")
+        .with_inner_size(LogicalSize::new(resolution.width as f64, resolution.height as f64))
+        .with_resizable(false)
+        .build(&event_loop)?;
+    let mut pixels = {
+        let host_window_size = host_window.inner_size();
+        let surface_texture = SurfaceTexture::new(host_window_size.width, host_window_size.height, &host_window);
+        Pixels::new(resolution.width as _, resolution.height as _, surface_texture)?
+    };
 
-    window.enter_event_loop();
+    let app = App::new(
+        Rc::new(AwmWindow::new("UEFIRC", resolution)),
+        font_regular,
+        font_italic,
+        irc_client,
+        chat_logger,
+        scrollback_store,
+        Some(storage),
+        Rc::clone(&clock),
+        wall_clock,
+        &config.timestamp_format,
+        &config.theme,
+    );
+    // Any size will do in a host window, so offer the usual suspects
+    app.set_available_resolutions(PREFERRED_RESOLUTIONS.to_vec());
+
+    event_loop.set_control_flow(ControlFlow::Poll);
+    event_loop.run(move |event, elwt| {
+        match event {
+            Event::WindowEvent { window_id, event: WindowEvent::CloseRequested } if window_id == host_window.id() => {
+                app.prepare_for_exit();
+                elwt.exit()
+            }
+            Event::WindowEvent { event: WindowEvent::KeyboardInput { event: key_event, .. }, .. } => {
+                if key_event.state == ElementState::Pressed {
+                    if let Some(key_code) = translate_key(&key_event) {
+                        app.handle_key_pressed(key_code);
+                    }
+                }
+            }
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                // Positions outside the window are clamped to the nearest pixel
+                let (x, y) = pixels
+                    .window_pos_to_pixel((position.x as f32, position.y as f32))
+                    .unwrap_or_else(|pos| pixels.clamp_pixel_pos(pos));
+                app.handle_mouse_moved_to(Point::new(x as _, y as _));
+            }
+            Event::WindowEvent { event: WindowEvent::MouseInput { state, button: MouseButton::Left, .. }, .. } => {
+                app.handle_left_click_state(state == ElementState::Pressed);
+            }
+            Event::WindowEvent { event: WindowEvent::MouseWheel { delta, .. }, .. } => {
+                // winit reports scrolling up as positive
+                let notches = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => -y.round() as isize,
+                    // Trackpads report pixels. Count a notch as roughly three lines of text.
+                    MouseScrollDelta::PixelDelta(position) => -(position.y / 60.0).round() as isize,
+                };
+                if notches != 0 {
+                    app.handle_scroll_wheel(notches);
+                }
+            }
+            Event::AboutToWait => {
+                app.step();
+                if let Some(resolution) = app.take_requested_resolution() {
+                    app.handle_resolution_changed(switch_resolution(&host_window, &mut pixels, resolution));
+                }
+                let damage = app.draw();
+                // Nothing changed, so leave the window alone
+                if damage.is_empty() {
+                    return;
+                }
+                render_window_to_pixels(&app, &mut pixels, &damage);
+                if let Err(e) = pixels.render() {
+                    info!("Failed to render: {e:?}");
+                    elwt.exit();
+                }
+            }
+            Event::WindowEvent { event: WindowEvent::RedrawRequested, .. } => {
+                // The window was uncovered or similar, so present what's already been drawn
+                if let Err(e) = pixels.render() {
+                    info!("Failed to render: {e:?}");
+                    elwt.exit();
+                }
+            }
+            _ => {}
+        }
+    })?;
     Ok(())
 }
@@ -0,0 +1,141 @@
+/// Which bits of a pixel hold each colour channel, for displays that describe their pixels with masks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ChannelMasks {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+    /// Bits that belong to the pixel but hold no colour. These still count towards its size.
+    pub reserved: u32,
+}
+
+impl ChannelMasks {
+    pub fn encode(&self, red: u8, green: u8, blue: u8) -> u32 {
+        scale_into_mask(red, self.red) | scale_into_mask(green, self.green) | scale_into_mask(blue, self.blue)
+    }
+
+    /// Enough bytes to hold the highest bit that any of the masks use
+    pub fn bytes_per_pixel(&self) -> usize {
+        let used_bits = u32::BITS - (self.red | self.green | self.blue | self.reserved).leading_zeros();
+        (used_bits as usize).div_ceil(8).max(1)
+    }
+}
+
+/// Scale an 8-bit channel to however many bits the mask has, and move it into place
+fn scale_into_mask(value: u8, mask: u32) -> u32 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max_value = (mask >> shift) as u64;
+    // Round to the nearest representable value, so that full intensity stays at full intensity
+    let scaled = (value as u64 * max_value + 127) / 255;
+    ((scaled as u32) << shift) & mask
+}
+
+/// How the display wants each pixel laid out in memory.
+/// The window is always rendered as BGRA, and converted to this when it's copied to the display.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisplayPixelFormat {
+    /// Red, green, blue, then a reserved byte
+    Rgb,
+    /// Blue, green, red, then a reserved byte. This matches the window.
+    Bgr,
+    /// Each pixel is a little-endian integer, as wide as the masks need, with the channels wherever the masks say
+    Bitmask(ChannelMasks),
+}
+
+impl DisplayPixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            Self::Rgb | Self::Bgr => 4,
+            Self::Bitmask(masks) => masks.bytes_per_pixel(),
+        }
+    }
+
+    /// Convert a run of BGRA pixels from the window into this format.
+    /// The output holds `bytes_per_pixel()` bytes for each pixel.
+    /// The reserved byte is set, so this also produces opaque RGBA.
+    pub fn convert_pixels(&self, bgra: &[u8], out: &mut [u8]) {
+        let bytes_per_pixel = self.bytes_per_pixel();
+        for (src, dst) in bgra.chunks_exact(4).zip(out.chunks_exact_mut(bytes_per_pixel)) {
+            let (blue, green, red) = (src[0], src[1], src[2]);
+            match self {
+                Self::Rgb => dst.copy_from_slice(&[red, green, blue, 0xff]),
+                Self::Bgr => dst.copy_from_slice(&[blue, green, red, 0xff]),
+                Self::Bitmask(masks) => dst.copy_from_slice(&masks.encode(red, green, blue).to_le_bytes()[..bytes_per_pixel]),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pixel_format::{ChannelMasks, DisplayPixelFormat};
+
+    #[test]
+    fn test_convert_pixels() {
+        // One orange pixel and one teal pixel
+        let bgra = [0x10, 0x80, 0xff, 0x00, 0xc0, 0xa0, 0x00, 0x00];
+        let mut out = [0; 8];
+        DisplayPixelFormat::Rgb.convert_pixels(&bgra, &mut out);
+        assert_eq!(out, [0xff, 0x80, 0x10, 0xff, 0x00, 0xa0, 0xc0, 0xff]);
+        DisplayPixelFormat::Bgr.convert_pixels(&bgra, &mut out);
+        assert_eq!(out, [0x10, 0x80, 0xff, 0xff, 0xc0, 0xa0, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn test_bitmask() {
+        // RGB565
+        let masks = ChannelMasks {
+            red: 0xf800,
+            green: 0x07e0,
+            blue: 0x001f,
+            reserved: 0,
+        };
+        assert_eq!(masks.bytes_per_pixel(), 2);
+        assert_eq!(masks.encode(0xff, 0xff, 0xff), 0xffff);
+        assert_eq!(masks.encode(0xff, 0, 0), 0xf800);
+        assert_eq!(masks.encode(0, 0x80, 0), 0x0400);
+        assert_eq!(masks.encode(0, 0, 0), 0);
+
+        // 10 bits per channel, with red in the low bits
+        let masks = ChannelMasks {
+            red: 0x0000_03ff,
+            green: 0x000f_fc00,
+            blue: 0x3ff0_0000,
+            reserved: 0xc000_0000,
+        };
+        assert_eq!(masks.bytes_per_pixel(), 4);
+        let mut out = [0; 4];
+        DisplayPixelFormat::Bitmask(masks).convert_pixels(&[0xff, 0x00, 0xff, 0x00], &mut out);
+        assert_eq!(u32::from_le_bytes(out), 0x3ff0_03ff);
+
+        // The reserved bits count towards the size, even though nothing is written to them
+        let masks = ChannelMasks {
+            red: 0x00_00ff,
+            green: 0x00_ff00,
+            blue: 0xff_0000,
+            reserved: 0xff00_0000,
+        };
+        assert_eq!(masks.bytes_per_pixel(), 4);
+    }
+
+    #[test]
+    fn test_convert_row_to_16_bits() {
+        // RGB555, with the top bit unused
+        let format = DisplayPixelFormat::Bitmask(
+            ChannelMasks {
+                red: 0x7c00,
+                green: 0x03e0,
+                blue: 0x001f,
+                reserved: 0x8000,
+            }
+        );
+        assert_eq!(format.bytes_per_pixel(), 2);
+        // White, red, blue
+        let bgra = [0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0xff, 0x00, 0x00, 0x00];
+        let mut out = [0; 6];
+        format.convert_pixels(&bgra, &mut out);
+        assert_eq!(out, [0xff, 0x7f, 0x00, 0x7c, 0x1f, 0x00]);
+    }
+}
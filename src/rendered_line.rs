@@ -0,0 +1,111 @@
+use alloc::string::{String, ToString};
+use crate::clock::DateTime;
+
+/// What a line represents, which decides how it's drawn
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    ServerNotice,
+    /// Messages addressed to the user, such as the MOTD
+    UserNotice,
+    /// The body of a multi-line user notice
+    UserNoticeDetail,
+    PrivateMessage,
+    /// Something the user sent
+    OwnMessage,
+    Join,
+    Names,
+    /// Informational replies that don't need a response
+    ServerPrompt,
+    Error,
+    Unparseable,
+    /// Marks a boundary in the scrollback, such as a change of day, rather than being a message itself
+    Separator,
+}
+
+impl LineKind {
+    const ALL: [LineKind; 11] = [
+        LineKind::ServerNotice,
+        LineKind::UserNotice,
+        LineKind::UserNoticeDetail,
+        LineKind::PrivateMessage,
+        LineKind::OwnMessage,
+        LineKind::Join,
+        LineKind::Names,
+        LineKind::ServerPrompt,
+        LineKind::Error,
+        LineKind::Unparseable,
+        LineKind::Separator,
+    ];
+
+    /// A stable name, for saving lines to disk
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineKind::ServerNotice => "server_notice",
+            LineKind::UserNotice => "user_notice",
+            LineKind::UserNoticeDetail => "user_notice_detail",
+            LineKind::PrivateMessage => "private_message",
+            LineKind::OwnMessage => "own_message",
+            LineKind::Join => "join",
+            LineKind::Names => "names",
+            LineKind::ServerPrompt => "server_prompt",
+            LineKind::Error => "error",
+            LineKind::Unparseable => "unparseable",
+            LineKind::Separator => "separator",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineStyle {
+    Normal,
+    /// Greyed out, such as for lines restored from the previous session
+    Dimmed,
+    /// Matches the search that's in progress
+    Highlighted,
+}
+
+/// A line of scrollback, retained so that it can be laid out and drawn again whenever the view needs it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedLine {
+    pub kind: LineKind,
+    /// The label drawn before the text, such as who sent the message
+    pub sender: String,
+    pub text: String,
+    /// When the message was sent. Separators have none.
+    pub timestamp: Option<DateTime>,
+    pub style: LineStyle,
+}
+
+impl RenderedLine {
+    pub fn new(kind: LineKind, sender: &str, text: &str, timestamp: Option<DateTime>) -> Self {
+        Self {
+            kind,
+            sender: sender.to_string(),
+            text: text.to_string(),
+            timestamp,
+            style: LineStyle::Normal,
+        }
+    }
+
+    pub fn separator(sender: &str, text: &str) -> Self {
+        Self::new(LineKind::Separator, sender, text, None)
+    }
+
+    pub fn dimmed(mut self) -> Self {
+        self.style = LineStyle::Dimmed;
+        self
+    }
+
+    pub fn highlighted(mut self) -> Self {
+        self.style = LineStyle::Highlighted;
+        self
+    }
+
+    pub fn is_separator(&self) -> bool {
+        self.kind == LineKind::Separator
+    }
+}
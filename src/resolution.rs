@@ -0,0 +1,149 @@
+use core::fmt::{Display, Formatter};
+
+/// A screen resolution, in pixels
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Resolution {
+    pub const fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+        }
+    }
+
+    /// Parse a resolution written as `<width>x<height>`, such as `1920x1080`
+    pub fn parse(s: &str) -> Option<Self> {
+        let (width, height) = s.trim().split_once(['x', 'X'])?;
+        let resolution = Self::new(width.parse().ok()?, height.parse().ok()?);
+        match resolution.width == 0 || resolution.height == 0 {
+            true => None,
+            false => Some(resolution),
+        }
+    }
+
+    fn area(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+impl Display for Resolution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// What the UI was laid out for, and what's used when running hosted
+pub const DEFAULT_RESOLUTION: Resolution = Resolution::new(1360, 768);
+
+/// Used when neither the config nor the display says what to use, best first.
+/// Once past the resolution the UI was designed for, these are common panel sizes, from large to small.
+pub const PREFERRED_RESOLUTIONS: [Resolution; 8] = [
+    DEFAULT_RESOLUTION,
+    Resolution::new(1920, 1080),
+    Resolution::new(1600, 900),
+    Resolution::new(1366, 768),
+    Resolution::new(1280, 800),
+    Resolution::new(1280, 720),
+    Resolution::new(1024, 768),
+    Resolution::new(800, 600),
+];
+
+/// Pick which of the available modes to use. In order of preference:
+/// - The resolution given in the config
+/// - The display's native resolution
+/// - The first of PREFERRED_RESOLUTIONS that's available
+/// - The largest mode available
+///
+/// Returns None if there aren't any modes at all.
+pub fn choose_resolution(
+    available: &[Resolution],
+    configured: Option<Resolution>,
+    native: Option<Resolution>,
+) -> Option<Resolution> {
+    configured.into_iter()
+        .chain(native)
+        .chain(PREFERRED_RESOLUTIONS)
+        .find(|resolution| available.contains(resolution))
+        .or_else(|| available.iter().copied().max_by_key(Resolution::area))
+}
+
+/// The display's native resolution, from the preferred timing in its EDID (the first detailed timing descriptor)
+pub fn native_resolution_from_edid(edid: &[u8]) -> Option<Resolution> {
+    const EDID_HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+    if edid.len() < 128 || edid[..8] != EDID_HEADER {
+        return None;
+    }
+    let descriptor = &edid[54..72];
+    // A zero pixel clock means this is a display descriptor rather than a timing
+    if descriptor[0] == 0 && descriptor[1] == 0 {
+        return None;
+    }
+    // The upper 4 bits of each dimension are packed into a shared byte
+    let width = descriptor[2] as usize | ((descriptor[4] as usize & 0xf0) << 4);
+    let height = descriptor[5] as usize | ((descriptor[7] as usize & 0xf0) << 4);
+    match width == 0 || height == 0 {
+        true => None,
+        false => Some(Resolution::new(width, height)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use crate::resolution::{choose_resolution, native_resolution_from_edid, Resolution};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Resolution::parse("1920x1080"), Some(Resolution::new(1920, 1080)));
+        assert_eq!(Resolution::parse(" 800X600 "), Some(Resolution::new(800, 600)));
+        assert_eq!(Resolution::parse("1920"), None);
+        assert_eq!(Resolution::parse("0x600"), None);
+        assert_eq!(Resolution::parse("axb"), None);
+    }
+
+    #[test]
+    fn test_choose_resolution() {
+        let available = vec![
+            Resolution::new(640, 480),
+            Resolution::new(1024, 768),
+            Resolution::new(1920, 1080),
+            Resolution::new(2560, 1440),
+        ];
+        // The config wins, if the mode exists
+        assert_eq!(
+            choose_resolution(&available, Some(Resolution::new(1024, 768)), Some(Resolution::new(2560, 1440))),
+            Some(Resolution::new(1024, 768)),
+        );
+        // Then the display's native resolution
+        assert_eq!(
+            choose_resolution(&available, Some(Resolution::new(1, 1)), Some(Resolution::new(2560, 1440))),
+            Some(Resolution::new(2560, 1440)),
+        );
+        // Then the preference list
+        assert_eq!(choose_resolution(&available, None, None), Some(Resolution::new(1920, 1080)));
+        // Then whatever's largest
+        let unusual = vec![Resolution::new(1000, 700), Resolution::new(1100, 600)];
+        assert_eq!(choose_resolution(&unusual, None, None), Some(Resolution::new(1000, 700)));
+        assert_eq!(choose_resolution(&[], None, None), None);
+    }
+
+    #[test]
+    fn test_native_resolution_from_edid() {
+        let mut edid = vec![0u8; 128];
+        edid[..8].copy_from_slice(&[0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00]);
+        assert_eq!(native_resolution_from_edid(&edid), None);
+
+        // 1920x1080 at 148.5MHz
+        edid[54..62].copy_from_slice(&[0x02, 0x3a, 0x80, 0x18, 0x71, 0x38, 0x2d, 0x40]);
+        assert_eq!(native_resolution_from_edid(&edid), Some(Resolution::new(1920, 1080)));
+
+        // Bad header
+        edid[0] = 0xff;
+        assert_eq!(native_resolution_from_edid(&edid), None);
+        assert_eq!(native_resolution_from_edid(&edid[..64]), None);
+    }
+}
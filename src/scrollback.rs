@@ -0,0 +1,325 @@
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use log::info;
+use crate::buffers::BufferKind;
+use crate::storage::FileStorage;
+use crate::clock::{Clock, DateTime};
+use crate::rendered_line::{LineKind, RenderedLine};
+
+/// Path of the saved scrollback, relative to the root of the EFI filesystem
+pub const SCROLLBACK_FILE_PATH: &str = "EFI\\Boot\\scrollback.txt";
+
+/// Each save is written here first, and then swapped in
+fn temp_file_path() -> String {
+    format!("{SCROLLBACK_FILE_PATH}.tmp")
+}
+
+/// Identifies the format, so that a file from an incompatible version is ignored rather than misread
+const FILE_HEADER: &str = "uefirc scrollback 3";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrollbackOptions {
+    pub is_enabled: bool,
+    /// How many of the most recent lines are kept for each buffer
+    pub line_count: usize,
+    pub save_interval_ms: u64,
+}
+
+impl Default for ScrollbackOptions {
+    fn default() -> Self {
+        Self {
+            is_enabled: true,
+            line_count: 100,
+            save_interval_ms: 60_000,
+        }
+    }
+}
+
+/// The tail end of a buffer's scrollback, as it's carried over to the next boot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedBuffer {
+    pub kind: BufferKind,
+    pub name: String,
+    pub lines: Vec<RenderedLine>,
+}
+
+/// Fields are tab-separated and records are newline-separated, so those are escaped within fields
+fn escape(field: &str) -> String {
+    let mut escaped = String::with_capacity(field.len());
+    for ch in field.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn unescape(field: &str) -> Result<String, String> {
+    let mut unescaped = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            unescaped.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => unescaped.push('\\'),
+            Some('t') => unescaped.push('\t'),
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            other => return Err(format!("Invalid escape {other:?}")),
+        }
+    }
+    Ok(unescaped)
+}
+
+/// Render the buffers in a compact line-based format:
+///   buffer<TAB>server|query<TAB>name
+///   line<TAB>kind<TAB>timestamp, or empty<TAB>sender<TAB>text
+/// Lines are always reloaded in the normal style, so that isn't saved.
+pub fn serialize(buffers: &[SavedBuffer]) -> String {
+    let mut out = format!("{FILE_HEADER}\n");
+    for buffer in buffers.iter() {
+        let kind = match buffer.kind {
+            BufferKind::Server => "server",
+            BufferKind::Query => "query",
+        };
+        out.push_str(&format!("buffer\t{kind}\t{}\n", escape(&buffer.name)));
+        for line in buffer.lines.iter() {
+            let timestamp = line.timestamp.map(|timestamp| timestamp.to_string()).unwrap_or_default();
+            out.push_str(&format!("line\t{}\t{timestamp}\t{}\t{}\n", line.kind.name(), escape(&line.sender), escape(&line.text)));
+        }
+    }
+    out
+}
+
+pub fn deserialize(s: &str) -> Result<Vec<SavedBuffer>, String> {
+    let mut lines = s.lines();
+    if lines.next() != Some(FILE_HEADER) {
+        return Err("Unrecognized file header".to_string());
+    }
+    let mut buffers: Vec<SavedBuffer> = vec![];
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["buffer", kind, name] => {
+                let kind = match *kind {
+                    "server" => BufferKind::Server,
+                    "query" => BufferKind::Query,
+                    _ => return Err(format!("Unknown buffer kind {kind}")),
+                };
+                buffers.push(SavedBuffer { kind, name: unescape(name)?, lines: vec![] });
+            }
+            ["line", kind, timestamp, sender, text] => {
+                let buffer = buffers.last_mut().ok_or("Line appears before any buffer")?;
+                let kind = LineKind::from_name(kind).ok_or(format!("Unknown line kind {kind}"))?;
+                let timestamp = match *timestamp {
+                    "" => None,
+                    timestamp => Some(DateTime::parse_iso8601(timestamp).ok_or(format!("Invalid timestamp {timestamp}"))?),
+                };
+                buffer.lines.push(RenderedLine::new(kind, &unescape(sender)?, &unescape(text)?, timestamp));
+            }
+            _ => return Err(format!("Malformed record {line:?}")),
+        }
+    }
+    Ok(buffers)
+}
+
+/// Carries each buffer's recent scrollback over to the next boot
+pub struct ScrollbackStore<'a> {
+    storage: Rc<dyn FileStorage + 'a>,
+    clock: Rc<dyn Clock + 'a>,
+    options: ScrollbackOptions,
+    last_save_ms: u64,
+    /// What was most recently written, so an unchanged scrollback isn't written again
+    last_saved: Option<String>,
+}
+
+impl<'a> ScrollbackStore<'a> {
+    pub fn new(
+        storage: Rc<dyn FileStorage + 'a>,
+        clock: Rc<dyn Clock + 'a>,
+        options: ScrollbackOptions,
+    ) -> Self {
+        let last_save_ms = clock.now_ms();
+        Self {
+            storage,
+            clock,
+            options,
+            last_save_ms,
+            last_saved: None,
+        }
+    }
+
+    /// The scrollback saved by the previous session. A missing or unreadable file just means there's nothing to restore.
+    pub fn load(&self) -> Vec<SavedBuffer> {
+        // Swapping in a save isn't atomic on the ESP. If power was lost partway through, the main file may be
+        // missing or truncated, but the temporary file it was being replaced with is complete.
+        for path in [SCROLLBACK_FILE_PATH.to_string(), temp_file_path()] {
+            match self.load_from(&path) {
+                None => {}
+                Some(Ok(buffers)) => return buffers,
+                Some(Err(e)) => info!("Ignoring saved scrollback in {path}: {e}"),
+            }
+        }
+        vec![]
+    }
+
+    fn load_from(&self, path: &str) -> Option<Result<Vec<SavedBuffer>, String>> {
+        let data = self.storage.read(path)?;
+        Some(
+            core::str::from_utf8(&data)
+                .map_err(|e| format!("{e}"))
+                .and_then(deserialize)
+        )
+    }
+
+    pub fn is_save_due(&self) -> bool {
+        self.clock.now_ms() - self.last_save_ms >= self.options.save_interval_ms
+    }
+
+    /// Write out the most recent lines of each buffer
+    pub fn save(&mut self, buffers: &[SavedBuffer]) {
+        self.last_save_ms = self.clock.now_ms();
+        let line_count = self.options.line_count;
+        let trimmed: Vec<SavedBuffer> = buffers
+            .iter()
+            .map(|buffer| SavedBuffer {
+                kind: buffer.kind,
+                name: buffer.name.clone(),
+                lines: buffer.lines[buffer.lines.len().saturating_sub(line_count)..].to_vec(),
+            })
+            .collect();
+        let serialized = serialize(&trimmed);
+        if self.last_saved.as_ref() == Some(&serialized) {
+            return;
+        }
+
+        // Write alongside and then swap it in, so that losing power mid-write doesn't lose the previous save.
+        // If the swap itself is interrupted, load() falls back to the temporary file.
+        let temp_path = temp_file_path();
+        let result = self.storage
+            .write(&temp_path, serialized.as_bytes())
+            .and_then(|_| self.storage.rename(&temp_path, SCROLLBACK_FILE_PATH));
+        match result {
+            Ok(_) => self.last_saved = Some(serialized),
+            Err(e) => info!("Failed to save scrollback: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::rc::Rc;
+    use alloc::vec;
+    use crate::buffers::BufferKind;
+    use crate::storage::{FileStorage, MemoryFileStorage};
+    use crate::clock::{Clock, DateTime, FakeClock};
+    use crate::rendered_line::{LineKind, RenderedLine};
+    use crate::scrollback::{deserialize, serialize, temp_file_path, SavedBuffer, ScrollbackOptions, ScrollbackStore, SCROLLBACK_FILE_PATH};
+
+    fn line(sender: &str, text: &str) -> RenderedLine {
+        RenderedLine::new(LineKind::PrivateMessage, sender, text, None)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let buffers = vec![
+            SavedBuffer {
+                kind: BufferKind::Server,
+                name: "Server".into(),
+                lines: vec![
+                    RenderedLine::new(
+                        LineKind::ServerNotice,
+                        "Notice",
+                        "Welcome\tto\\the\r\nnetwork",
+                        Some(DateTime::new(2024, 3, 7, 9, 5, 0)),
+                    ),
+                ],
+            },
+            SavedBuffer {
+                kind: BufferKind::Query,
+                name: "alice".into(),
+                lines: vec![line("alice", "hi"), line("", "")],
+            },
+        ];
+        let serialized = serialize(&buffers);
+        // Each record stays on its own line
+        assert_eq!(serialized.lines().count(), 6);
+        assert_eq!(deserialize(&serialized).unwrap(), buffers);
+
+        assert!(deserialize("something else\n").is_err());
+        assert!(deserialize("uefirc scrollback 3\nline\tjoin\t\ta\tb\n").is_err());
+        assert!(deserialize("uefirc scrollback 3\nbuffer\tchannel\t#a\n").is_err());
+        assert!(deserialize("uefirc scrollback 3\nbuffer\tserver\tServer\nline\tjoin\tnoon\ta\tb\n").is_err());
+        assert!(deserialize("uefirc scrollback 3\nbuffer\tserver\tServer\nline\tshout\t\ta\tb\n").is_err());
+        // Files in an older format are ignored
+        assert!(deserialize("uefirc scrollback 2\nbuffer\tserver\tServer\nline\t\ta\tb\n").is_err());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let storage = Rc::new(MemoryFileStorage::new());
+        let clock = Rc::new(FakeClock::new());
+        let options = ScrollbackOptions {
+            is_enabled: true,
+            line_count: 2,
+            save_interval_ms: 1_000,
+        };
+        let mut store = ScrollbackStore::new(
+            Rc::clone(&storage) as Rc<dyn FileStorage>,
+            Rc::clone(&clock) as Rc<dyn Clock>,
+            options,
+        );
+        // Nothing saved yet
+        assert_eq!(store.load(), vec![]);
+
+        assert!(!store.is_save_due());
+        clock.advance(1_000);
+        assert!(store.is_save_due());
+        let lines = vec![line("a", "1"), line("b", "2"), line("c", "3")];
+        store.save(&[SavedBuffer { kind: BufferKind::Server, name: "Server".into(), lines }]);
+        assert!(!store.is_save_due());
+        assert_eq!(storage.paths(), vec![SCROLLBACK_FILE_PATH]);
+
+        // Only the most recent lines are kept
+        let loaded = store.load();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].lines, vec![line("b", "2"), line("c", "3")]);
+
+        // A corrupt file is ignored
+        storage.write(SCROLLBACK_FILE_PATH, b"\xff\xfe").unwrap();
+        assert_eq!(store.load(), vec![]);
+    }
+
+    #[test]
+    fn test_load_interrupted_save() {
+        let storage = Rc::new(MemoryFileStorage::new());
+        let clock = Rc::new(FakeClock::new());
+        let store = ScrollbackStore::new(
+            Rc::clone(&storage) as Rc<dyn FileStorage>,
+            Rc::clone(&clock) as Rc<dyn Clock>,
+            ScrollbackOptions::default(),
+        );
+        let saved = vec![SavedBuffer { kind: BufferKind::Server, name: "Server".into(), lines: vec![line("a", "1")] }];
+        // Power was lost after the old file was removed, but before the new one was moved into place
+        storage.write(&temp_file_path(), serialize(&saved).as_bytes()).unwrap();
+        assert_eq!(store.load(), saved);
+
+        // Or partway through moving it, leaving a truncated file behind
+        storage.write(SCROLLBACK_FILE_PATH, b"uefirc scrollback 3\nbuffer\tser").unwrap();
+        assert_eq!(store.load(), saved);
+
+        // A complete main file takes precedence
+        let newer = vec![SavedBuffer { kind: BufferKind::Server, name: "Server".into(), lines: vec![line("b", "2")] }];
+        storage.write(SCROLLBACK_FILE_PATH, serialize(&newer).as_bytes()).unwrap();
+        assert_eq!(store.load(), newer);
+    }
+}
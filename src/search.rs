@@ -0,0 +1,225 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::rendered_line::RenderedLine;
+
+/// What to look for in the scrollback.
+/// Matching ignores case. A query containing `*` or `?` is treated as a glob, where `*` matches any run of
+/// characters and `?` matches any single character. Either way, the query can match anywhere within a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    pattern: Vec<char>,
+    is_glob: bool,
+}
+
+impl SearchQuery {
+    /// None if there's nothing to search for
+    pub fn new(query: &str) -> Option<Self> {
+        if query.is_empty() {
+            return None;
+        }
+        Some(
+            Self {
+                pattern: query.to_lowercase().chars().collect(),
+                is_glob: query.contains(['*', '?']),
+            }
+        )
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.to_lowercase().chars().collect();
+        match self.is_glob {
+            true => glob_matches_within(&self.pattern, &text),
+            false => text.windows(self.pattern.len()).any(|window| window == self.pattern.as_slice()),
+        }
+    }
+
+    /// Whether the line's sender or text matches
+    pub fn matches_line(&self, line: &RenderedLine) -> bool {
+        !line.is_separator() && (self.matches(&line.sender) || self.matches(&line.text))
+    }
+}
+
+/// Does the glob match any part of the text?
+fn glob_matches_within(pattern: &[char], text: &[char]) -> bool {
+    // Matching anywhere is the same as matching the whole text with a '*' on either side.
+    // Backtracks to the most recent '*' on a mismatch, which avoids the exponential blowup of recursing at each one
+    let pattern: Vec<char> = ['*'].iter().chain(pattern.iter()).chain(['*'].iter()).copied().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, t));
+            p += 1;
+        }
+        else if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        }
+        else if let Some((star_p, star_t)) = last_star {
+            // Let the '*' swallow one more character, and try again from there
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        }
+        else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&ch| ch == '*')
+}
+
+/// The hits of a search, ordered from the oldest to the most recent, and which of them is selected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchHits<T> {
+    hits: Vec<T>,
+    selected: Option<usize>,
+}
+
+impl<T> SearchHits<T> {
+    /// Starts off at the most recent hit, as that's likely what's being looked for
+    pub fn new(hits: Vec<T>) -> Self {
+        let selected = hits.len().checked_sub(1);
+        Self {
+            hits,
+            selected,
+        }
+    }
+
+    pub fn selected(&self) -> Option<&T> {
+        self.hits.get(self.selected?)
+    }
+
+    /// Move to the next hit back in the scrollback, wrapping around to the most recent
+    pub fn select_older(&mut self) {
+        if let Some(selected) = self.selected {
+            self.selected = Some(selected.checked_sub(1).unwrap_or(self.hits.len() - 1));
+        }
+    }
+
+    /// Move to the next hit forward in the scrollback, wrapping around to the oldest
+    pub fn select_newer(&mut self) {
+        if let Some(selected) = self.selected {
+            self.selected = Some((selected + 1) % self.hits.len());
+        }
+    }
+
+    /// Add a hit that's more recent than the others, leaving the selection where it is
+    pub fn push(&mut self, hit: T) {
+        self.hits.push(hit);
+        if self.selected.is_none() {
+            self.selected = Some(self.hits.len() - 1);
+        }
+    }
+
+    /// Drop the oldest hit, such as when its line leaves the scrollback
+    pub fn remove_oldest(&mut self) {
+        if self.hits.is_empty() {
+            return;
+        }
+        self.hits.remove(0);
+        self.selected = match self.hits.is_empty() {
+            true => None,
+            // Keep the same hit selected, unless it was the one that was dropped
+            false => self.selected.map(|selected| selected.saturating_sub(1)),
+        };
+    }
+
+    /// Where the selected hit is, for showing to the user. Counts from 1.
+    pub fn description(&self) -> String {
+        match self.selected {
+            None => "No matches".into(),
+            Some(selected) => alloc::format!("{} of {}", selected + 1, self.hits.len()),
+        }
+    }
+}
+
+impl<T> Default for SearchHits<T> {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use crate::rendered_line::{LineKind, RenderedLine};
+    use crate::search::{SearchHits, SearchQuery};
+
+    #[test]
+    fn test_plain_query() {
+        assert_eq!(SearchQuery::new(""), None);
+        let query = SearchQuery::new("Hello").unwrap();
+        assert!(query.matches("well, hello there"));
+        assert!(query.matches("HELLO"));
+        assert!(!query.matches("hell"));
+
+        let line = RenderedLine::new(LineKind::PrivateMessage, "alice", "hi everyone", None);
+        assert!(SearchQuery::new("ALI").unwrap().matches_line(&line));
+        assert!(SearchQuery::new("every").unwrap().matches_line(&line));
+        assert!(!SearchQuery::new("bob").unwrap().matches_line(&line));
+        // Separators aren't part of the conversation
+        assert!(!SearchQuery::new("day").unwrap().matches_line(&RenderedLine::separator("-", "Day changed")));
+    }
+
+    #[test]
+    fn test_glob_query() {
+        let query = SearchQuery::new("b*d").unwrap();
+        assert!(query.matches("it's a bad idea"));
+        assert!(query.matches("Bird"));
+        assert!(!query.matches("bat"));
+
+        let query = SearchQuery::new("c?t").unwrap();
+        assert!(query.matches("the CAT sat"));
+        assert!(query.matches("cut"));
+        assert!(!query.matches("ct"));
+
+        assert!(SearchQuery::new("*").unwrap().matches(""));
+        assert!(SearchQuery::new("a*b*c").unwrap().matches("xxaxxbxxbxxcxx"));
+        assert!(!SearchQuery::new("a*b*c").unwrap().matches("cba"));
+    }
+
+    #[test]
+    fn test_hit_navigation() {
+        let mut hits = SearchHits::new(vec![10, 20, 30]);
+        assert_eq!(hits.selected(), Some(&30));
+        assert_eq!(hits.description(), "3 of 3");
+        hits.select_older();
+        assert_eq!(hits.selected(), Some(&20));
+        hits.select_newer();
+        hits.select_newer();
+        // Wraps around
+        assert_eq!(hits.selected(), Some(&10));
+        hits.select_older();
+        assert_eq!(hits.selected(), Some(&30));
+
+        let mut no_hits: SearchHits<isize> = SearchHits::default();
+        no_hits.select_older();
+        assert_eq!(no_hits.selected(), None);
+        assert_eq!(no_hits.description(), "No matches");
+    }
+
+    #[test]
+    fn test_hits_change_while_searching() {
+        let mut hits = SearchHits::default();
+        // The first hit to arrive is selected
+        hits.push(10);
+        assert_eq!(hits.selected(), Some(&10));
+        hits.push(20);
+        hits.push(30);
+        // Newer hits don't move the selection
+        assert_eq!(hits.selected(), Some(&10));
+        hits.select_newer();
+        assert_eq!(hits.description(), "2 of 3");
+
+        hits.remove_oldest();
+        assert_eq!(hits.selected(), Some(&20));
+        assert_eq!(hits.description(), "1 of 2");
+        // Dropping the selected hit moves on to the one after it
+        hits.remove_oldest();
+        assert_eq!(hits.selected(), Some(&30));
+        hits.remove_oldest();
+        assert_eq!(hits.selected(), None);
+        hits.remove_oldest();
+        assert_eq!(hits.description(), "No matches");
+    }
+}
@@ -0,0 +1,85 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use uefi::CString16;
+use uefi::fs::FileSystem;
+use uefi::prelude::BootServices;
+use crate::storage::FileStorage;
+
+/// Storage on the filesystem that we were loaded from, which is normally the ESP
+pub struct EspFileStorage<'a> {
+    fs: RefCell<FileSystem<'a>>,
+}
+
+impl<'a> EspFileStorage<'a> {
+    pub fn new(boot_services: &'a BootServices) -> uefi::Result<Self> {
+        let sfs = boot_services.get_image_file_system(boot_services.image_handle())?;
+        Ok(
+            Self {
+                fs: RefCell::new(FileSystem::new(sfs)),
+            }
+        )
+    }
+}
+
+fn to_cstr16(path: &str) -> Result<CString16, String> {
+    CString16::try_from(path).map_err(|_| format!("Path \"{path}\" contains characters that UCS-2 can't represent"))
+}
+
+impl FileStorage for EspFileStorage<'_> {
+    fn create_dir_all(&self, path: &str) -> Result<(), String> {
+        let path = to_cstr16(path)?;
+        self.fs.borrow_mut().create_dir_all(path.as_ref()).map_err(|e| format!("{e:?}"))
+    }
+
+    fn file_size(&self, path: &str) -> Option<u64> {
+        let path = to_cstr16(path).ok()?;
+        self.fs.borrow_mut().metadata(path.as_ref()).ok().map(|info| info.file_size())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        let path = to_cstr16(path).ok()?;
+        self.fs.borrow_mut().read(path.as_ref()).ok()
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        let path = to_cstr16(path)?;
+        let mut fs = self.fs.borrow_mut();
+        // Remove the old file first, so that nothing is left over past the end of shorter contents
+        if let Ok(true) = fs.try_exists(path.as_ref()) {
+            fs.remove_file(path.as_ref()).map_err(|e| format!("{e:?}"))?;
+        }
+        fs.write(path.as_ref(), data).map_err(|e| format!("{e:?}"))
+    }
+
+    fn append(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        let path = to_cstr16(path)?;
+        let mut fs = self.fs.borrow_mut();
+        // uefi::fs has no way to append, so read back what's there and write the lot.
+        // The rotation size keeps this bounded. As the new contents are always longer, it doesn't
+        // matter whether the write truncates the existing file.
+        let mut contents = match fs.try_exists(path.as_ref()) {
+            Ok(true) => fs.read(path.as_ref()).map_err(|e| format!("{e:?}"))?,
+            _ => Vec::new(),
+        };
+        contents.extend_from_slice(data);
+        fs.write(path.as_ref(), &contents).map_err(|e| format!("{e:?}"))
+    }
+
+    /// Not atomic: the destination is removed first, and the move may itself copy the data
+    fn rename(&self, from: &str, to: &str) -> Result<(), String> {
+        let from = to_cstr16(from)?;
+        let to = to_cstr16(to)?;
+        let mut fs = self.fs.borrow_mut();
+        if let Ok(true) = fs.try_exists(to.as_ref()) {
+            fs.remove_file(to.as_ref()).map_err(|e| format!("{e:?}"))?;
+        }
+        fs.rename(from.as_ref(), to.as_ref()).map_err(|e| format!("{e:?}"))
+    }
+
+    fn remove(&self, path: &str) -> Result<(), String> {
+        let path = to_cstr16(path)?;
+        self.fs.borrow_mut().remove_file(path.as_ref()).map_err(|e| format!("{e:?}"))
+    }
+}
@@ -0,0 +1,63 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use crate::storage::FileStorage;
+
+/// In-memory storage, for inspecting what gets written
+#[derive(Debug)]
+pub struct MemoryFileStorage {
+    files: RefCell<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryFileStorage {
+    pub fn new() -> Self {
+        Self {
+            files: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn contents(&self, path: &str) -> Option<String> {
+        self.files.borrow().get(path).map(|data| String::from_utf8(data.clone()).unwrap())
+    }
+
+    pub fn paths(&self) -> Vec<String> {
+        self.files.borrow().keys().cloned().collect()
+    }
+}
+
+impl FileStorage for MemoryFileStorage {
+    fn create_dir_all(&self, _path: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn file_size(&self, path: &str) -> Option<u64> {
+        self.files.borrow().get(path).map(|data| data.len() as u64)
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        self.files.borrow().get(path).cloned()
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        self.files.borrow_mut().insert(path.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn append(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        self.files.borrow_mut().entry(path.to_string()).or_default().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), String> {
+        let mut files = self.files.borrow_mut();
+        let data = files.remove(from).ok_or(format!("{from} doesn't exist"))?;
+        files.insert(to.to_string(), data);
+        Ok(())
+    }
+
+    fn remove(&self, path: &str) -> Result<(), String> {
+        self.files.borrow_mut().remove(path).map(|_| ()).ok_or(format!("{path} doesn't exist"))
+    }
+}
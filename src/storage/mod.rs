@@ -0,0 +1,40 @@
+#[cfg(test)]
+mod memory_storage;
+#[cfg(feature = "run_in_uefi")]
+mod esp_storage;
+#[cfg(not(feature = "run_in_uefi"))]
+mod std_storage;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+#[cfg(test)]
+pub use self::memory_storage::MemoryFileStorage;
+#[cfg(feature = "run_in_uefi")]
+pub use self::esp_storage::EspFileStorage;
+#[cfg(not(feature = "run_in_uefi"))]
+pub use self::std_storage::StdFileStorage;
+
+/// Files kept alongside the app, such as chat logs, saved scrollback and themes.
+/// Paths use UEFI-style backslash separators, relative to the root of the filesystem.
+pub trait FileStorage {
+    /// Create the directory, along with any missing parents
+    fn create_dir_all(&self, path: &str) -> Result<(), String>;
+
+    /// The size of the file in bytes, or None if it doesn't exist
+    fn file_size(&self, path: &str) -> Option<u64>;
+
+    /// The whole contents of a file, or None if it doesn't exist or can't be read
+    fn read(&self, path: &str) -> Option<Vec<u8>>;
+
+    /// Replace the contents of a file, creating it if necessary
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), String>;
+
+    /// Add data to the end of a file, creating it if necessary
+    fn append(&self, path: &str, data: &[u8]) -> Result<(), String>;
+
+    /// Replaces the destination if it already exists
+    fn rename(&self, from: &str, to: &str) -> Result<(), String>;
+
+    fn remove(&self, path: &str) -> Result<(), String>;
+}
@@ -0,0 +1,58 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use crate::storage::FileStorage;
+
+/// Storage under a host directory, for the hosted build. Stands in for the ESP.
+#[derive(Debug)]
+pub struct StdFileStorage {
+    root: PathBuf,
+}
+
+impl StdFileStorage {
+    pub fn new(root: &str) -> Self {
+        Self {
+            root: PathBuf::from(root),
+        }
+    }
+
+    fn host_path(&self, path: &str) -> PathBuf {
+        // Paths are written for UEFI, so translate the separators
+        self.root.join(path.replace('\\', "/"))
+    }
+}
+
+impl FileStorage for StdFileStorage {
+    fn create_dir_all(&self, path: &str) -> Result<(), String> {
+        std::fs::create_dir_all(self.host_path(path)).map_err(|e| e.to_string())
+    }
+
+    fn file_size(&self, path: &str) -> Option<u64> {
+        std::fs::metadata(self.host_path(path)).ok().map(|metadata| metadata.len())
+    }
+
+    fn read(&self, path: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.host_path(path)).ok()
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        std::fs::write(self.host_path(path), data).map_err(|e| e.to_string())
+    }
+
+    fn append(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.host_path(path))
+            .map_err(|e| e.to_string())?;
+        file.write_all(data).map_err(|e| e.to_string())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), String> {
+        std::fs::rename(self.host_path(from), self.host_path(to)).map_err(|e| e.to_string())
+    }
+
+    fn remove(&self, path: &str) -> Result<(), String> {
+        std::fs::remove_file(self.host_path(path)).map_err(|e| e.to_string())
+    }
+}
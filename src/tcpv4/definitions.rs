@@ -1,12 +1,11 @@
 use alloc::format;
-use core::alloc::Layout;
 use core::ffi::c_void;
 use core::fmt::{Debug, Formatter};
-use core::ptr::copy_nonoverlapping;
 use uefi::{Event, Status};
 use crate::event::ManagedEvent;
 
-use crate::ipv4::IPv4Address;
+use crate::ipv4::{IPv4Address, StaticIpConfig};
+use crate::transport::TcpOptions;
 use crate::tcpv4::receive_data::TCPv4ReceiveData;
 use crate::tcpv4::{TCPv4TransmitData};
 
@@ -28,19 +27,24 @@ pub struct TCPv4AccessPoint {
 
 impl TCPv4AccessPoint {
     fn new(connection_mode: TCPv4ConnectionMode) -> Self {
-        let (remote_ip, remote_port, is_client) = match connection_mode {
+        let (remote_ip, remote_port, is_client, static_ip) = match connection_mode {
             TCPv4ConnectionMode::Client(params) => {
-                (params.remote_ip, params.remote_port, true)
+                (params.remote_ip, params.remote_port, true, params.static_ip)
             }
             TCPv4ConnectionMode::Server => {
-                (IPv4Address::zero(), 0, false)
+                (IPv4Address::zero(), 0, false, None)
             }
         };
+        // Without a static configuration, use whatever address DHCP hands out
+        let (use_default_address, station_address, subnet_mask) = match static_ip {
+            None => (true, IPv4Address::zero(), IPv4Address::zero()),
+            Some(static_ip) => (false, static_ip.station_address, static_ip.subnet_mask),
+        };
         Self {
-            use_default_address: true,
-            // These two fields are meaningless because we set use_default_address above
-            station_address: IPv4Address::zero(),
-            subnet_mask: IPv4Address::zero(),
+            use_default_address,
+            // These two fields are ignored if use_default_address is set
+            station_address,
+            subnet_mask,
             // Chosen on-demand
             station_port: 0,
             remote_address: remote_ip,
@@ -71,6 +75,44 @@ pub struct TCPv4Option {
     enable_path_mtu_discovery: bool,
 }
 
+impl TCPv4Option {
+    // These mirror the defaults in EDK2's TCP driver, for anything the user didn't specify.
+    // The driver may still clamp the values we pass to its own limits.
+    const DEFAULT_BUFFER_SIZE: u32 = 2 * 1024 * 1024;
+    const DEFAULT_SYN_BACK_LOG: u32 = 10;
+    const DEFAULT_CONNECTION_TIMEOUT_SECS: u32 = 75;
+    const DEFAULT_DATA_RETRIES: u32 = 12;
+    const DEFAULT_FIN_TIMEOUT_SECS: u32 = 2;
+    const DEFAULT_TIME_WAIT_TIMEOUT_SECS: u32 = 2;
+}
+
+impl From<&TcpOptions> for TCPv4Option {
+    fn from(options: &TcpOptions) -> Self {
+        let (keep_alive_probes, keep_alive_time, keep_alive_interval) = match options.keep_alive {
+            // Zero probes disables keep-alive
+            None => (0, 0, 0),
+            Some(keep_alive) => (keep_alive.probes, keep_alive.idle_secs, keep_alive.interval_secs),
+        };
+        Self {
+            receive_buffer_size: options.receive_buffer_size.unwrap_or(Self::DEFAULT_BUFFER_SIZE),
+            send_buffer_size: options.send_buffer_size.unwrap_or(Self::DEFAULT_BUFFER_SIZE),
+            max_syn_back_log: Self::DEFAULT_SYN_BACK_LOG,
+            connection_timeout: options.connection_timeout_secs.unwrap_or(Self::DEFAULT_CONNECTION_TIMEOUT_SECS),
+            data_retries: options.data_retries.unwrap_or(Self::DEFAULT_DATA_RETRIES),
+            fin_timeout: options.fin_timeout_secs.unwrap_or(Self::DEFAULT_FIN_TIMEOUT_SECS),
+            time_wait_timeout: options.time_wait_timeout_secs.unwrap_or(Self::DEFAULT_TIME_WAIT_TIMEOUT_SECS),
+            keep_alive_probes,
+            keep_alive_time,
+            keep_alive_interval,
+            enable_nagle: options.enable_nagle,
+            enable_time_stamp: true,
+            enable_window_scaling: true,
+            enable_selective_ack: false,
+            enable_path_mtu_discovery: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 #[repr(C)]
 pub struct TCPv4ConfigData<'a> {
@@ -80,10 +122,12 @@ pub struct TCPv4ConfigData<'a> {
     option: Option<&'a TCPv4Option>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TCPv4ClientConnectionModeParams {
     remote_ip: IPv4Address,
     remote_port: u16,
+    options: Option<TcpOptions>,
+    static_ip: Option<StaticIpConfig>,
 }
 
 impl TCPv4ClientConnectionModeParams {
@@ -94,8 +138,33 @@ impl TCPv4ClientConnectionModeParams {
         Self {
             remote_ip,
             remote_port,
+            options: None,
+            static_ip: None,
         }
     }
+
+    /// Use a fixed address rather than waiting on DHCP
+    pub fn with_static_ip(mut self, static_ip: StaticIpConfig) -> Self {
+        self.static_ip = Some(static_ip);
+        self
+    }
+
+    pub(crate) fn static_ip(&self) -> Option<StaticIpConfig> {
+        self.static_ip
+    }
+
+    /// Panics if the options don't pass validation
+    pub fn with_options(mut self, options: TcpOptions) -> Self {
+        if let Err(e) = options.validate() {
+            panic!("Invalid TCP options: {e}");
+        }
+        self.options = Some(options);
+        self
+    }
+
+    pub(crate) fn options(&self) -> Option<&TcpOptions> {
+        self.options.as_ref()
+    }
 }
 
 #[derive(Debug)]
@@ -188,47 +257,26 @@ impl TCPv4CompletionToken {
             status: Status::SUCCESS,
         }
     }
+
+    /// The result of the operation, as filled in by the TCP driver once the event is signalled
+    pub fn status(&self) -> Status {
+        // The driver writes this behind our back, so make sure we don't read a stale value
+        unsafe { core::ptr::read_volatile(&self.status) }
+    }
 }
 
 #[derive(Debug)]
 #[repr(C)]
-pub struct TCPv4FragmentData {
-    pub(crate) fragment_length: u32,
-    pub(crate) fragment_buf: *const c_void,
-}
-
-impl TCPv4FragmentData {
-    pub fn with_buffer_len(len: usize) -> Self {
-        unsafe {
-            let layout = Layout::array::<u8>(len).unwrap();
-            let buffer = alloc::alloc::alloc(layout);
-            Self {
-                fragment_length: len as u32,
-                fragment_buf: buffer as *const c_void,
-            }
-        }
-    }
-    pub fn with_data(data: &[u8]) -> Self {
-        unsafe {
-            let data_len = data.len();
-            let _self = Self::with_buffer_len(data_len);
-            let buffer = _self.fragment_buf as *mut u8;
-            copy_nonoverlapping(
-                data.as_ptr(),
-                buffer,
-                data_len,
-            );
-            _self
-        }
-    }
+pub struct TCPv4CloseToken {
+    pub completion_token: TCPv4CompletionToken,
+    abort_on_close: bool,
 }
 
-impl Drop for TCPv4FragmentData {
-    fn drop(&mut self) {
-        unsafe {
-            let layout = Layout::array::<u8>(self.fragment_length as usize).unwrap();
-            alloc::alloc::dealloc(self.fragment_buf as *mut u8, layout);
-            //println!("Deallocated fragment {:?}", self.fragment_buf);
+impl TCPv4CloseToken {
+    pub fn new(event: &ManagedEvent, abort_on_close: bool) -> Self {
+        Self {
+            completion_token: TCPv4CompletionToken::new(event),
+            abort_on_close,
         }
     }
 }
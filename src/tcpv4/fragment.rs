@@ -0,0 +1,67 @@
+use core::alloc::Layout;
+use core::ffi::c_void;
+use core::mem::ManuallyDrop;
+use core::ptr::copy_nonoverlapping;
+
+#[derive(Debug)]
+#[repr(C)]
+pub struct TCPv4FragmentData {
+    pub(crate) fragment_length: u32,
+    pub(crate) fragment_buf: *const c_void,
+}
+
+impl TCPv4FragmentData {
+    pub fn with_buffer_len(len: usize) -> Self {
+        unsafe {
+            let layout = Layout::array::<u8>(len).unwrap();
+            let buffer = alloc::alloc::alloc(layout);
+            Self {
+                fragment_length: len as u32,
+                fragment_buf: buffer as *const c_void,
+            }
+        }
+    }
+    pub fn with_data(data: &[u8]) -> Self {
+        unsafe {
+            let data_len = data.len();
+            let _self = Self::with_buffer_len(data_len);
+            let buffer = _self.fragment_buf as *mut u8;
+            copy_nonoverlapping(
+                data.as_ptr(),
+                buffer,
+                data_len,
+            );
+            _self
+        }
+    }
+
+    /// Describe a buffer that's owned by somebody else, such as an `RxBufferPool`.
+    /// The returned fragment must never be dropped, as it doesn't own the buffer it points to.
+    pub fn borrowing(buffer: &mut [u8]) -> ManuallyDrop<Self> {
+        ManuallyDrop::new(
+            Self {
+                fragment_length: u32::try_from(buffer.len()).expect("Fragment buffer is too large"),
+                fragment_buf: buffer.as_mut_ptr() as *const c_void,
+            }
+        )
+    }
+
+    /// The valid contents of the fragment.
+    /// On receive, the driver shrinks fragment_length down to the number of bytes it actually wrote.
+    ///
+    /// # Safety
+    /// fragment_buf must still point to at least fragment_length readable bytes.
+    pub unsafe fn as_slice(&self) -> &[u8] {
+        core::slice::from_raw_parts(self.fragment_buf as *const u8, self.fragment_length as usize)
+    }
+}
+
+impl Drop for TCPv4FragmentData {
+    fn drop(&mut self) {
+        unsafe {
+            let layout = Layout::array::<u8>(self.fragment_length as usize).unwrap();
+            alloc::alloc::dealloc(self.fragment_buf as *mut u8, layout);
+            //println!("Deallocated fragment {:?}", self.fragment_buf);
+        }
+    }
+}
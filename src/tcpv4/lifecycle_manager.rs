@@ -5,7 +5,8 @@ use log::info;
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum TCPv4ConnectionOperation {
     Connecting,
-    Transmitting,
+    /// A transmit that's been handed to the stack, identified by the ID its issuer gave it
+    Transmitting(u64),
 }
 
 #[derive(Debug)]
@@ -41,12 +42,41 @@ impl TCPv4ConnectionLifecycleManager {
         info!("Callback: connection completed!");
     }
 
-    pub fn register_started_transmitting(&mut self) {
-        self.add_if_not_present(TCPv4ConnectionOperation::Transmitting);
+    pub fn register_started_transmitting(&mut self, id: u64) {
+        self.add_if_not_present(TCPv4ConnectionOperation::Transmitting(id));
     }
 
-    pub fn register_transmitting_complete(&mut self) {
-        self.remove_if_present(TCPv4ConnectionOperation::Transmitting);
-        info!("Callback: transmit completed!");
+    pub fn register_transmitting_complete(&mut self, id: u64) {
+        self.remove_if_present(TCPv4ConnectionOperation::Transmitting(id));
+    }
+
+    pub fn transmits_in_flight(&self) -> usize {
+        self.pending_operations
+            .iter()
+            .filter(|op| matches!(op, TCPv4ConnectionOperation::Transmitting(_)))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::tcpv4::TCPv4ConnectionLifecycleManager;
+
+    #[test]
+    fn test_tracks_transmits() {
+        let mut manager = TCPv4ConnectionLifecycleManager::new();
+        manager.register_started_connecting();
+        manager.register_started_transmitting(0);
+        manager.register_started_transmitting(1);
+        // Registering the same transmit twice doesn't count it twice
+        manager.register_started_transmitting(1);
+        assert_eq!(manager.transmits_in_flight(), 2);
+
+        // Transmits can complete in any order
+        manager.register_transmitting_complete(1);
+        assert_eq!(manager.transmits_in_flight(), 1);
+        manager.register_connecting_complete();
+        manager.register_transmitting_complete(0);
+        assert_eq!(manager.transmits_in_flight(), 0);
     }
 }
@@ -1,25 +1,37 @@
 mod lifecycle_manager;
+#[cfg(feature = "run_in_uefi")]
 mod transmit_data;
+#[cfg(feature = "run_in_uefi")]
 mod proto;
+#[cfg(feature = "run_in_uefi")]
 mod definitions;
+mod fragment;
 mod receive_data;
+mod rx_buffer_pool;
 
+#[cfg(feature = "run_in_uefi")]
 pub use self::proto::{
     TCPv4Protocol,
     TCPv4ServiceBindingProtocol,
 };
+#[cfg(feature = "run_in_uefi")]
 pub use self::definitions::{
     TCPv4ClientConnectionModeParams,
     TCPv4ConnectionMode,
-    TCPv4FragmentData,
+    TCPv4ConnectionState,
     TCPv4IoToken,
 };
+pub use self::fragment::TCPv4FragmentData;
+pub use self::lifecycle_manager::TCPv4ConnectionLifecycleManager;
 
+#[cfg(feature = "run_in_uefi")]
 pub use self::transmit_data::{
     TCPv4TransmitData,
+    TCPv4TransmitDataHandle,
 };
 
 pub use self::receive_data::{
     TCPv4ReceiveDataHandle,
     TCPv4ReceiveData,
 };
+pub use self::rx_buffer_pool::RxBufferPool;
@@ -1,17 +1,20 @@
+use alloc::format;
 use alloc::string::{String, ToString};
 use log::info;
 use uefi::{Handle, Status, StatusExt};
 use uefi::prelude::BootServices;
 use crate::event::ManagedEvent;
-use crate::ipv4::{IPv4Address, IPv4ModeData};
+use crate::ipv4::{IPv4Address, IPv4ModeData, IPv4RouteTable};
 use crate::tcpv4::TCPv4ConnectionMode;
-use crate::tcpv4::transmit_data::TCPv4TransmitDataHandle;
 use uefi::proto::unsafe_protocol;
-use crate::tcpv4::definitions::{TCPv4CompletionToken, TCPv4ConfigData, TCPv4ConnectionState, TCPv4IoToken, UnmodelledPointer};
+use crate::tcpv4::definitions::{TCPv4CloseToken, TCPv4CompletionToken, TCPv4ConfigData, TCPv4ConnectionState, TCPv4IoToken, TCPv4Option, UnmodelledPointer};
 use uefi::Error;
-use uefi::table::boot::EventType;
+use uefi::table::boot::{EventType, TimerTrigger};
 use uefi_services::println;
 
+/// How long to wait for the peer to acknowledge a graceful close
+const CLOSE_TIMEOUT_MS: u64 = 5_000;
+
 #[derive(Debug)]
 #[repr(C)]
 #[unsafe_protocol("00720665-67EB-4a99-BAF7-D3C33A1C7CC9")]
@@ -76,12 +79,12 @@ pub struct TCPv4Protocol {
 
     close_fn: extern "efiapi" fn(
         this: &Self,
-        close_token: &UnmodelledPointer,
+        close_token: &TCPv4CloseToken,
     ) -> Status,
 
     cancel_fn: extern "efiapi" fn(
         this: &Self,
-        completion_token: &UnmodelledPointer,
+        completion_token: Option<&TCPv4CompletionToken>,
     ) -> Status,
 
     poll_fn: extern "efiapi" fn(this: &Self) -> Status,
@@ -101,7 +104,11 @@ impl TCPv4Protocol {
         bt: &BootServices,
         connection_mode: TCPv4ConnectionMode,
     ) -> uefi::Result<(), String> {
-        let configuration = TCPv4ConfigData::new(connection_mode, None);
+        let (options, static_ip) = match &connection_mode {
+            TCPv4ConnectionMode::Client(params) => (params.options().map(TCPv4Option::from), params.static_ip()),
+            TCPv4ConnectionMode::Server => (None, None),
+        };
+        let configuration = TCPv4ConfigData::new(connection_mode, options.as_ref());
         // Maximum timeout of 10 seconds
         for _ in 0..10 {
             let result = (self.configure_fn)(
@@ -110,8 +117,18 @@ impl TCPv4Protocol {
             );
             if result == Status::SUCCESS {
                 info!("Configured connection! {result:?}");
+                // With a static address, nobody's going to tell us about the gateway, so add the route ourselves
+                if let Some(gateway_address) = static_ip.and_then(|static_ip| static_ip.gateway_address) {
+                    self.add_route(&IPv4RouteTable::default_route(gateway_address)).map_err(|e| {
+                        Error::new(e.status(), format!("Failed to add a default route via {gateway_address}"))
+                    })?;
+                }
                 return Ok(())
             }
+            else if result == Status::INVALID_PARAMETER {
+                // Retrying won't help if the configuration itself is bad, such as a nonsensical static address
+                return Err(Error::new(result, "The TCP configuration was rejected.".to_string()));
+            }
             else if result == Status::NO_MAPPING {
                 info!("DHCP still running, waiting...");
                 bt.stall(1_000_000);
@@ -124,6 +141,16 @@ impl TCPv4Protocol {
         Err(Error::new(Status::PROTOCOL_ERROR, "Timeout before configuring the connection succeeded.".to_string()))
     }
 
+    pub fn add_route(&self, route: &IPv4RouteTable) -> uefi::Result {
+        (self.routes_fn)(
+            self,
+            false,
+            &route.subnet_address,
+            &route.subnet_mask,
+            &route.gateway_address,
+        ).to_result()
+    }
+
     pub fn get_tcp_connection_state(&self) -> TCPv4ConnectionState {
         let mut connection_state = core::mem::MaybeUninit::<TCPv4ConnectionState>::uninit();
         let connection_state_ptr = connection_state.as_mut_ptr();
@@ -159,7 +186,7 @@ impl TCPv4Protocol {
     pub fn connect(
         &mut self,
         bs: &'static BootServices,
-    ) {
+    ) -> uefi::Result {
         let event = ManagedEvent::new(
             bs,
             EventType::NOTIFY_WAIT,
@@ -169,27 +196,64 @@ impl TCPv4Protocol {
         (self.connect_fn)(
             &self,
             &completion_token,
-        ).to_result().expect("Failed to call Connect()");
+        ).to_result()?;
         event.wait();
+        completion_token.status().to_result()
     }
 
-    pub fn transmit(
-        &mut self,
+    /// Gracefully close the connection, blocking until the peer acknowledges or we time out.
+    /// If the graceful close times out, it's cancelled and an error is returned.
+    pub fn close(
+        &self,
         bs: &'static BootServices,
-        data: &[u8],
-    ) {
+        abort_on_close: bool,
+    ) -> uefi::Result {
         let event = ManagedEvent::new(
             bs,
             EventType::NOTIFY_WAIT,
-            move |_| {println!("Transmit completed!")});
+            |_| {},
+        );
+        let close_token = TCPv4CloseToken::new(&event, abort_on_close);
+        (self.close_fn)(
+            &self,
+            &close_token,
+        ).to_result()?;
 
-        let tx_data_handle = TCPv4TransmitDataHandle::new(data);
-        let tx_data = tx_data_handle.get_data_ref();
-        let io_token = TCPv4IoToken::new(&event, Some(&tx_data), None);
+        // Don't wait forever for a peer that's gone away
+        let timeout_event = ManagedEvent::new(
+            bs,
+            EventType::TIMER,
+            |_| {},
+        );
+        // Timer triggers are specified in units of 100ns
+        bs.set_timer(&timeout_event.event, TimerTrigger::Relative(CLOSE_TIMEOUT_MS * 10_000))?;
+        let signalled_event_idx = ManagedEvent::wait_for_events(bs, &[&event, &timeout_event]);
+        if signalled_event_idx == 1 {
+            info!("Timed out waiting for the connection to close");
+            // The close token is about to go out of scope, so make sure the driver doesn't hold onto it
+            let _ = self.cancel(Some(&close_token.completion_token));
+            return Err(Error::from(Status::TIMEOUT));
+        }
+        Ok(())
+    }
+
+    /// Cancel a pending asynchronous operation. Passing no token cancels all pending operations.
+    pub fn cancel(&self, completion_token: Option<&TCPv4CompletionToken>) -> uefi::Result {
+        (self.cancel_fn)(
+            self,
+            completion_token,
+        ).to_result()
+    }
+
+    /// Hand a transmit to the stack without waiting for it to complete.
+    /// The token's event is signalled once the data has been sent, or the transmit has failed.
+    pub fn start_transmit(
+        &self,
+        io_token: &TCPv4IoToken,
+    ) -> uefi::Result {
         (self.transmit_fn)(
             &self,
-            &io_token,
-        ).to_result().expect("Failed to transmit");
-        event.wait();
+            io_token,
+        ).to_result()
     }
 }
@@ -1,10 +1,10 @@
+use alloc::boxed::Box;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::alloc::Layout;
 use core::marker::PhantomData;
 use core::mem;
 use core::mem::ManuallyDrop;
-use core::ptr::copy_nonoverlapping;
 use crate::tcpv4::TCPv4FragmentData;
 
 /// This type is necessary because the underlying structure has a flexible array member.
@@ -12,44 +12,50 @@ use crate::tcpv4::TCPv4FragmentData;
 /// A Box cannot be used because the Box doesn't have the full knowledge of the layout.
 /// A wide pointer also cannot be used because the layout needs to be precisely controlled for FFI.
 /// Therefore, we use a wrapper 'handle' to manage the lifecycle of the allocation manually.
+///
+/// The fragments point into buffers that the handle holds on to (typically borrowed from an `RxBufferPool`),
+/// and which can be reclaimed via `into_buffers()` once the receive has completed.
 #[derive(Debug)]
-#[repr(C)]
 pub struct TCPv4ReceiveDataHandle<'a> {
     ptr: *const TCPv4ReceiveData,
     layout: Layout,
+    buffers: Vec<Box<[u8]>>,
     phantom: PhantomData<&'a ()>,
 }
 
 impl<'a> TCPv4ReceiveDataHandle<'a> {
-    fn total_layout_size(fragment_count: usize) -> usize {
+    fn layout(fragment_count: usize) -> Layout {
+        // The fragment table is a zero-length array at the end of the header,
+        // so the header's size already includes any padding before the first fragment.
         let size_of_fragments = mem::size_of::<ManuallyDrop<TCPv4FragmentData>>() * fragment_count;
-        let ret = mem::size_of::<Self>() + size_of_fragments;
-        ret
+        Layout::from_size_align(
+            mem::size_of::<TCPv4ReceiveData>() + size_of_fragments,
+            mem::align_of::<TCPv4ReceiveData>(),
+        ).unwrap()
     }
 
-    pub(crate) fn new() -> Self {
-        let buffer_len = 2048*16;
-        let fragment = ManuallyDrop::new(TCPv4FragmentData::with_buffer_len(buffer_len));
-        let layout = Layout::from_size_align(
-            Self::total_layout_size(1),
-            mem::align_of::<Self>(),
-        ).unwrap();
+    /// Set up a receive that'll fill the provided buffers, one fragment per buffer
+    pub(crate) fn new(mut buffers: Vec<Box<[u8]>>) -> Self {
+        assert!(!buffers.is_empty(), "Expected at least one buffer to receive into");
+        let fragment_count = buffers.len();
+        let data_length: usize = buffers.iter().map(|b| b.len()).sum();
+        let layout = Self::layout(fragment_count);
         unsafe {
             let ptr = alloc::alloc::alloc(layout) as *mut TCPv4ReceiveData;
             (*ptr).urgent = false;
-            (*ptr).data_length = buffer_len as _;
-
-            let fragment_count = 1;
+            (*ptr).data_length = u32::try_from(data_length).expect("Receive buffers are too large");
             (*ptr).fragment_count = fragment_count as _;
-            copy_nonoverlapping(
-                &fragment as *const _,
-                (*ptr).fragment_table.as_mut_ptr(),
-                fragment_count,
-            );
+
+            // Moving the boxes around doesn't move their contents, so the fragments stay valid
+            let fragment_table: *mut ManuallyDrop<TCPv4FragmentData> = (*ptr).fragment_table.as_mut_ptr();
+            for (i, buffer) in buffers.iter_mut().enumerate() {
+                fragment_table.add(i).write(TCPv4FragmentData::borrowing(buffer));
+            }
 
             Self {
                 ptr: ptr as _,
                 layout,
+                buffers,
                 phantom: PhantomData,
             }
         }
@@ -59,22 +65,19 @@ impl<'a> TCPv4ReceiveDataHandle<'a> {
         // Safety: The reference is strictly tied to the lifetime of this handle
         unsafe { &*self.ptr }
     }
+
+    /// Tear down the handle, handing back the buffers so they can be reused
+    pub(crate) fn into_buffers(mut self) -> Vec<Box<[u8]>> {
+        mem::take(&mut self.buffers)
+    }
 }
 
 impl Drop for TCPv4ReceiveDataHandle<'_> {
     fn drop(&mut self) {
         //info!("Dropping RX handle");
-        let ptr = self.ptr as *mut TCPv4ReceiveData;
+        // The fragments only borrow our buffers, so there's nothing to drop other than the allocation itself
         unsafe {
-            // First, drop all the fragments
-            let fragment_table: *mut ManuallyDrop<TCPv4FragmentData> = (*ptr).fragment_table.as_mut_ptr();
-            for i in 0..((*ptr).fragment_count as usize) {
-                let fragment_ptr = fragment_table.add(i as _);
-                ManuallyDrop::drop(&mut *fragment_ptr);
-            }
-
-            // Lastly, drop the allocation itself
-            alloc::alloc::dealloc(ptr as *mut u8, self.layout);
+            alloc::alloc::dealloc(self.ptr as *mut u8, self.layout);
         }
     }
 }
@@ -89,18 +92,30 @@ pub struct TCPv4ReceiveData {
 }
 
 impl TCPv4ReceiveData {
+    /// The total number of bytes the driver delivered
+    pub fn data_length(&self) -> usize {
+        self.data_length as usize
+    }
+
+    /// The received data, without copying it out of the fragments.
+    /// Each fragment contributes its own fragment_length bytes, and we never read past data_length overall.
+    pub fn fragments(&self) -> impl Iterator<Item = &[u8]> {
+        let fragment_table: *const ManuallyDrop<TCPv4FragmentData> = self.fragment_table.as_ptr();
+        let mut remaining = self.data_length as usize;
+        (0..(self.fragment_count as usize)).map(move |i| {
+            // Safety: The handle allocated fragment_count entries, each pointing to a live buffer
+            let fragment = unsafe { &*fragment_table.add(i) };
+            let fragment_slice = unsafe { fragment.as_slice() };
+            let len = fragment_slice.len().min(remaining);
+            remaining -= len;
+            &fragment_slice[..len]
+        })
+    }
+
     pub fn read_buffers(&self) -> Vec<u8> {
         let mut out = vec![];
-        unsafe {
-            let ptr = self as *const Self;
-            let fragment_table: *const ManuallyDrop<TCPv4FragmentData> = (*ptr).fragment_table.as_ptr();
-            for i in 0..(self.fragment_count as usize) {
-                let fragment_ptr = fragment_table.add(i as _);
-                let fragment = &*fragment_ptr;
-                let fragment_buf = fragment.fragment_buf as *const u8;
-                let fragment_slice = core::slice::from_raw_parts(fragment_buf, self.data_length as _);
-                out.extend_from_slice(fragment_slice);
-            }
+        for fragment in self.fragments() {
+            out.extend_from_slice(fragment);
         }
         out
     }
@@ -111,3 +126,84 @@ impl Drop for TCPv4ReceiveData {
         panic!("Should be manually dropped by TCPv4ReceiveDataHandle")
     }
 }
+
+#[cfg(test)]
+mod test {
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::mem;
+    use core::mem::ManuallyDrop;
+    use crate::tcpv4::{TCPv4FragmentData, TCPv4ReceiveDataHandle};
+
+    fn buffers(sizes: &[usize]) -> Vec<Box<[u8]>> {
+        sizes.iter().map(|&size| vec![0u8; size].into_boxed_slice()).collect()
+    }
+
+    /// Do what the TCP driver does when a receive completes:
+    /// write the data into each fragment, and shrink the lengths to match what was written
+    fn complete_receive(handle: &TCPv4ReceiveDataHandle, per_fragment: &[&[u8]]) {
+        let ptr = handle.ptr as *mut super::TCPv4ReceiveData;
+        unsafe {
+            let fragment_table: *mut ManuallyDrop<TCPv4FragmentData> = (*ptr).fragment_table.as_mut_ptr();
+            for (i, data) in per_fragment.iter().enumerate() {
+                let fragment = &mut *fragment_table.add(i);
+                core::ptr::copy_nonoverlapping(data.as_ptr(), fragment.fragment_buf as *mut u8, data.len());
+                fragment.fragment_length = data.len() as _;
+            }
+            (*ptr).data_length = per_fragment.iter().map(|d| d.len()).sum::<usize>() as _;
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let handle = TCPv4ReceiveDataHandle::new(buffers(&[128, 64]));
+        let data = handle.get_data_ref();
+        assert_eq!(data.fragment_count, 2);
+        assert_eq!(data.data_length(), 192);
+        // The fragment table must start right after the header, as the driver expects
+        let header_addr = data as *const _ as usize;
+        let table_addr = data.fragment_table.as_ptr() as usize;
+        assert_eq!(table_addr - header_addr, mem::size_of::<super::TCPv4ReceiveData>());
+        assert_eq!(table_addr % mem::align_of::<TCPv4FragmentData>(), 0);
+        assert!(handle.layout.size() >= mem::size_of::<super::TCPv4ReceiveData>() + 2 * mem::size_of::<TCPv4FragmentData>());
+
+        // Each fragment describes exactly one of our buffers
+        let fragments: Vec<&[u8]> = data.fragments().collect();
+        assert_eq!(fragments[0].as_ptr(), handle.buffers[0].as_ptr());
+        assert_eq!(fragments[0].len(), 128);
+        assert_eq!(fragments[1].as_ptr(), handle.buffers[1].as_ptr());
+        assert_eq!(fragments[1].len(), 64);
+    }
+
+    #[test]
+    fn test_read_uses_fragment_lengths() {
+        let handle = TCPv4ReceiveDataHandle::new(buffers(&[16, 16]));
+        complete_receive(&handle, &[b"PING :a\r\n", b"PING :b"]);
+        let data = handle.get_data_ref();
+        assert_eq!(data.data_length(), 16);
+        assert_eq!(data.read_buffers(), b"PING :a\r\nPING :b".to_vec());
+    }
+
+    #[test]
+    fn test_read_is_bounded_by_data_length() {
+        let handle = TCPv4ReceiveDataHandle::new(buffers(&[8]));
+        complete_receive(&handle, &[b"abcdefgh"]);
+        // A fragment that claims more than the overall length shouldn't leak stale bytes
+        unsafe { (*(handle.ptr as *mut super::TCPv4ReceiveData)).data_length = 3 };
+        assert_eq!(handle.get_data_ref().read_buffers(), b"abc".to_vec());
+    }
+
+    #[test]
+    fn test_buffers_are_returned() {
+        let buffers = buffers(&[32]);
+        let buffer_ptr = buffers[0].as_ptr();
+        let handle = TCPv4ReceiveDataHandle::new(buffers);
+        complete_receive(&handle, &[b"hi"]);
+        let returned = handle.into_buffers();
+        assert_eq!(returned.len(), 1);
+        assert_eq!(returned[0].as_ptr(), buffer_ptr);
+        // The buffer keeps its full capacity, regardless of how much was received into it
+        assert_eq!(returned[0].len(), 32);
+    }
+}
@@ -0,0 +1,89 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A fixed set of equally-sized buffers that receive operations borrow from and hand back.
+/// This saves us from allocating (and zeroing) a fresh buffer every time the stack delivers data.
+#[derive(Debug)]
+pub struct RxBufferPool {
+    buffer_size: usize,
+    buffer_count: usize,
+    free_buffers: Vec<Box<[u8]>>,
+}
+
+impl RxBufferPool {
+    pub fn new(buffer_size: usize, buffer_count: usize) -> Self {
+        if buffer_size == 0 || buffer_count == 0 {
+            panic!("RX buffer pool needs at least one non-empty buffer");
+        }
+        // The buffer's length is handed to the TCP driver as a u32
+        if u32::try_from(buffer_size).is_err() {
+            panic!("RX buffer size {buffer_size} is too large");
+        }
+        let free_buffers = (0..buffer_count)
+            .map(|_| vec![0u8; buffer_size].into_boxed_slice())
+            .collect();
+        Self {
+            buffer_size,
+            buffer_count,
+            free_buffers,
+        }
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.buffer_count
+    }
+
+    /// The number of buffers that aren't currently lent out
+    pub fn available(&self) -> usize {
+        self.free_buffers.len()
+    }
+
+    /// Borrow a buffer, or None if they're all in use
+    pub fn acquire(&mut self) -> Option<Box<[u8]>> {
+        self.free_buffers.pop()
+    }
+
+    pub fn release(&mut self, buffer: Box<[u8]>) {
+        assert_eq!(buffer.len(), self.buffer_size, "Released a buffer that didn't come from this pool");
+        assert!(self.free_buffers.len() < self.buffer_count, "Released more buffers than were acquired");
+        self.free_buffers.push(buffer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec;
+    use crate::tcpv4::RxBufferPool;
+
+    #[test]
+    fn test_acquire_and_release() {
+        let mut pool = RxBufferPool::new(64, 2);
+        let first = pool.acquire().unwrap();
+        let second = pool.acquire().unwrap();
+        assert_eq!(first.len(), 64);
+        assert_eq!(pool.available(), 0);
+        assert!(pool.acquire().is_none());
+
+        // Buffers are recycled rather than reallocated
+        let first_ptr = first.as_ptr();
+        pool.release(first);
+        let reacquired = pool.acquire().unwrap();
+        assert_eq!(reacquired.as_ptr(), first_ptr);
+        pool.release(reacquired);
+        pool.release(second);
+        assert_eq!(pool.available(), 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_release_foreign_buffer() {
+        let mut pool = RxBufferPool::new(64, 1);
+        let _buffer = pool.acquire().unwrap();
+        pool.release(vec![0u8; 32].into_boxed_slice());
+    }
+}
@@ -0,0 +1,556 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use agx_definitions::Color;
+use crate::storage::FileStorage;
+use crate::rendered_line::{LineKind, LineStyle};
+
+/// Custom themes are read from here, relative to the root of the EFI filesystem, as `<name>.txt`
+pub const THEME_DIRECTORY: &str = "EFI\\Boot\\themes";
+pub const DEFAULT_THEME_NAME: &str = "light";
+pub const BUILT_IN_THEME_NAMES: [&str; 2] = ["light", "dark"];
+
+/// The colours of each part of a line
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LineColors {
+    pub leading_text: Color,
+    pub leading_text_background: Color,
+    pub leading_text_background_border: Color,
+    pub main_text: Color,
+    pub main_text_background: Color,
+    pub main_text_background_border: Color,
+}
+
+impl LineColors {
+    pub fn new(
+        leading_text: Color,
+        leading_text_background: Color,
+        leading_text_background_border: Color,
+        main_text: Color,
+        main_text_background: Color,
+        main_text_background_border: Color,
+    ) -> Self {
+        Self {
+            leading_text,
+            leading_text_background,
+            leading_text_background_border,
+            main_text,
+            main_text_background,
+            main_text_background_border,
+        }
+    }
+
+    /// Look up a colour by the name used in theme files
+    fn part_mut(&mut self, part: &str) -> Option<&mut Color> {
+        match part {
+            "leading_text" => Some(&mut self.leading_text),
+            "leading_background" => Some(&mut self.leading_text_background),
+            "leading_border" => Some(&mut self.leading_text_background_border),
+            "text" => Some(&mut self.main_text),
+            "background" => Some(&mut self.main_text_background),
+            "border" => Some(&mut self.main_text_background_border),
+            _ => None,
+        }
+    }
+}
+
+/// Every colour the UI draws with: each kind of line, the chrome around them, and the cursor
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub name: String,
+
+    pub server_notice: LineColors,
+    pub user_notice: LineColors,
+    pub user_notice_detail: LineColors,
+    pub private_message: LineColors,
+    pub own_message: LineColors,
+    pub join: LineColors,
+    pub names: LineColors,
+    pub server_prompt: LineColors,
+    pub error: LineColors,
+    pub unparseable: LineColors,
+    pub separator: LineColors,
+    /// Used for any kind of line restored from the previous session
+    pub dimmed: LineColors,
+    /// Used for any kind of line that matches the search
+    pub highlighted: LineColors,
+
+    pub content_background: Color,
+    /// Raw text written to the content view, outside of any line
+    pub content_text: Color,
+    pub timestamp: Color,
+    pub title_background: Color,
+    pub title_text: Color,
+    pub title_status_text: Color,
+    pub title_buffer_list_text: Color,
+    /// The network status panel
+    pub panel_background: Color,
+    pub panel_label: Color,
+    pub panel_text: Color,
+    pub search_background: Color,
+    pub search_label: Color,
+    pub search_text: Color,
+    pub scrollbar_track: Color,
+    pub scrollbar_thumb: Color,
+    pub cursor_fill: Color,
+    pub cursor_outline: Color,
+}
+
+impl Theme {
+    pub fn light() -> Self {
+        Self {
+            name: "light".to_string(),
+            server_notice: LineColors::new(
+                Color::new(40, 40, 40),
+                Color::new(71, 179, 255),
+                Color::new(53, 133, 189),
+                Color::black(),
+                Color::new(181, 224, 255),
+                Color::new(150, 186, 212),
+            ),
+            user_notice: LineColors::new(
+                Color::new(20, 20, 20),
+                Color::new(255, 143, 38),
+                Color::new(207, 116, 31),
+                Color::black(),
+                Color::new(252, 187, 126),
+                Color::new(196, 145, 96),
+            ),
+            user_notice_detail: LineColors::new(
+                Color::black(),
+                Color::new(255, 143, 38),
+                Color::new(207, 116, 31),
+                Color::black(),
+                Color::new(250, 198, 150),
+                Color::new(199, 158, 119),
+            ),
+            private_message: LineColors::new(
+                Color::new(0, 0, 0),
+                Color::new(255, 231, 166),
+                Color::new(194, 176, 128),
+                Color::black(),
+                Color::new(255, 243, 212),
+                Color::new(140, 173, 135),
+            ),
+            own_message: LineColors::new(
+                Color::black(),
+                Color::new(80, 224, 250),
+                Color::new(106, 150, 158),
+                Color::black(),
+                Color::new(255, 255, 255),
+                Color::new(255, 255, 255),
+            ),
+            join: LineColors::new(
+                Color::black(),
+                Color::new(221, 227, 48),
+                Color::new(134, 138, 29),
+                Color::black(),
+                Color::new(232, 235, 150),
+                Color::new(181, 184, 116),
+            ),
+            names: LineColors::new(
+                Color::black(),
+                Color::new(191, 177, 250),
+                Color::new(138, 129, 181),
+                Color::black(),
+                Color::new(221, 215, 250),
+                Color::black(),
+            ),
+            server_prompt: LineColors::new(
+                Color::new(54, 54, 54),
+                Color::new(217, 217, 217),
+                Color::new(180, 180, 180),
+                Color::black(),
+                Color::new(207, 207, 207),
+                Color::new(207, 207, 207),
+            ),
+            error: LineColors::new(
+                Color::new(255, 0, 0),
+                Color::new(217, 217, 217),
+                Color::new(180, 180, 180),
+                Color::new(186, 26, 26),
+                Color::new(255, 207, 207),
+                Color::new(184, 149, 149),
+            ),
+            unparseable: LineColors::new(
+                Color::new(255, 0, 0),
+                Color::new(255, 253, 237),
+                Color::new(191, 190, 176),
+                Color::black(),
+                Color::new(255, 248, 156),
+                Color::new(181, 176, 110),
+            ),
+            separator: LineColors::new(
+                Color::new(120, 120, 120),
+                Color::new(255, 255, 255),
+                Color::new(255, 255, 255),
+                Color::new(120, 120, 120),
+                Color::new(255, 255, 255),
+                Color::new(255, 255, 255),
+            ),
+            dimmed: LineColors::new(
+                Color::new(120, 120, 120),
+                Color::new(230, 230, 230),
+                Color::new(200, 200, 200),
+                Color::new(130, 130, 130),
+                Color::new(242, 242, 242),
+                Color::new(215, 215, 215),
+            ),
+            highlighted: LineColors::new(
+                Color::black(),
+                Color::new(255, 200, 0),
+                Color::new(196, 150, 0),
+                Color::black(),
+                Color::new(255, 236, 140),
+                Color::new(196, 170, 60),
+            ),
+            content_background: Color::white(),
+            content_text: Color::black(),
+            timestamp: Color::new(140, 140, 140),
+            title_background: Color::white(),
+            title_text: Color::black(),
+            title_status_text: Color::new(90, 90, 90),
+            title_buffer_list_text: Color::new(40, 40, 40),
+            panel_background: Color::new(235, 235, 235),
+            panel_label: Color::new(90, 90, 90),
+            panel_text: Color::black(),
+            search_background: Color::new(255, 250, 220),
+            search_label: Color::new(90, 90, 90),
+            search_text: Color::black(),
+            scrollbar_track: Color::new(235, 235, 235),
+            scrollbar_thumb: Color::new(150, 150, 150),
+            cursor_fill: Color::new(66, 206, 245),
+            cursor_outline: Color::new(20, 20, 20),
+        }
+    }
+
+    pub fn dark() -> Self {
+        Self {
+            name: "dark".to_string(),
+            server_notice: LineColors::new(
+                Color::new(230, 240, 255),
+                Color::new(31, 94, 140),
+                Color::new(23, 70, 105),
+                Color::new(220, 220, 220),
+                Color::new(28, 52, 71),
+                Color::new(40, 72, 96),
+            ),
+            user_notice: LineColors::new(
+                Color::new(20, 20, 20),
+                Color::new(230, 125, 30),
+                Color::new(170, 92, 22),
+                Color::new(230, 230, 230),
+                Color::new(80, 50, 25),
+                Color::new(110, 72, 40),
+            ),
+            user_notice_detail: LineColors::new(
+                Color::new(20, 20, 20),
+                Color::new(230, 125, 30),
+                Color::new(170, 92, 22),
+                Color::new(230, 230, 230),
+                Color::new(70, 46, 26),
+                Color::new(100, 70, 44),
+            ),
+            private_message: LineColors::new(
+                Color::new(20, 20, 20),
+                Color::new(201, 170, 90),
+                Color::new(150, 126, 66),
+                Color::new(230, 230, 230),
+                Color::new(60, 52, 36),
+                Color::new(82, 100, 78),
+            ),
+            own_message: LineColors::new(
+                Color::new(20, 20, 20),
+                Color::new(60, 180, 205),
+                Color::new(50, 120, 135),
+                Color::new(230, 230, 230),
+                Color::new(30, 30, 30),
+                Color::new(30, 30, 30),
+            ),
+            join: LineColors::new(
+                Color::new(20, 20, 20),
+                Color::new(170, 175, 40),
+                Color::new(120, 124, 28),
+                Color::new(230, 230, 230),
+                Color::new(52, 54, 24),
+                Color::new(80, 82, 40),
+            ),
+            names: LineColors::new(
+                Color::new(20, 20, 20),
+                Color::new(150, 135, 215),
+                Color::new(105, 95, 150),
+                Color::new(230, 230, 230),
+                Color::new(48, 44, 70),
+                Color::new(70, 64, 100),
+            ),
+            server_prompt: LineColors::new(
+                Color::new(210, 210, 210),
+                Color::new(70, 70, 70),
+                Color::new(95, 95, 95),
+                Color::new(220, 220, 220),
+                Color::new(50, 50, 50),
+                Color::new(50, 50, 50),
+            ),
+            error: LineColors::new(
+                Color::new(255, 120, 120),
+                Color::new(70, 40, 40),
+                Color::new(100, 55, 55),
+                Color::new(255, 170, 170),
+                Color::new(80, 30, 30),
+                Color::new(110, 50, 50),
+            ),
+            unparseable: LineColors::new(
+                Color::new(255, 110, 110),
+                Color::new(60, 58, 40),
+                Color::new(90, 88, 60),
+                Color::new(230, 230, 230),
+                Color::new(74, 70, 30),
+                Color::new(100, 96, 50),
+            ),
+            separator: LineColors::new(
+                Color::new(130, 130, 130),
+                Color::new(30, 30, 30),
+                Color::new(30, 30, 30),
+                Color::new(130, 130, 130),
+                Color::new(30, 30, 30),
+                Color::new(30, 30, 30),
+            ),
+            dimmed: LineColors::new(
+                Color::new(120, 120, 120),
+                Color::new(45, 45, 45),
+                Color::new(60, 60, 60),
+                Color::new(130, 130, 130),
+                Color::new(38, 38, 38),
+                Color::new(52, 52, 52),
+            ),
+            highlighted: LineColors::new(
+                Color::new(20, 20, 20),
+                Color::new(255, 200, 0),
+                Color::new(196, 150, 0),
+                Color::new(20, 20, 20),
+                Color::new(200, 170, 60),
+                Color::new(150, 125, 40),
+            ),
+            content_background: Color::new(30, 30, 30),
+            content_text: Color::new(220, 220, 220),
+            timestamp: Color::new(120, 120, 120),
+            title_background: Color::new(45, 45, 45),
+            title_text: Color::new(235, 235, 235),
+            title_status_text: Color::new(170, 170, 170),
+            title_buffer_list_text: Color::new(210, 210, 210),
+            panel_background: Color::new(55, 55, 55),
+            panel_label: Color::new(160, 160, 160),
+            panel_text: Color::new(235, 235, 235),
+            search_background: Color::new(70, 62, 30),
+            search_label: Color::new(170, 170, 170),
+            search_text: Color::new(245, 245, 245),
+            scrollbar_track: Color::new(50, 50, 50),
+            scrollbar_thumb: Color::new(110, 110, 110),
+            cursor_fill: Color::new(66, 206, 245),
+            cursor_outline: Color::new(235, 235, 235),
+        }
+    }
+
+    pub fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "light" => Some(Self::light()),
+            "dark" => Some(Self::dark()),
+            _ => None,
+        }
+    }
+
+    /// Parse a theme file. Each line sets a colour as `key=#rrggbb`, starting from the built-in theme
+    /// given by an optional `base=<name>` line, or the light theme otherwise. The base must come before any colours,
+    /// as it would otherwise silently replace them.
+    /// Line colours are keyed as `<kind>.<part>`, such as `error.text` or `dimmed.leading_background`.
+    pub fn parse(name: &str, theme_str: &str) -> Result<Self, String> {
+        let mut theme = Self::light();
+        let mut has_set_color = false;
+        for (i, line) in theme_str.lines().enumerate() {
+            let line = line.trim();
+            // Skip comments and blank lines
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or(format!("Line {}: expected key=value", i + 1))?;
+            let (key, value) = (key.trim(), value.trim());
+            if key == "base" {
+                if has_set_color {
+                    return Err(format!("Line {}: base must come before any colours", i + 1));
+                }
+                theme = Self::built_in(value).ok_or(format!("Line {}: unknown base theme {value}", i + 1))?;
+                continue;
+            }
+            let color = parse_color(value).ok_or(format!("Line {}: expected a colour like #rrggbb, got {value}", i + 1))?;
+            *theme.color_mut(key).ok_or(format!("Line {}: unknown colour {key}", i + 1))? = color;
+            has_set_color = true;
+        }
+        theme.name = name.to_string();
+        Ok(theme)
+    }
+
+    fn line_colors_mut(&mut self, name: &str) -> Option<&mut LineColors> {
+        if let Some(kind) = LineKind::from_name(name) {
+            return Some(self.line_colors_for_kind_mut(kind));
+        }
+        match name {
+            "dimmed" => Some(&mut self.dimmed),
+            "highlighted" => Some(&mut self.highlighted),
+            _ => None,
+        }
+    }
+
+    fn line_colors_for_kind(&self, kind: LineKind) -> LineColors {
+        match kind {
+            LineKind::ServerNotice => self.server_notice,
+            LineKind::UserNotice => self.user_notice,
+            LineKind::UserNoticeDetail => self.user_notice_detail,
+            LineKind::PrivateMessage => self.private_message,
+            LineKind::OwnMessage => self.own_message,
+            LineKind::Join => self.join,
+            LineKind::Names => self.names,
+            LineKind::ServerPrompt => self.server_prompt,
+            LineKind::Error => self.error,
+            LineKind::Unparseable => self.unparseable,
+            LineKind::Separator => self.separator,
+        }
+    }
+
+    fn line_colors_for_kind_mut(&mut self, kind: LineKind) -> &mut LineColors {
+        match kind {
+            LineKind::ServerNotice => &mut self.server_notice,
+            LineKind::UserNotice => &mut self.user_notice,
+            LineKind::UserNoticeDetail => &mut self.user_notice_detail,
+            LineKind::PrivateMessage => &mut self.private_message,
+            LineKind::OwnMessage => &mut self.own_message,
+            LineKind::Join => &mut self.join,
+            LineKind::Names => &mut self.names,
+            LineKind::ServerPrompt => &mut self.server_prompt,
+            LineKind::Error => &mut self.error,
+            LineKind::Unparseable => &mut self.unparseable,
+            LineKind::Separator => &mut self.separator,
+        }
+    }
+
+    /// Look up a colour by the key used in theme files
+    fn color_mut(&mut self, key: &str) -> Option<&mut Color> {
+        if let Some((line_colors, part)) = key.split_once('.') {
+            return self.line_colors_mut(line_colors)?.part_mut(part);
+        }
+        match key {
+            "content_background" => Some(&mut self.content_background),
+            "content_text" => Some(&mut self.content_text),
+            "timestamp" => Some(&mut self.timestamp),
+            "title_background" => Some(&mut self.title_background),
+            "title_text" => Some(&mut self.title_text),
+            "title_status_text" => Some(&mut self.title_status_text),
+            "title_buffer_list_text" => Some(&mut self.title_buffer_list_text),
+            "panel_background" => Some(&mut self.panel_background),
+            "panel_label" => Some(&mut self.panel_label),
+            "panel_text" => Some(&mut self.panel_text),
+            "search_background" => Some(&mut self.search_background),
+            "search_label" => Some(&mut self.search_label),
+            "search_text" => Some(&mut self.search_text),
+            "scrollbar_track" => Some(&mut self.scrollbar_track),
+            "scrollbar_thumb" => Some(&mut self.scrollbar_thumb),
+            "cursor_fill" => Some(&mut self.cursor_fill),
+            "cursor_outline" => Some(&mut self.cursor_outline),
+            _ => None,
+        }
+    }
+
+    /// How to draw a line of the given kind and style
+    pub fn line_colors(&self, kind: LineKind, style: LineStyle) -> LineColors {
+        match style {
+            LineStyle::Highlighted => self.highlighted,
+            // Separators are already muted
+            LineStyle::Dimmed if kind != LineKind::Separator => self.dimmed,
+            _ => self.line_colors_for_kind(kind),
+        }
+    }
+}
+
+fn parse_color(s: &str) -> Option<Color> {
+    let hex = s.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Color::new(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Look up a theme by name, trying the built-in themes before the theme directory on the ESP
+pub fn load_theme(storage: Option<&dyn FileStorage>, name: &str) -> Result<Theme, String> {
+    if let Some(theme) = Theme::built_in(name) {
+        return Ok(theme);
+    }
+    let path = format!("{THEME_DIRECTORY}\\{name}.txt");
+    let storage = storage.ok_or(format!("Can't read {path}, as the filesystem isn't available"))?;
+    let theme_bytes = storage.read(&path).ok_or(format!("No built-in theme named {name}, and couldn't read {path}"))?;
+    let theme_str = String::from_utf8(theme_bytes).map_err(|_| format!("{path} isn't valid UTF-8"))?;
+    Theme::parse(name, &theme_str).map_err(|e| format!("{path}: {e}"))
+}
+
+#[cfg(test)]
+mod test {
+    use agx_definitions::Color;
+    use crate::storage::{FileStorage, MemoryFileStorage};
+    use crate::rendered_line::{LineKind, LineStyle};
+    use crate::theme::{load_theme, Theme};
+
+    #[test]
+    fn test_line_colors() {
+        let theme = Theme::light();
+        assert_eq!(theme.line_colors(LineKind::Error, LineStyle::Normal), theme.error);
+        assert_eq!(theme.line_colors(LineKind::Error, LineStyle::Dimmed), theme.dimmed);
+        assert_eq!(theme.line_colors(LineKind::Separator, LineStyle::Dimmed), theme.separator);
+        assert_eq!(theme.line_colors(LineKind::Join, LineStyle::Highlighted), theme.highlighted);
+    }
+
+    #[test]
+    fn test_parse() {
+        let theme = Theme::parse(
+            "solarized",
+            "# Based on the dark theme\n\
+            base=dark\n\
+            \n\
+            content_background=#002b36\n\
+            error.text = #DC322F\n\
+            dimmed.leading_background=#073642\n",
+        ).unwrap();
+        let dark = Theme::dark();
+        assert_eq!(theme.name, "solarized");
+        assert_eq!(theme.content_background, Color::new(0x00, 0x2b, 0x36));
+        assert_eq!(theme.error.main_text, Color::new(0xdc, 0x32, 0x2f));
+        assert_eq!(theme.error.leading_text, dark.error.leading_text);
+        assert_eq!(theme.dimmed.leading_text_background, Color::new(0x07, 0x36, 0x42));
+        assert_eq!(theme.cursor_fill, dark.cursor_fill);
+
+        // Without a base, the light theme is used
+        assert_eq!(Theme::parse("empty", "").unwrap().content_background, Theme::light().content_background);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(Theme::parse("t", "base=sepia").is_err());
+        assert!(Theme::parse("t", "content_background").is_err());
+        assert!(Theme::parse("t", "content_background=white").is_err());
+        assert!(Theme::parse("t", "content_background=#12345").is_err());
+        assert!(Theme::parse("t", "error.glow=#123456").is_err());
+        assert!(Theme::parse("t", "window_border=#123456").is_err());
+        assert_eq!(
+            Theme::parse("t", "# Mine\ntimestamp=#ff0000\nbase=dark"),
+            Err("Line 3: base must come before any colours".to_string()),
+        );
+    }
+
+    #[test]
+    fn test_load_theme() {
+        let storage = MemoryFileStorage::new();
+        storage.write("EFI\\Boot\\themes\\mine.txt", b"base=dark\ntimestamp=#ff0000").unwrap();
+        assert_eq!(load_theme(Some(&storage), "dark"), Ok(Theme::dark()));
+        assert_eq!(load_theme(None, "light"), Ok(Theme::light()));
+        assert_eq!(load_theme(Some(&storage), "mine").unwrap().timestamp, Color::new(255, 0, 0));
+        assert!(load_theme(Some(&storage), "missing").is_err());
+        assert!(load_theme(None, "mine").is_err());
+    }
+}
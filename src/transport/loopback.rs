@@ -0,0 +1,84 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use crate::transport::{ConnectionState, SendError, Transport, TransportError};
+
+/// In-memory transport that records everything sent through it, and hands back whatever
+/// data has been queued up via `push_received()`.
+#[derive(Debug)]
+pub struct LoopbackTransport {
+    sent_data: RefCell<Vec<u8>>,
+    pending_rx: RefCell<Vec<u8>>,
+    state: Cell<ConnectionState>,
+    last_error: RefCell<Option<TransportError>>,
+    is_accepting_sends: Cell<bool>,
+}
+
+impl LoopbackTransport {
+    pub fn new() -> Self {
+        Self {
+            sent_data: RefCell::new(vec![]),
+            pending_rx: RefCell::new(vec![]),
+            state: Cell::new(ConnectionState::Connected),
+            last_error: RefCell::new(None),
+            is_accepting_sends: Cell::new(true),
+        }
+    }
+
+    /// Make data available to the next call to `poll_received()`, as though the peer sent it
+    pub fn push_received(&self, data: &[u8]) {
+        self.pending_rx.borrow_mut().extend_from_slice(data)
+    }
+
+    /// Everything that's been sent so far, split into IRC lines
+    pub fn sent_lines(&self) -> Vec<String> {
+        let sent_data = self.sent_data.borrow();
+        let sent_str = core::str::from_utf8(&sent_data).expect("Sent data should be UTF-8");
+        sent_str.split_terminator("\r\n").map(String::from).collect()
+    }
+
+    pub fn clear_sent(&self) {
+        self.sent_data.borrow_mut().clear()
+    }
+
+    /// Simulate the send queue filling up (or draining)
+    pub fn set_accepting_sends(&self, is_accepting_sends: bool) {
+        self.is_accepting_sends.set(is_accepting_sends)
+    }
+
+    /// Simulate the connection dropping out from under us
+    pub fn fail(&self, error: TransportError) {
+        *self.last_error.borrow_mut() = Some(error);
+        self.state.set(ConnectionState::Closed)
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send(&self, data: &[u8]) -> Result<(), SendError> {
+        if self.state.get() != ConnectionState::Connected {
+            return Err(SendError::NotConnected);
+        }
+        if !self.is_accepting_sends.get() {
+            return Err(SendError::QueueFull);
+        }
+        self.sent_data.borrow_mut().extend_from_slice(data);
+        Ok(())
+    }
+
+    fn poll_received(&self) -> Vec<u8> {
+        self.pending_rx.borrow_mut().drain(..).collect()
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.state.get()
+    }
+
+    fn last_error(&self) -> Option<TransportError> {
+        self.last_error.borrow().clone()
+    }
+
+    fn close(&self) {
+        self.state.set(ConnectionState::Closed)
+    }
+}
@@ -0,0 +1,113 @@
+#[cfg(test)]
+mod loopback;
+#[cfg(not(feature = "run_in_uefi"))]
+mod std_tcp;
+mod tcp_options;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display, Formatter};
+use crate::ipv4::{IPv4Address, IPv4RouteTable};
+
+#[cfg(test)]
+pub use self::loopback::LoopbackTransport;
+#[cfg(not(feature = "run_in_uefi"))]
+pub use self::std_tcp::StdTcpTransport;
+pub use self::tcp_options::{KeepAlive, TcpOptions};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Closing,
+    Closed,
+}
+
+/// Why a connection stopped working
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportError {
+    /// The peer closed its side of the connection
+    ConnectionFin,
+    ConnectionReset,
+    Timeout,
+    NetworkUnreachable,
+    /// Any other failure, as described by the underlying transport
+    Other(String),
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TransportError::ConnectionFin => f.write_str("Connection closed by the server"),
+            TransportError::ConnectionReset => f.write_str("Connection reset"),
+            TransportError::Timeout => f.write_str("Connection timed out"),
+            TransportError::NetworkUnreachable => f.write_str("Network unreachable"),
+            TransportError::Other(description) => f.write_str(description),
+        }
+    }
+}
+
+/// How the network underneath a transport ended up being configured
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkInfo {
+    pub station_address: IPv4Address,
+    pub subnet_mask: IPv4Address,
+    pub gateway_address: Option<IPv4Address>,
+    pub routes: Vec<IPv4RouteTable>,
+    /// Whether the addressing came from the config file, rather than DHCP
+    pub is_static: bool,
+}
+
+impl Display for NetworkInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Address {}, subnet mask {}", self.station_address, self.subnet_mask)?;
+        match self.gateway_address {
+            None => write!(f, ", no gateway")?,
+            Some(gateway_address) => write!(f, ", gateway {gateway_address}")?,
+        };
+        match self.is_static {
+            true => write!(f, " (static)"),
+            false => write!(f, " (DHCP)"),
+        }
+    }
+}
+
+/// Why data couldn't be accepted for sending
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SendError {
+    /// The connection is closed, or has failed
+    NotConnected,
+    /// Too much data is already waiting to go out. Try again once some of it has been sent.
+    QueueFull,
+}
+
+/// A bidirectional byte stream that the IRC client can run on top of.
+/// This lets the client logic stay agnostic to whether it's running over the UEFI TCP stack,
+/// a hosted socket, or an in-memory buffer in tests.
+pub trait Transport: Debug {
+    /// Queue data to be sent. This doesn't wait for the data to make it onto the wire.
+    /// Either all of the data is accepted, or none of it is.
+    fn send(&self, data: &[u8]) -> Result<(), SendError>;
+
+    /// Drain everything that's been received since the last poll.
+    /// Returns an empty buffer if nothing new has arrived.
+    fn poll_received(&self) -> Vec<u8>;
+
+    fn state(&self) -> ConnectionState;
+
+    /// If the connection failed, the reason why
+    fn last_error(&self) -> Option<TransportError>;
+
+    /// Details of the local network configuration, if the transport manages it
+    fn network_info(&self) -> Option<NetworkInfo> {
+        None
+    }
+
+    /// The underlying protocol's own view of the connection, such as the TCP state machine's state.
+    /// Only used for diagnostics.
+    fn protocol_state(&self) -> Option<String> {
+        None
+    }
+
+    fn close(&self);
+}
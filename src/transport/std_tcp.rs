@@ -0,0 +1,180 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+use log::info;
+use crate::transport::{ConnectionState, SendError, TcpOptions, Transport, TransportError};
+
+/// Once this much data is waiting to go out, further sends are refused until some of it has been sent
+const MAX_QUEUED_TX_BYTES: usize = 64 * 1024;
+
+/// Transport backed by the host's socket API, for running the client outside of UEFI
+#[derive(Debug)]
+pub struct StdTcpTransport {
+    stream: TcpStream,
+    state: Cell<ConnectionState>,
+    last_error: RefCell<Option<TransportError>>,
+    /// Data accepted by send() that the socket hasn't taken yet
+    tx_queue: RefCell<VecDeque<u8>>,
+    /// Set when we close the connection ourselves, so that the EOF that follows isn't reported as a failure
+    is_closed: Cell<bool>,
+}
+
+impl StdTcpTransport {
+    /// Only the options that std exposes are applied. The rest are left to the OS.
+    pub fn connect(address: SocketAddr, options: &TcpOptions) -> std::io::Result<Self> {
+        let stream = match options.connection_timeout_secs {
+            None => TcpStream::connect(address)?,
+            Some(timeout_secs) => TcpStream::connect_timeout(&address, Duration::from_secs(timeout_secs as u64))?,
+        };
+        stream.set_nodelay(!options.enable_nagle)?;
+        if options.keep_alive.is_some() {
+            info!("TCP keep-alive isn't supported in hosted builds, ignoring it");
+        }
+        // Reads are polled from the event loop, so they must never block
+        stream.set_nonblocking(true)?;
+        Ok(
+            Self {
+                stream,
+                state: Cell::new(ConnectionState::Connected),
+                last_error: RefCell::new(None),
+                tx_queue: RefCell::new(VecDeque::new()),
+                is_closed: Cell::new(false),
+            }
+        )
+    }
+
+    fn fail(&self, error: TransportError) {
+        info!("Connection failed: {error}");
+        *self.last_error.borrow_mut() = Some(error);
+        self.state.set(ConnectionState::Closed);
+    }
+
+    /// Hand as much of the queue to the socket as it'll take without blocking
+    fn flush_tx_queue(&self) {
+        let mut tx_queue = self.tx_queue.borrow_mut();
+        while !tx_queue.is_empty() && self.state.get() != ConnectionState::Closed {
+            let (pending, _) = tx_queue.as_slices();
+            match (&self.stream).write(pending) {
+                Ok(0) => self.fail(TransportError::ConnectionReset),
+                Ok(written) => {
+                    tx_queue.drain(..written);
+                }
+                // The kernel's send buffer is full. Try again on the next poll.
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => self.fail_with_io_error(e),
+            }
+        }
+    }
+
+    fn fail_with_io_error(&self, error: Error) {
+        let transport_error = match error.kind() {
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => TransportError::ConnectionReset,
+            ErrorKind::TimedOut => TransportError::Timeout,
+            _ => TransportError::Other(error.to_string()),
+        };
+        self.fail(transport_error)
+    }
+}
+
+impl Transport for StdTcpTransport {
+    /// Queue data to be sent, without waiting for it to go out.
+    /// Refused if the connection is dead, or if too much data is already waiting.
+    fn send(&self, data: &[u8]) -> Result<(), SendError> {
+        if self.state.get() == ConnectionState::Closed {
+            return Err(SendError::NotConnected);
+        }
+        let queued_bytes = self.tx_queue.borrow().len();
+        // Always let a send through when nothing is queued, so that oversized sends can't get stuck
+        if queued_bytes != 0 && queued_bytes + data.len() > MAX_QUEUED_TX_BYTES {
+            return Err(SendError::QueueFull);
+        }
+        self.tx_queue.borrow_mut().extend(data);
+        self.flush_tx_queue();
+        Ok(())
+    }
+
+    fn poll_received(&self) -> Vec<u8> {
+        // Catch up on anything the socket couldn't take last time
+        self.flush_tx_queue();
+        let mut out = vec![];
+        let mut chunk = [0u8; 4096];
+        loop {
+            match (&self.stream).read(&mut chunk) {
+                Ok(0) => {
+                    if !self.is_closed.get() {
+                        self.fail(TransportError::ConnectionFin);
+                    }
+                    break;
+                }
+                Ok(len) => out.extend_from_slice(&chunk[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    if !self.is_closed.get() {
+                        self.fail_with_io_error(e);
+                    }
+                    break;
+                }
+            }
+        }
+        out
+    }
+
+    fn state(&self) -> ConnectionState {
+        self.state.get()
+    }
+
+    fn last_error(&self) -> Option<TransportError> {
+        self.last_error.borrow().clone()
+    }
+
+    fn close(&self) {
+        // Give whatever's still queued a last chance to go out before the FIN
+        self.flush_tx_queue();
+        self.is_closed.set(true);
+        let _ = self.stream.shutdown(Shutdown::Both);
+        self.state.set(ConnectionState::Closed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{ErrorKind, Read};
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use crate::transport::{ConnectionState, SendError, StdTcpTransport, TcpOptions, Transport};
+
+    #[test]
+    fn test_send_is_queued_rather_than_blocking() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = StdTcpTransport::connect(listener.local_addr().unwrap(), &TcpOptions::default()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+
+        // The peer isn't reading, so the socket's buffers fill up, and then our own queue does
+        let chunk = [b'a'; 16 * 1024];
+        let mut sent_bytes = 0;
+        loop {
+            match transport.send(&chunk) {
+                Ok(()) => sent_bytes += chunk.len(),
+                Err(SendError::QueueFull) => break,
+                Err(e) => panic!("Unexpected send error {e:?}"),
+            }
+        }
+        assert_eq!(transport.state(), ConnectionState::Connected);
+
+        // Once the peer reads, polling hands the rest of the queue over
+        peer.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let mut received = vec![0; sent_bytes];
+        let mut received_bytes = 0;
+        while received_bytes < sent_bytes {
+            transport.poll_received();
+            match peer.read(&mut received[received_bytes..]) {
+                Ok(len) => received_bytes += len,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => panic!("Peer failed to read: {e}"),
+            }
+        }
+        assert!(received.iter().all(|&byte| byte == b'a'));
+    }
+}
@@ -0,0 +1,93 @@
+use alloc::format;
+use alloc::string::String;
+
+/// Smallest and largest socket buffers we'll ask for
+const MIN_BUFFER_SIZE: u32 = 4 * 1024;
+const MAX_BUFFER_SIZE: u32 = 16 * 1024 * 1024;
+/// Longest timeout we'll accept, in seconds
+const MAX_TIMEOUT_SECS: u32 = 60 * 60;
+
+/// Send periodic probes on an idle connection, so that NATs and firewalls along the way don't forget about it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct KeepAlive {
+    /// How long the connection can sit idle before we start probing
+    pub idle_secs: u32,
+    /// Time between unanswered probes
+    pub interval_secs: u32,
+    /// How many unanswered probes before the connection is considered dead
+    pub probes: u32,
+}
+
+/// Tunables for the TCP connection to the server.
+/// Anything left as None uses the TCP stack's default.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    pub receive_buffer_size: Option<u32>,
+    pub send_buffer_size: Option<u32>,
+    pub connection_timeout_secs: Option<u32>,
+    /// How many times unacknowledged data is retransmitted before giving up on the connection
+    pub data_retries: Option<u32>,
+    pub fin_timeout_secs: Option<u32>,
+    pub time_wait_timeout_secs: Option<u32>,
+    /// Disabled when None
+    pub keep_alive: Option<KeepAlive>,
+    /// Nagle's algorithm coalesces small writes, at the cost of latency.
+    /// Off by default, as IRC is chatty and line-based and we don't want lines held back waiting for more data.
+    pub enable_nagle: bool,
+}
+
+fn validate_range(name: &str, value: Option<u32>, min: u32, max: u32) -> Result<(), String> {
+    match value {
+        Some(value) if value < min || value > max => {
+            Err(format!("{name} must be between {min} and {max}, but was {value}"))
+        }
+        _ => Ok(()),
+    }
+}
+
+impl TcpOptions {
+    /// Returns a description of the first nonsensical value, if there is one
+    pub fn validate(&self) -> Result<(), String> {
+        validate_range("TCP receive buffer size", self.receive_buffer_size, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE)?;
+        validate_range("TCP send buffer size", self.send_buffer_size, MIN_BUFFER_SIZE, MAX_BUFFER_SIZE)?;
+        validate_range("TCP connection timeout", self.connection_timeout_secs, 1, MAX_TIMEOUT_SECS)?;
+        validate_range("TCP data retries", self.data_retries, 1, 255)?;
+        validate_range("TCP FIN timeout", self.fin_timeout_secs, 1, MAX_TIMEOUT_SECS)?;
+        validate_range("TCP TIME_WAIT timeout", self.time_wait_timeout_secs, 1, MAX_TIMEOUT_SECS)?;
+        if let Some(keep_alive) = self.keep_alive {
+            validate_range("TCP keep-alive idle time", Some(keep_alive.idle_secs), 1, MAX_TIMEOUT_SECS)?;
+            validate_range("TCP keep-alive interval", Some(keep_alive.interval_secs), 1, MAX_TIMEOUT_SECS)?;
+            validate_range("TCP keep-alive probes", Some(keep_alive.probes), 1, 255)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::transport::{KeepAlive, TcpOptions};
+
+    #[test]
+    fn test_validate() {
+        assert!(TcpOptions::default().validate().is_ok());
+
+        let options = TcpOptions {
+            receive_buffer_size: Some(64 * 1024),
+            keep_alive: Some(KeepAlive { idle_secs: 60, interval_secs: 10, probes: 5 }),
+            ..Default::default()
+        };
+        assert!(options.validate().is_ok());
+
+        let tiny_buffer = TcpOptions {
+            send_buffer_size: Some(16),
+            ..Default::default()
+        };
+        assert!(tiny_buffer.validate().is_err());
+
+        let no_probes = TcpOptions {
+            keep_alive: Some(KeepAlive { idle_secs: 60, interval_secs: 10, probes: 0 }),
+            ..Default::default()
+        };
+        assert!(no_probes.validate().is_err());
+    }
+}
@@ -1,42 +1,142 @@
+use alloc::vec::Vec;
 use agx_definitions::Size;
 use log::info;
-use uefi::Result;
+use uefi::{Handle, Result, Status};
 use uefi::prelude::BootServices;
-use uefi::proto::console::gop::GraphicsOutput;
+use uefi::proto::console::gop::{GraphicsOutput, Mode, ModeInfo, PixelFormat};
+use uefi::proto::unsafe_protocol;
+use uefi::proto::ProtocolPointer;
 use uefi::table::boot::{OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol};
 use uefi_services::println;
+use crate::pixel_format::{ChannelMasks, DisplayPixelFormat};
+use crate::resolution::{choose_resolution, native_resolution_from_edid, Resolution};
 
-pub fn set_resolution(
-    boot_services: &BootServices,
-    desired_resolution: Size,
-) -> Result<ScopedProtocol<GraphicsOutput>> {
-    println!("trying to get protos");
-    let gop_handle = boot_services.get_handle_for_protocol::<GraphicsOutput>()?;
-    // PT: open_protocol_exclusive just hangs forever, so ask more politely
-    let mut gop = unsafe {
-        boot_services.open_protocol::<GraphicsOutput>(
+#[derive(Debug)]
+#[repr(C)]
+struct EdidData {
+    size_of_edid: u32,
+    edid: *const u8,
+}
+
+impl EdidData {
+    fn native_resolution(&self) -> Option<Resolution> {
+        if self.edid.is_null() {
+            return None;
+        }
+        let edid = unsafe { core::slice::from_raw_parts(self.edid, self.size_of_edid as usize) };
+        native_resolution_from_edid(edid)
+    }
+}
+
+/// The EDID that's in use, which the platform may have overridden
+#[derive(Debug)]
+#[repr(C)]
+#[unsafe_protocol("BD8C1056-9F36-44EC-92A8-A6337F817986")]
+struct EdidActiveProtocol {
+    data: EdidData,
+}
+
+/// The EDID as it was read from the display
+#[derive(Debug)]
+#[repr(C)]
+#[unsafe_protocol("1C0C34F6-D380-41FA-A049-8AD06C1A66AA")]
+struct EdidDiscoveredProtocol {
+    data: EdidData,
+}
+
+fn open_protocol<P: ProtocolPointer + ?Sized>(boot_services: &BootServices, handle: Handle) -> Option<ScopedProtocol<P>> {
+    unsafe {
+        boot_services.open_protocol::<P>(
             OpenProtocolParams {
-                handle: gop_handle,
+                handle,
                 agent: boot_services.image_handle(),
                 controller: None,
             },
             OpenProtocolAttributes::GetProtocol,
         )
-    }?;
-
-    let mut switched_to_desired_resolution = false;
-    for mode in gop.modes(boot_services) {
-        let res = mode.info().resolution();
-        info!("Found supported resolution {:?}", res);
-        if res == (desired_resolution.width as _, desired_resolution.height as _) {
-            gop.set_mode(&mode).expect("Failed to set desired resolution");
-            switched_to_desired_resolution = true;
-            break;
+    }.ok()
+}
+
+/// The native resolution of the display, if the firmware can tell us its EDID
+fn native_resolution(boot_services: &BootServices, gop_handle: Handle) -> Option<Resolution> {
+    let active = open_protocol::<EdidActiveProtocol>(boot_services, gop_handle)
+        .and_then(|edid| edid.data.native_resolution());
+    if active.is_some() {
+        return active;
+    }
+    open_protocol::<EdidDiscoveredProtocol>(boot_services, gop_handle)
+        .and_then(|edid| edid.data.native_resolution())
+}
+
+fn mode_resolution(mode: &Mode) -> Resolution {
+    let (width, height) = mode.info().resolution();
+    Resolution::new(width, height)
+}
+
+/// How pixels are laid out in the mode's framebuffer, or None if the display can only be drawn to with blt()
+pub fn display_pixel_format(mode_info: &ModeInfo) -> Option<DisplayPixelFormat> {
+    match mode_info.pixel_format() {
+        PixelFormat::Rgb => Some(DisplayPixelFormat::Rgb),
+        PixelFormat::Bgr => Some(DisplayPixelFormat::Bgr),
+        PixelFormat::Bitmask => {
+            let bitmask = mode_info.pixel_bitmask()?;
+            Some(
+                DisplayPixelFormat::Bitmask(
+                    ChannelMasks {
+                        red: bitmask.red,
+                        green: bitmask.green,
+                        blue: bitmask.blue,
+                        reserved: bitmask.reserved,
+                    }
+                )
+            )
         }
+        PixelFormat::BltOnly => None,
     }
-    if !switched_to_desired_resolution {
-        panic!("Failed to switch to the desired resolution");
+}
+
+/// The resolutions of each mode the display supports
+pub fn available_resolutions(boot_services: &BootServices, gop: &GraphicsOutput) -> Vec<Resolution> {
+    gop.modes(boot_services).map(|mode| mode_resolution(&mode)).collect()
+}
+
+/// Switch the display to the mode with the given resolution
+pub fn switch_resolution(
+    boot_services: &BootServices,
+    gop: &mut GraphicsOutput,
+    resolution: Resolution,
+) -> Result {
+    let mode = gop.modes(boot_services)
+        .find(|mode| mode_resolution(mode) == resolution)
+        .ok_or(Status::UNSUPPORTED)?;
+    gop.set_mode(&mode)?;
+    info!("Switched to {resolution}");
+    Ok(())
+}
+
+/// Switch the display to the best mode available, returning the resolution that was picked.
+/// The configured resolution is used if the display supports it.
+pub fn set_resolution(
+    boot_services: &BootServices,
+    configured_resolution: Option<Resolution>,
+) -> Result<(ScopedProtocol<GraphicsOutput>, Size)> {
+    println!("trying to get protos");
+    let gop_handle = boot_services.get_handle_for_protocol::<GraphicsOutput>()?;
+    // PT: open_protocol_exclusive just hangs forever, so ask more politely
+    let mut gop = open_protocol::<GraphicsOutput>(boot_services, gop_handle).ok_or(Status::UNSUPPORTED)?;
+
+    let available = available_resolutions(boot_services, &gop);
+    info!("Found supported resolutions {available:?}");
+    if let Some(configured_resolution) = configured_resolution {
+        if !available.contains(&configured_resolution) {
+            info!("Configured resolution {configured_resolution} isn't supported by the display, picking another");
+        }
     }
+    let native_resolution = native_resolution(boot_services, gop_handle);
+    info!("Native resolution: {native_resolution:?}");
+
+    let resolution = choose_resolution(&available, configured_resolution, native_resolution).ok_or(Status::UNSUPPORTED)?;
+    switch_resolution(boot_services, &mut gop, resolution)?;
 
-    Ok(gop)
-}
\ No newline at end of file
+    Ok((gop, Size::new(resolution.width as _, resolution.height as _)))
+}